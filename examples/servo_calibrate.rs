@@ -0,0 +1,54 @@
+//! Moves one servo so its calibration can be measured.
+//!
+//! cargo run --example servo_calibrate -- finale.json gripper 30
+//! cargo run --example servo_calibrate -- finale.json gripper pulse 270
+//!
+//! Angles go through the calibration and limits of the configuration, raw
+//! pulses only through the limits.
+
+use finale::config::Config;
+use finale::hardware::Pwm;
+use finale::servo::Servos;
+
+use std::env;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, name, rest) = match args.as_slice() {
+        [path, name, rest @ ..] if !rest.is_empty() => (path, name, rest),
+        _ => return Err("usage: servo_calibrate CONFIG SERVO (DEGREES | pulse PULSE)".into()),
+    };
+    let config = Config::load(path)?;
+    let mut servos = Servos::new(config.servos.clone(), Vec::new(), &[]);
+    let index = servos
+        .index(name)
+        .ok_or_else(|| format!("no servo named {}", name))?;
+
+    match rest {
+        [kind, pulse] if kind == "pulse" => {
+            let pulse: f32 = pulse.parse().map_err(|e| format!("bad pulse: {:?}", e))?;
+            servos.set_pulse(index, pulse);
+        }
+        [degrees] => {
+            let degrees: f32 = degrees.parse().map_err(|e| format!("bad angle: {:?}", e))?;
+            servos.set_angle(index, degrees);
+        }
+        _ => return Err("expected DEGREES or pulse PULSE".into()),
+    }
+
+    let pulse = servos.pulses()[index].expect("servo was just commanded");
+    let angle = servos.angles()[index].expect("servo was just commanded");
+    let mut pwm = Pwm::new(&config.i2c_bus, config.pwm_prescale)?;
+    pwm.set_pulse(config.servos[index].channel, pulse)?;
+    println!(
+        "{} on C{}: pulse {} ({:.1} degrees)",
+        name, config.servos[index].channel, pulse, angle
+    );
+
+    // Leave the signal on long enough for the servo to get there.
+    thread::sleep(Duration::from_millis(2000));
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Flies the vehicle from a gamepad.
+//!
+//! cargo run -p finale-client --example gamepad -- rov.local:12345
+//! cargo run -p finale-client --example gamepad -- rov.local:12345 --config pad.json /dev/input/event5
+//! cargo run -p finale-client --example gamepad -- rov.local:12345 --replay pad.bin
+//!
+//! Without a configuration the default layout is used, without a device the
+//! first gamepad found. A replay plays events recorded from a device node,
+//! e.g. with `cat /dev/input/event5 > pad.bin`, instead of reading one.
+
+use finale_client::gamepad::{self, GamepadConfig, Mapper};
+use finale_client::{Connection, ControlGroup, Role};
+
+use std::env;
+use std::error::Error;
+
+fn usage() -> String {
+    "usage: gamepad PILOT_ADDRESS [--config FILE] [DEVICE | --replay FILE]".to_string()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let address = args.next().ok_or_else(usage)?;
+    let mut config = GamepadConfig::default();
+    let mut device = None;
+    let mut replay = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = GamepadConfig::load(args.next().ok_or_else(usage)?)?,
+            "--replay" => replay = Some(args.next().ok_or_else(usage)?),
+            _ if arg.starts_with('-') => return Err(usage().into()),
+            _ => device = Some(arg),
+        }
+    }
+    let mut mapper = Mapper::new(&config)?;
+
+    let vehicle = Connection::open(address, Role::Pilot, ControlGroup::ALL.to_vec());
+    let control = vehicle.connected().await;
+    println!(
+        "connected as {:?} holding {:?}",
+        control.role, control.groups
+    );
+
+    if let Some(path) = replay {
+        let recording = gamepad::read_recording(path)?;
+        gamepad::replay(&recording, &mut mapper, &vehicle).await;
+        vehicle.disarm();
+        return Ok(());
+    }
+    fly(device, mapper, vehicle).await
+}
+
+#[cfg(target_os = "linux")]
+async fn fly(
+    device: Option<String>,
+    mut mapper: Mapper,
+    vehicle: Connection,
+) -> Result<(), Box<dyn Error>> {
+    let mut pad = gamepad::Gamepad::open(device.as_deref())?;
+    pad.configure(&mut mapper)?;
+    loop {
+        let event = match pad.next().await {
+            Ok(event) => event,
+            Err(e) => {
+                // Unplugged, leave the vehicle safe.
+                vehicle.disarm();
+                return Err(e.into());
+            }
+        };
+        let gain = mapper.gain();
+        gamepad::apply(&mut mapper, event, &vehicle);
+        if mapper.gain() != gain {
+            println!("gain {:.0}%", mapper.gain() * 100.0);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn fly(_: Option<String>, _: Mapper, _: Connection) -> Result<(), Box<dyn Error>> {
+    Err("live gamepads need evdev, only --replay works here".into())
+}
@@ -0,0 +1,431 @@
+use finale::control::{ControlGroup, ControlStatus, Role};
+use finale::link::RoundTrip;
+use finale::mixer::Wrench;
+use finale::pilot::PilotInput;
+use finale::protocol::{self, Command, Message, Reply, Session, MAGIC};
+use finale::stabilise::StabiliseMode;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::RECONNECT_DELAY;
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the last wrench is repeated, well within the link timeout of
+/// the vehicle.
+const PILOT_INTERVAL: Duration = Duration::from_millis(50);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// State of the connection, as last seen by the client.
+#[derive(Debug, Clone, Default)]
+pub struct LinkStatus {
+    pub connected: bool,
+    /// Role and groups granted by the vehicle, `None` until it answers.
+    pub control: Option<ControlStatus>,
+    /// Round trip time, jitter and loss measured from the acks.
+    pub round_trip: RoundTrip,
+    /// Why the last connection failed.
+    pub error: Option<String>,
+}
+
+/// Handle to a vehicle, kept connected in the background.
+///
+/// After every (re)connection the client says hello with its role and takes
+/// the groups it asked for. The wrench given to [`Connection::pilot`] is
+/// repeated until it changes and forgotten when the connection drops, so a
+/// reconnect never resumes stale sticks. Commands given while disconnected
+/// are dropped.
+pub struct Connection {
+    messages: mpsc::UnboundedSender<Message>,
+    wrench: watch::Sender<Option<Wrench>>,
+    status: watch::Receiver<LinkStatus>,
+    task: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Connects to the pilot port of the daemon, e.g. `rov.local:12345`.
+    pub fn open(address: impl Into<String>, role: Role, groups: Vec<ControlGroup>) -> Connection {
+        let (messages, rx) = mpsc::unbounded_channel();
+        let (wrench, wrench_rx) = watch::channel(None);
+        let (status_tx, status) = watch::channel(LinkStatus::default());
+        let task = tokio::spawn(run(
+            address.into(),
+            Desired { role, groups },
+            rx,
+            wrench_rx,
+            Arc::new(status_tx),
+        ));
+        Connection {
+            messages,
+            wrench,
+            status,
+            task,
+        }
+    }
+
+    pub fn pilot(&self, wrench: Wrench) {
+        self.wrench.send_replace(Some(wrench));
+    }
+
+    pub fn send(&self, command: Command) {
+        let _ = self.messages.send(Message::Command(command));
+    }
+
+    pub fn arm(&self) {
+        self.send(Command::SetArmed(true));
+    }
+
+    pub fn disarm(&self) {
+        self.send(Command::SetArmed(false));
+    }
+
+    pub fn set_stabilise(&self, mode: StabiliseMode) {
+        self.send(Command::SetStabilise(mode));
+    }
+
+    /// Selects a speed gear of the vehicle by index.
+    pub fn set_gear(&self, gear: u8) {
+        self.send(Command::SetGear(gear));
+    }
+
+    /// Moves the vehicle `step` gears up or down.
+    pub fn shift_gear(&self, step: i8) {
+        self.send(Command::ShiftGear(step));
+    }
+
+    /// Moves a servo to a target in degrees.
+    pub fn set_servo(&self, index: u8, degrees: f32) {
+        self.send(Command::SetServo { index, degrees });
+    }
+
+    pub fn servo_preset(&self, name: &str) {
+        self.send(Command::ServoPreset(name.to_string()));
+    }
+
+    /// Sets a light in percent, index 0xFF for all of them.
+    pub fn set_light(&self, index: u8, percent: u8) {
+        self.send(Command::SetLight { index, percent });
+    }
+
+    /// Asks for more groups, also taken again after a reconnect.
+    pub fn take(&self, groups: Vec<ControlGroup>) {
+        let _ = self.messages.send(Message::Session(Session::Take(groups)));
+    }
+
+    pub fn release(&self, groups: Vec<ControlGroup>) {
+        let _ = self
+            .messages
+            .send(Message::Session(Session::Release(groups)));
+    }
+
+    pub fn status(&self) -> LinkStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Waits until the vehicle granted control on the current connection,
+    /// returning the groups the client got.
+    pub async fn connected(&self) -> ControlStatus {
+        let mut status = self.status.clone();
+        let status = status
+            .wait_for(|status| status.control.is_some())
+            .await
+            .expect("the connection task outlives the handle");
+        status.control.clone().unwrap()
+    }
+
+    /// Receiver notified on every status change.
+    pub fn watch_status(&self) -> watch::Receiver<LinkStatus> {
+        self.status.clone()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Role and groups to ask for on every connection.
+struct Desired {
+    role: Role,
+    groups: Vec<ControlGroup>,
+}
+
+impl Desired {
+    fn update(&mut self, message: &Message) {
+        match message {
+            Message::Session(Session::Hello(role)) => self.role = *role,
+            Message::Session(Session::Take(groups)) => {
+                for group in groups {
+                    if !self.groups.contains(group) {
+                        self.groups.push(*group);
+                    }
+                }
+            }
+            Message::Session(Session::Release(groups)) => {
+                self.groups.retain(|group| !groups.contains(group))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Stamps messages with a sequence number and the client clock.
+struct Stamper {
+    start: Instant,
+    seq: u32,
+}
+
+impl Stamper {
+    fn now_ms(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+
+    fn frame(&mut self, message: &Message) -> Vec<u8> {
+        self.seq = self.seq.wrapping_add(1);
+        let stamp = Message::Stamp {
+            seq: self.seq,
+            timestamp_ms: self.now_ms(),
+        };
+        let mut frame = stamp.encode();
+        frame.extend_from_slice(&message.encode());
+        frame
+    }
+}
+
+async fn run(
+    address: String,
+    mut desired: Desired,
+    mut messages: mpsc::UnboundedReceiver<Message>,
+    mut wrench: watch::Receiver<Option<Wrench>>,
+    status: Arc<watch::Sender<LinkStatus>>,
+) {
+    let start = Instant::now();
+    loop {
+        let error = match time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&address)).await {
+            Ok(Ok(stream)) => {
+                let mut stamper = Stamper { start, seq: 0 };
+                session(
+                    stream,
+                    &mut desired,
+                    &mut stamper,
+                    &mut messages,
+                    &mut wrench,
+                    &status,
+                )
+                .await
+            }
+            Ok(Err(e)) => e,
+            Err(_) => io::Error::new(io::ErrorKind::TimedOut, "connect timed out"),
+        };
+        status.send_replace(LinkStatus {
+            error: Some(error.to_string()),
+            ..LinkStatus::default()
+        });
+
+        // Forget what was asked while connected, then wait.
+        wrench.borrow_and_update();
+        let retry = time::sleep(RECONNECT_DELAY);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                _ = &mut retry => break,
+                message = messages.recv() => match message {
+                    Some(message) => desired.update(&message),
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+/// Runs one connection until it fails.
+async fn session(
+    stream: TcpStream,
+    desired: &mut Desired,
+    stamper: &mut Stamper,
+    messages: &mut mpsc::UnboundedReceiver<Message>,
+    wrench: &mut watch::Receiver<Option<Wrench>>,
+    status: &Arc<watch::Sender<LinkStatus>>,
+) -> io::Error {
+    if let Err(e) = stream.set_nodelay(true) {
+        return e;
+    }
+    let (reader, mut writer) = stream.into_split();
+    while let Ok(message) = messages.try_recv() {
+        desired.update(&message);
+    }
+    let mut hello = MAGIC.to_vec();
+    hello.extend(stamper.frame(&Message::Session(Session::Hello(desired.role))));
+    hello.extend(stamper.frame(&Message::Session(Session::Take(desired.groups.clone()))));
+    if let Err(e) = writer.write_all(&hello).await {
+        return e;
+    }
+    status.send_modify(|status| {
+        status.connected = true;
+        status.error = None;
+    });
+
+    let mut replies = tokio::spawn(read_replies(reader, stamper.start, status.clone()));
+    let mut interval = time::interval(PILOT_INTERVAL);
+    let mut current: Option<Wrench> = None;
+    let error = loop {
+        let message = tokio::select! {
+            _ = interval.tick() => match current {
+                Some(wrench) => pilot(wrench),
+                None => continue,
+            },
+            Ok(()) = wrench.changed() => {
+                current = *wrench.borrow_and_update();
+                interval.reset();
+                match current {
+                    Some(wrench) => pilot(wrench),
+                    None => continue,
+                }
+            }
+            message = messages.recv() => match message {
+                Some(message) => {
+                    desired.update(&message);
+                    message
+                }
+                None => break io::Error::other("connection closed"),
+            },
+            result = &mut replies => break match result {
+                Ok(e) => e,
+                Err(e) => io::Error::other(e),
+            },
+        };
+        if let Err(e) = writer.write_all(&stamper.frame(&message)).await {
+            break e;
+        }
+    };
+    replies.abort();
+    error
+}
+
+fn pilot(wrench: Wrench) -> Message {
+    Message::Pilot(PilotInput {
+        wrench,
+        servos: [f32::NAN; 5],
+    })
+}
+
+/// Applies replies to the status until the connection fails.
+async fn read_replies<R: AsyncRead + Unpin>(
+    mut reader: R,
+    start: Instant,
+    status: Arc<watch::Sender<LinkStatus>>,
+) -> io::Error {
+    // The answer to the hello has no groups yet, the one to the take sent
+    // right after it is the first worth showing.
+    let mut hello = true;
+    loop {
+        let reply = match protocol::read_reply(&mut reader).await {
+            Ok(reply) => reply,
+            Err(e) => return e,
+        };
+        let now_ms = start.elapsed().as_millis() as u32;
+        match reply {
+            Reply::Control(_) if hello => hello = false,
+            Reply::Control(control) => status.send_modify(|status| status.control = Some(control)),
+            Reply::Ack(ack) => status.send_modify(|status| status.round_trip.ack(&ack, now_ms)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use finale::link::Ack;
+
+    #[test]
+    fn desired_follows_session_messages() {
+        let mut desired = Desired {
+            role: Role::Pilot,
+            groups: vec![ControlGroup::Flight],
+        };
+        desired.update(&Message::Session(Session::Take(vec![
+            ControlGroup::Flight,
+            ControlGroup::Lights,
+        ])));
+        assert_eq!(
+            desired.groups,
+            vec![ControlGroup::Flight, ControlGroup::Lights]
+        );
+        desired.update(&Message::Session(Session::Hello(Role::CoPilot)));
+        assert_eq!(desired.role, Role::CoPilot);
+        desired.update(&Message::Session(Session::Release(vec![
+            ControlGroup::Flight,
+            ControlGroup::Manipulator,
+        ])));
+        assert_eq!(desired.groups, vec![ControlGroup::Lights]);
+        desired.update(&Message::Command(Command::SetArmed(true)));
+        desired.update(&pilot(Wrench::default()));
+        assert_eq!(desired.role, Role::CoPilot);
+        assert_eq!(desired.groups, vec![ControlGroup::Lights]);
+    }
+
+    #[test]
+    fn frames_carry_a_stamp_then_the_message() {
+        let mut stamper = Stamper {
+            start: Instant::now(),
+            seq: u32::MAX - 1,
+        };
+        let arm = Message::Command(Command::SetArmed(true));
+        for seq in [u32::MAX, 0] {
+            let messages = protocol::decode_frames(&stamper.frame(&arm)).unwrap();
+            assert_eq!(messages.len(), 2);
+            assert!(matches!(messages[0], Message::Stamp { seq: s, .. } if s == seq));
+            assert_eq!(messages[1], arm);
+        }
+    }
+
+    fn control(role: Role, groups: Vec<ControlGroup>) -> Reply {
+        Reply::Control(ControlStatus { role, groups })
+    }
+
+    #[tokio::test]
+    async fn ignores_the_reply_to_hello() {
+        let mut data = control(Role::Pilot, Vec::new()).encode();
+        data.extend(control(Role::Pilot, vec![ControlGroup::Flight]).encode());
+        data.extend(
+            Reply::Ack(Ack {
+                seq: 1,
+                echo_seq: 1,
+                echo_timestamp_ms: 0,
+                delay_ms: 0,
+                received: 3,
+                lost: 1,
+                jitter_ms: 0.0,
+            })
+            .encode(),
+        );
+        let (tx, status) = watch::channel(LinkStatus::default());
+        let tx = Arc::new(tx);
+
+        let error = read_replies(&data[..], Instant::now(), tx.clone()).await;
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let status = status.borrow().clone();
+        assert_eq!(
+            status.control,
+            Some(ControlStatus {
+                role: Role::Pilot,
+                groups: vec![ControlGroup::Flight],
+            })
+        );
+        assert_eq!(status.round_trip.uplink_loss, 0.25);
+
+        // Only the hello answered so far: nothing granted yet.
+        tx.send_replace(LinkStatus::default());
+        let hello = control(Role::Pilot, Vec::new()).encode();
+        read_replies(&hello[..], Instant::now(), tx.clone()).await;
+        assert_eq!(tx.borrow().control, None);
+    }
+}
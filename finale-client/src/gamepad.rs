@@ -0,0 +1,785 @@
+use finale::mixer::Wrench;
+use finale::protocol::Command;
+use finale::stabilise::StabiliseMode;
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::Connection;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Event types of `linux/input-event-codes.h` the mapper looks at.
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+
+/// Names accepted in a mapping for the codes a gamepad usually reports. Any
+/// other code can be given as `ABS_<number>` or `KEY_<number>`.
+const CODES: [(&str, u16, u16); 23] = [
+    ("ABS_X", EV_ABS, 0x00),
+    ("ABS_Y", EV_ABS, 0x01),
+    ("ABS_Z", EV_ABS, 0x02),
+    ("ABS_RX", EV_ABS, 0x03),
+    ("ABS_RY", EV_ABS, 0x04),
+    ("ABS_RZ", EV_ABS, 0x05),
+    ("ABS_GAS", EV_ABS, 0x09),
+    ("ABS_BRAKE", EV_ABS, 0x0a),
+    ("ABS_HAT0X", EV_ABS, 0x10),
+    ("ABS_HAT0Y", EV_ABS, 0x11),
+    ("BTN_SOUTH", EV_KEY, 0x130),
+    ("BTN_EAST", EV_KEY, 0x131),
+    ("BTN_NORTH", EV_KEY, 0x133),
+    ("BTN_WEST", EV_KEY, 0x134),
+    ("BTN_TL", EV_KEY, 0x136),
+    ("BTN_TR", EV_KEY, 0x137),
+    ("BTN_TL2", EV_KEY, 0x138),
+    ("BTN_TR2", EV_KEY, 0x139),
+    ("BTN_SELECT", EV_KEY, 0x13a),
+    ("BTN_START", EV_KEY, 0x13b),
+    ("BTN_MODE", EV_KEY, 0x13c),
+    ("BTN_THUMBL", EV_KEY, 0x13d),
+    ("BTN_THUMBR", EV_KEY, 0x13e),
+];
+
+/// Raw range assumed for an axis when neither the mapping nor the device
+/// gives one.
+const DEFAULT_RANGE: (i32, i32) = (-32768, 32767);
+
+/// Smallest change of a servo axis, in degrees, worth a command.
+const SERVO_RESOLUTION: f32 = 1.0;
+
+/// One event as read from an evdev device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// What a mapped axis drives.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AxisTarget {
+    Surge,
+    Sway,
+    Heave,
+    Roll,
+    Pitch,
+    Yaw,
+    /// Positions a servo, the ends of the axis at `min` and `max` degrees.
+    Servo {
+        index: u8,
+        min: f32,
+        max: f32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AxisMapping {
+    /// Code name, e.g. `ABS_Y`.
+    pub input: String,
+    pub target: AxisTarget,
+    /// Raw range, taken from the device when not given.
+    #[serde(default)]
+    pub min: Option<i32>,
+    #[serde(default)]
+    pub max: Option<i32>,
+    /// Sticks rest in the middle of their range and give -1..1, triggers
+    /// rest at one end and give 0..1.
+    #[serde(default = "default_true")]
+    pub centred: bool,
+    #[serde(default)]
+    pub invert: bool,
+    /// Fraction of the travel around rest that reads as zero.
+    #[serde(default = "default_deadzone")]
+    pub deadzone: f32,
+    /// Blend between a linear (0) and a cubic (1) response, for finer
+    /// control near rest.
+    #[serde(default = "default_expo")]
+    pub expo: f32,
+    /// Applied after the expo, mappings to the same thrust axis add up.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_deadzone() -> f32 {
+    0.08
+}
+
+fn default_expo() -> f32 {
+    0.3
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// What a button does when pressed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    /// Sent once per press, e.g. `{"command": {"set_armed": true}}`.
+    Command(Command),
+    /// Steps through [`GamepadConfig::gains`]. Speed gears of the vehicle,
+    /// shared by every client, are changed with the `shift_gear` command.
+    GainUp,
+    GainDown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ButtonMapping {
+    /// Code name of a button, e.g. `BTN_START`, or of one direction of an
+    /// axis, e.g. `ABS_HAT0Y-` for the d-pad up.
+    pub input: String,
+    pub action: ButtonAction,
+}
+
+/// How the gamepad drives the vehicle, read from a JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    pub axes: Vec<AxisMapping>,
+    pub buttons: Vec<ButtonMapping>,
+    /// Scales applied to every thrust axis on top of the vehicle gear, the
+    /// gain buttons step through them.
+    pub gains: Vec<f32>,
+    /// Index of the gain used at start.
+    pub gain: usize,
+}
+
+impl Default for GamepadConfig {
+    /// Xbox style layout: left stick surge and yaw, right stick heave and
+    /// sway, right trigger on the gripper (servo 0), start and select arm
+    /// and disarm, the bumpers shift the vehicle gear.
+    fn default() -> Self {
+        let axis = |input: &str, target, invert| AxisMapping {
+            input: input.to_string(),
+            target,
+            min: None,
+            max: None,
+            centred: true,
+            invert,
+            deadzone: default_deadzone(),
+            expo: default_expo(),
+            scale: default_scale(),
+        };
+        let button = |input: &str, action| ButtonMapping {
+            input: input.to_string(),
+            action,
+        };
+        GamepadConfig {
+            axes: vec![
+                axis("ABS_Y", AxisTarget::Surge, true),
+                axis("ABS_X", AxisTarget::Yaw, false),
+                axis("ABS_RY", AxisTarget::Heave, true),
+                axis("ABS_RX", AxisTarget::Sway, false),
+                AxisMapping {
+                    centred: false,
+                    deadzone: 0.0,
+                    expo: 0.0,
+                    ..axis(
+                        "ABS_RZ",
+                        AxisTarget::Servo {
+                            index: 0,
+                            min: -90.0,
+                            max: 90.0,
+                        },
+                        false,
+                    )
+                },
+            ],
+            buttons: vec![
+                button("BTN_START", ButtonAction::Command(Command::SetArmed(true))),
+                button(
+                    "BTN_SELECT",
+                    ButtonAction::Command(Command::SetArmed(false)),
+                ),
+                button("BTN_TR", ButtonAction::Command(Command::ShiftGear(1))),
+                button("BTN_TL", ButtonAction::Command(Command::ShiftGear(-1))),
+                button(
+                    "BTN_NORTH",
+                    ButtonAction::Command(Command::SetStabilise(StabiliseMode::Level)),
+                ),
+                button(
+                    "BTN_WEST",
+                    ButtonAction::Command(Command::SetStabilise(StabiliseMode::Off)),
+                ),
+            ],
+            gains: vec![1.0],
+            gain: 0,
+        }
+    }
+}
+
+impl GamepadConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GamepadConfig, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let config = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
+
+/// Resolves a code name to its event type and code.
+fn parse_code(name: &str) -> Result<(u16, u16), String> {
+    if let Some(&(_, kind, code)) = CODES.iter().find(|(known, _, _)| *known == name) {
+        return Ok((kind, code));
+    }
+    let (kind, number) = if let Some(number) = name.strip_prefix("ABS_") {
+        (EV_ABS, number)
+    } else if let Some(number) = name.strip_prefix("KEY_") {
+        (EV_KEY, number)
+    } else {
+        return Err(format!("unknown input {}", name));
+    };
+    let code = number
+        .parse()
+        .map_err(|_| format!("unknown input {}", name))?;
+    Ok((kind, code))
+}
+
+struct Axis {
+    kind: u16,
+    code: u16,
+    mapping: AxisMapping,
+    /// Last servo position sent.
+    sent: Option<f32>,
+}
+
+struct Button {
+    kind: u16,
+    code: u16,
+    /// For an axis used as a button, the direction that presses it.
+    direction: i32,
+    action: ButtonAction,
+    pressed: bool,
+}
+
+/// Turns gamepad events into a wrench and commands.
+///
+/// Thrust axes are summed per target, scaled by the current gain and
+/// clamped to [-1, 1]. An axis reads as rest until its first event, so a
+/// stick is never taken for deflected because the device has not moved yet.
+pub struct Mapper {
+    axes: Vec<Axis>,
+    buttons: Vec<Button>,
+    gains: Vec<f32>,
+    gain: usize,
+    /// Latest raw value of every axis.
+    values: HashMap<u16, i32>,
+    ranges: HashMap<u16, (i32, i32)>,
+}
+
+impl Mapper {
+    pub fn new(config: &GamepadConfig) -> Result<Mapper, String> {
+        let mut axes = Vec::new();
+        for mapping in &config.axes {
+            let (kind, code) = parse_code(&mapping.input)?;
+            if kind != EV_ABS {
+                return Err(format!("{} is not an axis", mapping.input));
+            }
+            axes.push(Axis {
+                kind,
+                code,
+                mapping: mapping.clone(),
+                sent: None,
+            });
+        }
+        let mut buttons = Vec::new();
+        for mapping in &config.buttons {
+            let (name, direction) = match mapping.input.strip_suffix('-') {
+                Some(name) => (name, -1),
+                None => match mapping.input.strip_suffix('+') {
+                    Some(name) => (name, 1),
+                    None => (mapping.input.as_str(), 0),
+                },
+            };
+            let (kind, code) = parse_code(name)?;
+            if (kind == EV_ABS) != (direction != 0) {
+                return Err(format!(
+                    "{}: axes need a direction, buttons none",
+                    mapping.input
+                ));
+            }
+            buttons.push(Button {
+                kind,
+                code,
+                direction,
+                action: mapping.action.clone(),
+                pressed: false,
+            });
+        }
+        if config.gains.is_empty() {
+            return Err("no gains".to_string());
+        }
+        Ok(Mapper {
+            axes,
+            buttons,
+            gains: config.gains.clone(),
+            gain: config.gain.min(config.gains.len() - 1),
+            values: HashMap::new(),
+            ranges: HashMap::new(),
+        })
+    }
+
+    /// Sets the raw range the device reports for an axis, used by mappings
+    /// that do not give their own.
+    pub fn set_range(&mut self, code: u16, min: i32, max: i32) {
+        if min < max {
+            self.ranges.insert(code, (min, max));
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gains[self.gain]
+    }
+
+    /// Takes one event, returning the commands it triggers.
+    pub fn event(&mut self, event: InputEvent) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if event.kind == EV_ABS {
+            self.values.insert(event.code, event.value);
+        }
+        for index in 0..self.buttons.len() {
+            let button = &self.buttons[index];
+            if button.kind != event.kind || button.code != event.code {
+                continue;
+            }
+            let pressed = if button.direction == 0 {
+                event.value != 0
+            } else {
+                self.normalised(event.code, None, None, true) * button.direction as f32 > 0.5
+            };
+            let button = &mut self.buttons[index];
+            let action = (pressed && !button.pressed).then(|| button.action.clone());
+            button.pressed = pressed;
+            match action {
+                Some(ButtonAction::Command(command)) => commands.push(command),
+                Some(ButtonAction::GainUp) => self.gain = (self.gain + 1).min(self.gains.len() - 1),
+                Some(ButtonAction::GainDown) => self.gain = self.gain.saturating_sub(1),
+                None => {}
+            }
+        }
+        for index in 0..self.axes.len() {
+            let axis = &self.axes[index];
+            if axis.kind != event.kind || axis.code != event.code {
+                continue;
+            }
+            if let AxisTarget::Servo {
+                index: servo,
+                min,
+                max,
+            } = axis.mapping.target
+            {
+                let value = self.shaped(axis);
+                let fraction = if axis.mapping.centred {
+                    (value + 1.0) / 2.0
+                } else {
+                    value
+                };
+                let degrees = min + fraction.clamp(0.0, 1.0) * (max - min);
+                let axis = &mut self.axes[index];
+                if axis
+                    .sent
+                    .is_none_or(|sent| (degrees - sent).abs() >= SERVO_RESOLUTION)
+                {
+                    axis.sent = Some(degrees);
+                    commands.push(Command::SetServo {
+                        index: servo,
+                        degrees,
+                    });
+                }
+            }
+        }
+        commands
+    }
+
+    /// Current thrust request.
+    pub fn wrench(&self) -> Wrench {
+        let mut wrench = Wrench::default();
+        for axis in &self.axes {
+            let value = self.shaped(axis) * axis.mapping.scale * self.gain();
+            match axis.mapping.target {
+                AxisTarget::Surge => wrench.surge += value,
+                AxisTarget::Sway => wrench.sway += value,
+                AxisTarget::Heave => wrench.heave += value,
+                AxisTarget::Roll => wrench.roll += value,
+                AxisTarget::Pitch => wrench.pitch += value,
+                AxisTarget::Yaw => wrench.yaw += value,
+                AxisTarget::Servo { .. } => {}
+            }
+        }
+        for value in [
+            &mut wrench.surge,
+            &mut wrench.sway,
+            &mut wrench.heave,
+            &mut wrench.roll,
+            &mut wrench.pitch,
+            &mut wrench.yaw,
+        ] {
+            *value = value.clamp(-1.0, 1.0);
+        }
+        wrench
+    }
+
+    /// Raw value of an axis scaled to -1..1 (centred) or 0..1, 0 before its
+    /// first event.
+    fn normalised(&self, code: u16, min: Option<i32>, max: Option<i32>, centred: bool) -> f32 {
+        let value = match self.values.get(&code) {
+            Some(value) => *value as f32,
+            None => return 0.0,
+        };
+        let range = self.ranges.get(&code).copied().unwrap_or(DEFAULT_RANGE);
+        let min = min.unwrap_or(range.0) as f32;
+        let max = max.unwrap_or(range.1) as f32;
+        let fraction = ((value - min) / (max - min)).clamp(0.0, 1.0);
+        if centred {
+            fraction * 2.0 - 1.0
+        } else {
+            fraction
+        }
+    }
+
+    /// Axis value after deadzone, expo and inversion.
+    fn shaped(&self, axis: &Axis) -> f32 {
+        let mapping = &axis.mapping;
+        let mut value = self.normalised(axis.code, mapping.min, mapping.max, mapping.centred);
+        if mapping.invert {
+            value = if mapping.centred { -value } else { 1.0 - value };
+        }
+        let magnitude = value.abs();
+        if magnitude <= mapping.deadzone {
+            return 0.0;
+        }
+        let magnitude = (magnitude - mapping.deadzone) / (1.0 - mapping.deadzone);
+        let expo = mapping.expo.clamp(0.0, 1.0);
+        let magnitude = (1.0 - expo) * magnitude + expo * magnitude.powi(3);
+        magnitude.copysign(value)
+    }
+}
+
+/// Size of a `struct input_event` on 64 bit Linux: a timeval of two i64,
+/// then type, code and value.
+const RECORD_LEN: usize = 24;
+
+/// Reads events recorded from a device node, e.g. with
+/// `cat /dev/input/event5 > pad.bin`, with their time since the first one.
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<(Duration, InputEvent)>> {
+    let data = fs::read(path)?;
+    if data.len() % RECORD_LEN != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} bytes is not a whole number of events", data.len()),
+        ));
+    }
+    let mut start = None;
+    let mut events = Vec::with_capacity(data.len() / RECORD_LEN);
+    for record in data.chunks_exact(RECORD_LEN) {
+        let seconds = i64::from_le_bytes(record[0..8].try_into().unwrap());
+        let micros = i64::from_le_bytes(record[8..16].try_into().unwrap());
+        let time = seconds as f64 + micros as f64 / 1e6;
+        let start = *start.get_or_insert(time);
+        let event = InputEvent {
+            kind: u16::from_le_bytes([record[16], record[17]]),
+            code: u16::from_le_bytes([record[18], record[19]]),
+            value: i32::from_le_bytes(record[20..24].try_into().unwrap()),
+        };
+        events.push((Duration::from_secs_f64((time - start).max(0.0)), event));
+    }
+    Ok(events)
+}
+
+/// Hands one event to the mapper and what comes out to the vehicle.
+pub fn apply(mapper: &mut Mapper, event: InputEvent, vehicle: &Connection) {
+    for command in mapper.event(event) {
+        vehicle.send(command);
+    }
+    vehicle.pilot(mapper.wrench());
+}
+
+/// Plays a recording back in real time.
+pub async fn replay(
+    recording: &[(Duration, InputEvent)],
+    mapper: &mut Mapper,
+    vehicle: &Connection,
+) {
+    let start = time::Instant::now();
+    for (at, event) in recording {
+        time::sleep_until(start + *at).await;
+        apply(mapper, *event, vehicle);
+    }
+}
+
+/// A gamepad read through evdev, a real one or a uinput virtual device.
+#[cfg(target_os = "linux")]
+pub struct Gamepad {
+    stream: evdev::EventStream,
+}
+
+#[cfg(target_os = "linux")]
+impl Gamepad {
+    /// Opens a device node, or the first device with a left stick and a
+    /// south button.
+    pub fn open(path: Option<&str>) -> io::Result<Gamepad> {
+        let device = match path {
+            Some(path) => evdev::Device::open(path)?,
+            None => evdev::enumerate()
+                .map(|(_, device)| device)
+                .find(|device| {
+                    let stick = device
+                        .supported_absolute_axes()
+                        .is_some_and(|axes| axes.contains(evdev::AbsoluteAxisType::ABS_X));
+                    let button = device
+                        .supported_keys()
+                        .is_some_and(|keys| keys.contains(evdev::Key::BTN_SOUTH));
+                    stick && button
+                })
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no gamepad found"))?,
+        };
+        println!("gamepad: {}", device.name().unwrap_or("unnamed"));
+        Ok(Gamepad {
+            stream: device.into_event_stream()?,
+        })
+    }
+
+    /// Copies the axis ranges and positions of the device into the mapper.
+    pub fn configure(&self, mapper: &mut Mapper) -> io::Result<()> {
+        let device = self.stream.device();
+        let state = device.get_abs_state()?;
+        if let Some(axes) = device.supported_absolute_axes() {
+            for axis in axes.iter() {
+                let info = &state[axis.0 as usize];
+                mapper.set_range(axis.0, info.minimum, info.maximum);
+                mapper.event(InputEvent {
+                    kind: EV_ABS,
+                    code: axis.0,
+                    value: info.value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn next(&mut self) -> io::Result<InputEvent> {
+        let event = self.stream.next_event().await?;
+        Ok(InputEvent {
+            kind: event.event_type().0,
+            code: event.code(),
+            value: event.value(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            kind: EV_KEY,
+            code,
+            value,
+        }
+    }
+
+    fn abs(code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            kind: EV_ABS,
+            code,
+            value,
+        }
+    }
+
+    const ABS_X: u16 = 0x00;
+    const ABS_Y: u16 = 0x01;
+    const ABS_RZ: u16 = 0x05;
+    const ABS_HAT0Y: u16 = 0x11;
+    const BTN_SOUTH: u16 = 0x130;
+    const BTN_START: u16 = 0x13b;
+
+    #[test]
+    fn parses_code_names() {
+        assert_eq!(parse_code("BTN_START"), Ok((EV_KEY, BTN_START)));
+        assert_eq!(parse_code("ABS_HAT0Y"), Ok((EV_ABS, ABS_HAT0Y)));
+        assert_eq!(parse_code("ABS_40"), Ok((EV_ABS, 40)));
+        assert_eq!(parse_code("KEY_304"), Ok((EV_KEY, 304)));
+        assert!(parse_code("BTN_NOPE").is_err());
+        assert!(parse_code("ABS_Q").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_mappings() {
+        let axis_on_button = GamepadConfig {
+            axes: vec![AxisMapping {
+                input: "BTN_SOUTH".to_string(),
+                ..GamepadConfig::default().axes.remove(0)
+            }],
+            ..GamepadConfig::default()
+        };
+        assert!(Mapper::new(&axis_on_button).is_err());
+        for input in ["ABS_HAT0Y", "BTN_SOUTH+"] {
+            let config = GamepadConfig {
+                buttons: vec![ButtonMapping {
+                    input: input.to_string(),
+                    action: ButtonAction::GainUp,
+                }],
+                ..GamepadConfig::default()
+            };
+            assert!(Mapper::new(&config).is_err(), "{}", input);
+        }
+        let no_gains = GamepadConfig {
+            gains: Vec::new(),
+            ..GamepadConfig::default()
+        };
+        assert!(Mapper::new(&no_gains).is_err());
+    }
+
+    #[test]
+    fn sticks_read_as_rest_until_they_move() {
+        let mut mapper = Mapper::new(&GamepadConfig::default()).unwrap();
+        assert_eq!(mapper.wrench(), Wrench::default());
+        // Full forward on the inverted left stick, inside the deadzone
+        // sideways.
+        mapper.event(abs(ABS_Y, -32768));
+        mapper.event(abs(ABS_X, 1000));
+        let wrench = mapper.wrench();
+        assert_eq!(wrench.surge, 1.0);
+        assert_eq!(wrench.yaw, 0.0);
+        // Half way: (0.5 - 0.08) / 0.92 through the 0.3 expo.
+        mapper.event(abs(ABS_X, 16384));
+        let magnitude: f32 = (0.5 - 0.08) / 0.92;
+        let expected = 0.7 * magnitude + 0.3 * magnitude.powi(3);
+        assert!((mapper.wrench().yaw - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mapped_axes_add_up_and_clamp() {
+        let mut config = GamepadConfig::default();
+        let mut extra = config.axes[0].clone();
+        extra.input = "ABS_HAT0Y".to_string();
+        extra.min = Some(-1);
+        extra.max = Some(1);
+        extra.scale = 0.5;
+        config.axes.push(extra);
+        config.gains = vec![0.5, 1.0];
+        config.gain = 1;
+        let mut mapper = Mapper::new(&config).unwrap();
+        mapper.event(abs(ABS_HAT0Y, -1));
+        assert_eq!(mapper.wrench().surge, 0.5);
+        mapper.event(abs(ABS_Y, -32768));
+        assert_eq!(mapper.wrench().surge, 1.0);
+    }
+
+    #[test]
+    fn buttons_fire_once_per_press() {
+        let mut mapper = Mapper::new(&GamepadConfig::default()).unwrap();
+        assert_eq!(
+            mapper.event(key(BTN_START, 1)),
+            vec![Command::SetArmed(true)]
+        );
+        assert!(mapper.event(key(BTN_START, 2)).is_empty());
+        assert!(mapper.event(key(BTN_START, 0)).is_empty());
+        assert_eq!(
+            mapper.event(key(BTN_START, 1)),
+            vec![Command::SetArmed(true)]
+        );
+        assert!(mapper.event(key(BTN_SOUTH, 1)).is_empty());
+    }
+
+    #[test]
+    fn axis_directions_work_as_buttons() {
+        let config = GamepadConfig {
+            buttons: vec![
+                ButtonMapping {
+                    input: "ABS_HAT0Y-".to_string(),
+                    action: ButtonAction::GainUp,
+                },
+                ButtonMapping {
+                    input: "ABS_HAT0Y+".to_string(),
+                    action: ButtonAction::GainDown,
+                },
+            ],
+            gains: vec![0.25, 0.5, 1.0],
+            gain: 0,
+            ..GamepadConfig::default()
+        };
+        let mut mapper = Mapper::new(&config).unwrap();
+        mapper.set_range(ABS_HAT0Y, -1, 1);
+        for value in [-1, 0, -1, 0, -1, 0] {
+            mapper.event(abs(ABS_HAT0Y, value));
+        }
+        assert_eq!(mapper.gain(), 1.0);
+        mapper.event(abs(ABS_HAT0Y, 1));
+        assert_eq!(mapper.gain(), 0.5);
+        mapper.event(abs(ABS_HAT0Y, 1));
+        assert_eq!(mapper.gain(), 0.5);
+    }
+
+    #[test]
+    fn servo_axes_send_changes_of_a_degree() {
+        let mut mapper = Mapper::new(&GamepadConfig::default()).unwrap();
+        mapper.set_range(ABS_RZ, 0, 1023);
+        assert_eq!(
+            mapper.event(abs(ABS_RZ, 0)),
+            vec![Command::SetServo {
+                index: 0,
+                degrees: -90.0
+            }]
+        );
+        assert!(mapper.event(abs(ABS_RZ, 3)).is_empty());
+        assert_eq!(
+            mapper.event(abs(ABS_RZ, 1023)),
+            vec![Command::SetServo {
+                index: 0,
+                degrees: 90.0
+            }]
+        );
+        assert_eq!(mapper.wrench(), Wrench::default());
+    }
+
+    fn record(seconds: i64, micros: i64, event: InputEvent) -> Vec<u8> {
+        let mut record = seconds.to_le_bytes().to_vec();
+        record.extend_from_slice(&micros.to_le_bytes());
+        record.extend_from_slice(&event.kind.to_le_bytes());
+        record.extend_from_slice(&event.code.to_le_bytes());
+        record.extend_from_slice(&event.value.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn reads_recorded_events() {
+        let path = std::env::temp_dir().join(format!("finale-pad-{}.bin", std::process::id()));
+        let mut data = record(1000, 900_000, key(BTN_START, 1));
+        data.extend(record(1001, 150_000, abs(ABS_Y, -32768)));
+        data.extend(record(
+            1001,
+            150_000,
+            InputEvent {
+                kind: 0,
+                code: 0,
+                value: 0,
+            },
+        ));
+        fs::write(&path, &data).unwrap();
+        let events = read_recording(&path).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], (Duration::ZERO, key(BTN_START, 1)));
+        assert_eq!(events[1].1, abs(ABS_Y, -32768));
+        assert!((events[1].0.as_secs_f64() - 0.25).abs() < 1e-6);
+        assert_eq!(events[2].0, events[1].0);
+
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let error = read_recording(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,42 @@
+//! Topside side of the finale link.
+//!
+//! A [`Connection`] drives the vehicle over the framed TCP protocol, using
+//! the codec of the daemon itself, and reconnects on its own. A
+//! [`Subscription`] follows the telemetry stream served over HTTP. The
+//! [`gamepad`] module turns gamepad input into pilot commands.
+//!
+//! ```no_run
+//! use finale_client::{Connection, ControlGroup, Role, Subscription, Wrench};
+//!
+//! # async fn fly() {
+//! let vehicle = Connection::open("rov.local:12345", Role::Pilot, ControlGroup::ALL.to_vec());
+//! let mut telemetry = Subscription::open("rov.local:8080", 10.0);
+//! vehicle.connected().await;
+//! vehicle.arm();
+//! while let Some(sample) = telemetry.next().await {
+//!     let surge = if sample.attitude.pitch.abs() < 0.3 { 0.5 } else { 0.0 };
+//!     vehicle.pilot(Wrench { surge, ..Wrench::default() });
+//! }
+//! # }
+//! ```
+
+mod connection;
+pub mod gamepad;
+mod subscription;
+
+pub use connection::{Connection, LinkStatus};
+pub use subscription::Subscription;
+
+pub use finale::alarms::Severity;
+pub use finale::autotune::TuneAxis;
+pub use finale::control::{ControlGroup, ControlStatus, Role};
+pub use finale::link::RoundTrip;
+pub use finale::mixer::Wrench;
+pub use finale::protocol::Command;
+pub use finale::stabilise::StabiliseMode;
+pub use finale::telemetry::Telemetry;
+
+use std::time::Duration;
+
+/// Wait between connection attempts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
@@ -0,0 +1,146 @@
+use finale::telemetry::Telemetry;
+use hyper::body::HttpBody;
+use hyper::{Client, StatusCode, Uri};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::RECONNECT_DELAY;
+
+use std::error::Error;
+
+/// Telemetry from the server-sent event stream of the daemon, followed in
+/// the background and resumed after a dropout.
+pub struct Subscription {
+    telemetry: watch::Receiver<Option<Telemetry>>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// Subscribes to the HTTP server of the daemon, e.g. `rov.local:8080`,
+    /// at `rate` samples per second.
+    pub fn open(address: impl Into<String>, rate: f32) -> Subscription {
+        let (tx, telemetry) = watch::channel(None);
+        let uri = format!("http://{}/api/stream?rate={}", address.into(), rate);
+        let task = tokio::spawn(run(uri, tx));
+        Subscription { telemetry, task }
+    }
+
+    /// Latest sample, `None` while the stream is down.
+    pub fn latest(&self) -> Option<Telemetry> {
+        self.telemetry.borrow().clone()
+    }
+
+    /// Waits for the next sample.
+    pub async fn next(&mut self) -> Option<Telemetry> {
+        loop {
+            self.telemetry.changed().await.ok()?;
+            if let Some(sample) = self.telemetry.borrow_and_update().clone() {
+                return Some(sample);
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(uri: String, tx: watch::Sender<Option<Telemetry>>) {
+    loop {
+        if let Err(e) = stream(&uri, &tx).await {
+            println!("telemetry stream from {}: {}", uri, e);
+        }
+        tx.send_replace(None);
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn stream(uri: &str, tx: &watch::Sender<Option<Telemetry>>) -> Result<(), Box<dyn Error>> {
+    let uri: Uri = uri.parse()?;
+    let response = Client::new().get(uri).await?;
+    if response.status() != StatusCode::OK {
+        return Err(format!("server answered {}", response.status()).into());
+    }
+    let mut body = response.into_body();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        buf.extend_from_slice(&chunk?);
+        while let Some(event) = take_event(&mut buf) {
+            if let Some(sample) = parse_event(&event)? {
+                tx.send_replace(Some(sample));
+            }
+        }
+    }
+    Err("stream ended".into())
+}
+
+/// Removes the first whole event from `buf`. Chunks can end anywhere, even
+/// inside a character, so events are cut from the bytes before decoding.
+fn take_event(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let end = buf.windows(2).position(|pair| pair == b"\n\n")?;
+    let mut event: Vec<u8> = buf.drain(..end + 2).collect();
+    event.truncate(end);
+    Some(event)
+}
+
+/// Telemetry carried by an event, `None` for events without data such as
+/// comments. The data lines of an event are joined as one sample.
+fn parse_event(event: &[u8]) -> Result<Option<Telemetry>, Box<dyn Error>> {
+    let event = std::str::from_utf8(event)?;
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let sample = serde_json::from_str(&data.join("\n"))
+        .map_err(|e| format!("bad telemetry sample: {}", e))?;
+    Ok(Some(sample))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_split_across_chunks() {
+        let sample = serde_json::to_string(&Telemetry::default()).unwrap();
+        let stream = format!(": hello\n\ndata: {}\n\ndata: {}\n\n", sample, sample);
+        let mut buf = Vec::new();
+        let mut samples = Vec::new();
+        let mut events = 0;
+        // One byte at a time, so every event is cut somewhere.
+        for byte in stream.bytes() {
+            buf.push(byte);
+            while let Some(event) = take_event(&mut buf) {
+                events += 1;
+                samples.extend(parse_event(&event).unwrap());
+            }
+        }
+        assert_eq!(events, 3);
+        assert_eq!(samples, vec![Telemetry::default(), Telemetry::default()]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn joins_data_lines() {
+        let sample = serde_json::to_string(&Telemetry {
+            time: 2.5,
+            ..Telemetry::default()
+        })
+        .unwrap();
+        // Broken after a comma, JSON strings cannot hold the newline.
+        let at = sample.find(',').unwrap() + 1;
+        let (first, second) = sample.split_at(at);
+        let event = format!("event: telemetry\ndata: {}\ndata:{}", first, second);
+        let parsed = parse_event(event.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed.time, 2.5);
+        assert!(parse_event(format!("data: {}", first).as_bytes()).is_err());
+        assert!(parse_event(&[b'd', b'a', b't', b'a', b':', 0xE2, 0x82]).is_err());
+    }
+}
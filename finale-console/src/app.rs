@@ -0,0 +1,298 @@
+use finale_client::{Connection, LinkStatus, StabiliseMode, Telemetry, Wrench};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Change of an axis per key press.
+const AXIS_STEP: f32 = 0.1;
+
+/// Change of a servo target per key press, in degrees.
+const SERVO_STEP: f32 = 5.0;
+
+const LIGHT_STEP: u8 = 10;
+
+/// Keys raising and lowering each axis, in the order of
+/// [`Wrench::as_array`].
+pub const AXIS_KEYS: [(char, char, &str); 6] = [
+    ('w', 's', "surge"),
+    ('d', 'a', "sway"),
+    ('r', 'f', "heave"),
+    ('l', 'j', "roll"),
+    ('i', 'k', "pitch"),
+    ('c', 'z', "yaw"),
+];
+
+pub const HELP: &str =
+    "w/s a/d r/f z/c i/k j/l axes  space stop  1-9 servo  [ ] move  m arm  g mode  , . gear  +/- light  esc disarm  q quit";
+
+/// Keyboard state of the console and the last telemetry it saw.
+pub struct App {
+    /// Axes are latched, a key press moves them one step.
+    pub wrench: Wrench,
+    pub servo: usize,
+    /// Servo targets in degrees, `None` until known.
+    pub servo_targets: Vec<Option<f32>>,
+    pub lights: u8,
+    pub telemetry: Option<Telemetry>,
+    pub status: LinkStatus,
+    /// Feedback for the last key, shown above the help line.
+    pub notice: String,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new() -> Self {
+        App {
+            wrench: Wrench::default(),
+            servo: 0,
+            servo_targets: vec![None; 5],
+            lights: 0,
+            telemetry: None,
+            status: LinkStatus::default(),
+            notice: String::new(),
+            quit: false,
+        }
+    }
+
+    fn armed(&self) -> bool {
+        self.telemetry.as_ref().is_some_and(|t| t.armed)
+    }
+
+    pub fn key(&mut self, key: KeyEvent, vehicle: &Connection) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Esc => {
+                self.wrench = Wrench::default();
+                vehicle.disarm();
+                self.notice = "disarm".to_string();
+            }
+            KeyCode::Char(' ') => {
+                self.wrench = Wrench::default();
+                self.notice = "all stop".to_string();
+            }
+            KeyCode::Char('m') => {
+                // Never arm into a latched thrust.
+                self.wrench = Wrench::default();
+                if self.armed() {
+                    vehicle.disarm();
+                    self.notice = "disarm".to_string();
+                } else {
+                    vehicle.arm();
+                    self.notice = "arm".to_string();
+                }
+            }
+            KeyCode::Char('g') => {
+                let mode = match self.telemetry.as_ref().map(|t| t.stabilise) {
+                    Some(StabiliseMode::Off) => StabiliseMode::Level,
+                    Some(StabiliseMode::Level) => StabiliseMode::Attitude,
+                    Some(StabiliseMode::Attitude) => StabiliseMode::Rate,
+                    Some(StabiliseMode::Rate) | None => StabiliseMode::Off,
+                };
+                vehicle.set_stabilise(mode);
+                self.notice = format!("stabilise {:?}", mode);
+            }
+            KeyCode::Char('.') => {
+                vehicle.shift_gear(1);
+                self.notice = "gear up".to_string();
+            }
+            KeyCode::Char(',') => {
+                vehicle.shift_gear(-1);
+                self.notice = "gear down".to_string();
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.lights = (self.lights + LIGHT_STEP).min(100);
+                vehicle.set_light(0xFF, self.lights);
+                self.notice = format!("lights {}%", self.lights);
+            }
+            KeyCode::Char('-') => {
+                self.lights = self.lights.saturating_sub(LIGHT_STEP);
+                vehicle.set_light(0xFF, self.lights);
+                self.notice = format!("lights {}%", self.lights);
+            }
+            KeyCode::Char(digit @ '1'..='9') => {
+                let index = digit as usize - '1' as usize;
+                if index < self.servo_targets.len() {
+                    self.servo = index;
+                    self.notice = format!("servo {}", self.servo_name(index));
+                }
+            }
+            KeyCode::Char('[') => self.move_servo(-SERVO_STEP, vehicle),
+            KeyCode::Char(']') => self.move_servo(SERVO_STEP, vehicle),
+            KeyCode::Char(c) => {
+                if let Some(axis) = AXIS_KEYS
+                    .iter()
+                    .position(|(up, down, _)| c == *up || c == *down)
+                {
+                    let step = if c == AXIS_KEYS[axis].0 {
+                        AXIS_STEP
+                    } else {
+                        -AXIS_STEP
+                    };
+                    let value = axis_mut(&mut self.wrench, axis);
+                    // Round so repeated steps land on zero exactly.
+                    *value = ((*value + step).clamp(-1.0, 1.0) * 10.0).round() / 10.0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Adopts a new telemetry sample, sizing the servo list to the vehicle
+    /// and taking its targets, which it clamps to the servo travel.
+    pub fn update(&mut self, telemetry: Telemetry) {
+        self.servo_targets.resize(telemetry.servos.len(), None);
+        for (target, servo) in self.servo_targets.iter_mut().zip(&telemetry.servos) {
+            if servo.target.is_some() {
+                *target = servo.target;
+            }
+        }
+        self.servo = self.servo.min(telemetry.servos.len().saturating_sub(1));
+        self.telemetry = Some(telemetry);
+    }
+
+    pub fn servo_name(&self, index: usize) -> String {
+        self.telemetry
+            .as_ref()
+            .and_then(|t| t.servos.get(index))
+            .map_or_else(|| format!("b{}", index), |servo| servo.name.clone())
+    }
+
+    fn move_servo(&mut self, step: f32, vehicle: &Connection) {
+        if self.servo >= self.servo_targets.len() {
+            return;
+        }
+        // Start from where the vehicle has the servo.
+        let current = self.servo_targets[self.servo]
+            .or_else(|| {
+                let servo = self.telemetry.as_ref()?.servos.get(self.servo)?;
+                servo.target.or(servo.angle)
+            })
+            .unwrap_or(0.0);
+        let degrees = current + step;
+        self.servo_targets[self.servo] = Some(degrees);
+        vehicle.set_servo(self.servo as u8, degrees);
+        self.notice = format!("{} to {:.0}°", self.servo_name(self.servo), degrees);
+    }
+}
+
+fn axis_mut(wrench: &mut Wrench, axis: usize) -> &mut f32 {
+    match axis {
+        0 => &mut wrench.surge,
+        1 => &mut wrench.sway,
+        2 => &mut wrench.heave,
+        3 => &mut wrench.roll,
+        4 => &mut wrench.pitch,
+        _ => &mut wrench.yaw,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use finale::telemetry::ServoTelemetry;
+    use finale_client::{ControlGroup, Role};
+
+    /// A connection to nowhere, its commands are dropped.
+    fn vehicle() -> Connection {
+        Connection::open("127.0.0.1:9", Role::Pilot, ControlGroup::ALL.to_vec())
+    }
+
+    fn press(app: &mut App, vehicle: &Connection, c: char) {
+        app.key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE), vehicle);
+    }
+
+    fn servo(name: &str, target: Option<f32>) -> ServoTelemetry {
+        ServoTelemetry {
+            name: name.to_string(),
+            pulse: None,
+            angle: target,
+            target,
+            backed_off: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn arming_drops_the_latched_wrench() {
+        let vehicle = vehicle();
+        let mut app = App::new();
+        press(&mut app, &vehicle, 'w');
+        press(&mut app, &vehicle, 'c');
+        press(&mut app, &vehicle, 'm');
+        assert_eq!(app.wrench, Wrench::default());
+        assert_eq!(app.notice, "arm");
+
+        app.update(Telemetry {
+            armed: true,
+            ..Telemetry::default()
+        });
+        press(&mut app, &vehicle, 'r');
+        press(&mut app, &vehicle, 'm');
+        assert_eq!(app.wrench, Wrench::default());
+        assert_eq!(app.notice, "disarm");
+    }
+
+    #[tokio::test]
+    async fn axis_steps_come_back_to_zero() {
+        let vehicle = vehicle();
+        let mut app = App::new();
+        for _ in 0..7 {
+            press(&mut app, &vehicle, 'w');
+            press(&mut app, &vehicle, 'z');
+        }
+        assert_eq!(app.wrench.surge, 0.7);
+        assert_eq!(app.wrench.yaw, -0.7);
+        for _ in 0..7 {
+            press(&mut app, &vehicle, 's');
+            press(&mut app, &vehicle, 'c');
+        }
+        assert_eq!(app.wrench, Wrench::default());
+        for _ in 0..15 {
+            press(&mut app, &vehicle, 'f');
+        }
+        assert_eq!(app.wrench.heave, -1.0);
+        press(&mut app, &vehicle, ' ');
+        assert_eq!(app.wrench, Wrench::default());
+    }
+
+    #[tokio::test]
+    async fn mode_key_cycles_the_stabilisation() {
+        let vehicle = vehicle();
+        let mut app = App::new();
+        press(&mut app, &vehicle, 'g');
+        assert_eq!(app.notice, "stabilise Off");
+        for (current, next) in [
+            (StabiliseMode::Off, StabiliseMode::Level),
+            (StabiliseMode::Level, StabiliseMode::Attitude),
+            (StabiliseMode::Attitude, StabiliseMode::Rate),
+            (StabiliseMode::Rate, StabiliseMode::Off),
+        ] {
+            app.update(Telemetry {
+                stabilise: current,
+                ..Telemetry::default()
+            });
+            press(&mut app, &vehicle, 'g');
+            assert_eq!(app.notice, format!("stabilise {:?}", next));
+        }
+    }
+
+    #[test]
+    fn update_keeps_the_servo_index_on_the_vehicle() {
+        let mut app = App::new();
+        app.servo = 4;
+        app.update(Telemetry {
+            servos: vec![servo("gripper", Some(10.0)), servo("camera", None)],
+            ..Telemetry::default()
+        });
+        assert_eq!(app.servo, 1);
+        assert_eq!(app.servo_targets, vec![Some(10.0), None]);
+        assert_eq!(app.servo_name(1), "camera");
+
+        app.update(Telemetry::default());
+        assert_eq!(app.servo, 0);
+        assert!(app.servo_targets.is_empty());
+        assert_eq!(app.servo_name(0), "b0");
+    }
+}
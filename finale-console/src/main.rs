@@ -0,0 +1,94 @@
+//! Keyboard pilot console for bench tests.
+//!
+//! `finale-console [HOST]` connects to the pilot port (12345) and the HTTP
+//! telemetry (8080) of the daemon on `HOST`, `rov.local` by default. Either
+//! address can be given in full with `--pilot ADDRESS` and `--http ADDRESS`.
+
+mod app;
+mod ui;
+
+use finale_client::{Connection, ControlGroup, Role, Subscription};
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use app::App;
+
+use std::env;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+const PILOT_PORT: u16 = 12345;
+const HTTP_PORT: u16 = 8080;
+
+/// Telemetry rate asked for, and how often the screen is redrawn.
+const RATE: f32 = 10.0;
+
+fn usage() -> String {
+    "usage: finale-console [HOST] [--pilot ADDRESS] [--http ADDRESS]".to_string()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut host = "rov.local".to_string();
+    let mut pilot = None;
+    let mut http = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pilot" => pilot = Some(args.next().ok_or_else(usage)?),
+            "--http" => http = Some(args.next().ok_or_else(usage)?),
+            "-h" | "--help" => {
+                println!("{}", usage());
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => return Err(usage().into()),
+            _ => host = arg,
+        }
+    }
+    let pilot = pilot.unwrap_or_else(|| format!("{}:{}", host, PILOT_PORT));
+    let http = http.unwrap_or_else(|| format!("{}:{}", host, HTTP_PORT));
+
+    let vehicle = Connection::open(pilot, Role::Pilot, ControlGroup::ALL.to_vec());
+    let mut telemetry = Subscription::open(http, RATE);
+
+    // Crossterm reads block, keep them off the runtime.
+    let (keys_tx, mut keys) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event {
+                if key.kind == KeyEventKind::Press && keys_tx.send(key).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let mut app = App::new();
+    let mut redraw = time::interval(Duration::from_secs_f32(1.0 / RATE));
+    let result = loop {
+        tokio::select! {
+            Some(key) = keys.recv() => {
+                app.key(key, &vehicle);
+                vehicle.pilot(app.wrench);
+            }
+            Some(sample) = telemetry.next() => app.update(sample),
+            _ = redraw.tick() => {}
+        }
+        if app.quit {
+            break Ok(());
+        }
+        app.status = vehicle.status();
+        if let Err(e) = terminal.draw(|frame| ui::draw(frame, &app)) {
+            break Err(e);
+        }
+    };
+    ratatui::restore();
+
+    // Leave the vehicle safe, the link timeout would stop it anyway.
+    vehicle.disarm();
+    time::sleep(Duration::from_millis(100)).await;
+    Ok(result?)
+}
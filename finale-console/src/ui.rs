@@ -0,0 +1,279 @@
+use finale_client::Severity;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph};
+use ratatui::Frame;
+
+use crate::app::{App, AXIS_KEYS, HELP};
+
+/// Width of the bars drawn for axes and thrusters.
+const BAR_WIDTH: usize = 21;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, lists, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(10),
+        Constraint::Length(8),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+    let [axes, servos, vehicle] = Layout::horizontal([
+        Constraint::Percentage(34),
+        Constraint::Percentage(30),
+        Constraint::Percentage(36),
+    ])
+    .areas(body);
+    let [alarms, events] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(lists);
+
+    draw_header(frame, app, header);
+    draw_axes(frame, app, axes);
+    draw_servos(frame, app, servos);
+    draw_vehicle(frame, app, vehicle);
+    draw_alarms(frame, app, alarms);
+    draw_events(frame, app, events);
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(app.notice.as_str()),
+            Line::styled(HELP, Style::new().fg(Color::DarkGray)),
+        ]),
+        footer,
+    );
+}
+
+fn badge(text: String, color: Color) -> Span<'static> {
+    Span::styled(
+        format!(" {} ", text),
+        Style::new().fg(Color::Black).bg(color),
+    )
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let status = &app.status;
+    let mut spans = vec![if status.connected {
+        badge("connected".to_string(), Color::Green)
+    } else {
+        badge(
+            status
+                .error
+                .clone()
+                .unwrap_or_else(|| "connecting".to_string()),
+            Color::Yellow,
+        )
+    }];
+    if let Some(control) = &status.control {
+        let groups: Vec<String> = control
+            .groups
+            .iter()
+            .map(|group| format!("{:?}", group).to_lowercase())
+            .collect();
+        spans.push(Span::raw(format!(
+            " {:?} [{}]",
+            control.role,
+            groups.join(",")
+        )));
+    }
+    let round_trip = &status.round_trip;
+    spans.push(Span::raw(format!(
+        " rtt {:.0}ms jitter {:.1}ms loss {:.1}%/{:.1}% ",
+        round_trip.rtt_ms,
+        round_trip.jitter_ms,
+        round_trip.uplink_loss * 100.0,
+        round_trip.downlink_loss * 100.0
+    )));
+    match &app.telemetry {
+        Some(telemetry) => {
+            spans.push(if telemetry.armed {
+                badge("ARMED".to_string(), Color::Red)
+            } else {
+                badge("disarmed".to_string(), Color::Gray)
+            });
+            let mode = match telemetry.tuning {
+                Some(axis) => format!("tuning {:?}", axis),
+                None => format!("stabilise {:?}", telemetry.stabilise),
+            };
+            let gear = telemetry
+                .gears
+                .get(telemetry.gear)
+                .map_or("-".to_string(), |scale| format!("{:.0}%", scale * 100.0));
+            spans.push(Span::raw(format!(
+                " {} gear {} t={:.1}s",
+                mode, gear, telemetry.time
+            )));
+        }
+        None => spans.push(badge("no telemetry".to_string(), Color::Yellow)),
+    }
+    frame.render_widget(Line::from(spans), area);
+}
+
+/// A bar centred on zero for a value in [-1, 1].
+fn bar(value: f32) -> String {
+    let half = BAR_WIDTH / 2;
+    let filled = (value.abs().min(1.0) * half as f32).round() as usize;
+    let mut bar: Vec<char> = vec![' '; BAR_WIDTH];
+    bar[half] = '|';
+    for i in 1..=filled {
+        if value > 0.0 {
+            bar[half + i] = '#';
+        } else {
+            bar[half - i] = '#';
+        }
+    }
+    bar.into_iter().collect()
+}
+
+fn draw_axes(frame: &mut Frame, app: &App, area: Rect) {
+    let lines: Vec<Line> = AXIS_KEYS
+        .iter()
+        .zip(app.wrench.as_array())
+        .map(|((up, down, name), value)| {
+            Line::from(format!(
+                "{} {}  {:<6}[{}] {:+.1}",
+                down,
+                up,
+                name,
+                bar(value),
+                value
+            ))
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Thrust (keys)")),
+        area,
+    );
+}
+
+fn draw_servos(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = (0..app.servo_targets.len())
+        .map(|index| {
+            let servo = app.telemetry.as_ref().and_then(|t| t.servos.get(index));
+            let angle = servo
+                .and_then(|servo| servo.angle)
+                .map_or("-".to_string(), |angle| format!("{:.0}°", angle));
+            let target = app.servo_targets[index]
+                .map_or("-".to_string(), |target| format!("{:.0}°", target));
+            let holding = if servo.is_some_and(|servo| servo.backed_off) {
+                " hold"
+            } else {
+                ""
+            };
+            let text = format!(
+                "{} {:<8} {:>5} -> {:>5}{}",
+                index + 1,
+                app.servo_name(index),
+                angle,
+                target,
+                holding
+            );
+            let style = if index == app.servo {
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::new()
+            };
+            ListItem::new(text).style(style)
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Servos")),
+        area,
+    );
+}
+
+fn optional(value: Option<f32>, digits: usize, unit: &str) -> String {
+    value.map_or("-".to_string(), |value| {
+        format!("{:.*}{}", digits, value, unit)
+    })
+}
+
+fn draw_vehicle(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::bordered().title("Vehicle");
+    let telemetry = match &app.telemetry {
+        Some(telemetry) => telemetry,
+        None => {
+            frame.render_widget(Paragraph::new("waiting for telemetry").block(block), area);
+            return;
+        }
+    };
+    let attitude = &telemetry.attitude;
+    let mut lines = vec![
+        Line::from(format!(
+            "roll {:+.1}°  pitch {:+.1}°  heading {:.0}°",
+            attitude.roll.to_degrees(),
+            attitude.pitch.to_degrees(),
+            attitude.yaw.to_degrees()
+        )),
+        Line::from(format!(
+            "battery {}  {}  {:.0}mAh",
+            optional(telemetry.power.voltage, 2, "V"),
+            optional(telemetry.power.current, 1, "A"),
+            telemetry.power.consumed_mah
+        )),
+        Line::from(format!(
+            "tube {} {}  thrust limit {:.0}%",
+            optional(telemetry.thermal.tube_temperature, 1, "°C"),
+            optional(telemetry.thermal.humidity, 0, "%"),
+            telemetry
+                .power
+                .thrust_limit
+                .min(telemetry.thermal.thrust_limit)
+                * 100.0
+        )),
+    ];
+    for thruster in &telemetry.thrusters {
+        lines.push(Line::from(format!(
+            "{:<8}[{}] {:+.2}",
+            thruster.name,
+            bar(thruster.output),
+            thruster.output
+        )));
+    }
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn draw_alarms(frame: &mut Frame, app: &App, area: Rect) {
+    let alarms = app
+        .telemetry
+        .as_ref()
+        .map_or(&[][..], |t| t.alarms.as_slice());
+    let items: Vec<ListItem> = if alarms.is_empty() {
+        vec![ListItem::new("none").style(Style::new().fg(Color::DarkGray))]
+    } else {
+        alarms
+            .iter()
+            .map(|alarm| {
+                let color = match alarm.severity {
+                    Severity::Warning => Color::Yellow,
+                    Severity::Critical => Color::Red,
+                };
+                ListItem::new(Line::from(vec![
+                    badge(alarm.id.clone(), color),
+                    Span::raw(format!(" {}", alarm.message)),
+                ]))
+            })
+            .collect()
+    };
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Alarms")),
+        area,
+    );
+}
+
+fn draw_events(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .telemetry
+        .iter()
+        .flat_map(|t| t.events.iter().rev())
+        .take(area.height.saturating_sub(2) as usize)
+        .map(|event| {
+            ListItem::new(format!(
+                "{:>7.1}s {}: {}",
+                event.time, event.source, event.message
+            ))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Events")),
+        area,
+    );
+}
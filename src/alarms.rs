@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+/// A condition the pilot must know about.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alarm {
+    /// Stable name, e.g. `leak`, used to raise and clear it.
+    pub id: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Alarms currently active on the vehicle.
+#[derive(Debug, Clone, Default)]
+pub struct Alarms {
+    active: Vec<Alarm>,
+}
+
+impl Alarms {
+    pub fn active(&self) -> &[Alarm] {
+        &self.active
+    }
+
+    pub fn is_active(&self, id: &str) -> bool {
+        self.active.iter().any(|alarm| alarm.id == id)
+    }
+
+    /// Raises or updates an alarm, returns true if it was not active.
+    pub fn raise(&mut self, id: &str, severity: Severity, message: String) -> bool {
+        if let Some(alarm) = self.active.iter_mut().find(|alarm| alarm.id == id) {
+            alarm.severity = severity;
+            alarm.message = message;
+            return false;
+        }
+        println!("alarm {:?} {}: {}", severity, id, message);
+        self.active.push(Alarm {
+            id: id.to_string(),
+            severity,
+            message,
+        });
+        true
+    }
+
+    /// Clears an alarm, returns true if it was active.
+    pub fn clear(&mut self, id: &str) -> bool {
+        let before = self.active.len();
+        self.active.retain(|alarm| alarm.id != id);
+        let cleared = self.active.len() != before;
+        if cleared {
+            println!("alarm {} cleared", id);
+        }
+        cleared
+    }
+}
+
+/// Severity of a reading that is bad when high, against its warning and
+/// critical thresholds. A severity that is active holds until the reading is
+/// `hysteresis` below its threshold, so a reading hovering at a threshold
+/// does not flap the alarm. Readings that are bad when low are negated.
+pub fn severity(
+    active: Option<Severity>,
+    value: f32,
+    warn: f32,
+    critical: f32,
+    hysteresis: f32,
+) -> Option<Severity> {
+    let holds = |severity, threshold: f32| {
+        value >= threshold || (active >= Some(severity) && value > threshold - hysteresis)
+    };
+    if holds(Severity::Critical, critical) {
+        Some(Severity::Critical)
+    } else if holds(Severity::Warning, warn) {
+        Some(Severity::Warning)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_holds_within_the_hysteresis() {
+        let level = |active, value| severity(active, value, 50.0, 65.0, 2.0);
+        assert_eq!(level(None, 49.0), None);
+        assert_eq!(level(None, 50.0), Some(Severity::Warning));
+        assert_eq!(
+            level(Some(Severity::Warning), 48.5),
+            Some(Severity::Warning)
+        );
+        assert_eq!(level(Some(Severity::Warning), 48.0), None);
+        assert_eq!(
+            level(Some(Severity::Warning), 70.0),
+            Some(Severity::Critical)
+        );
+        assert_eq!(
+            level(Some(Severity::Critical), 63.5),
+            Some(Severity::Critical)
+        );
+        assert_eq!(
+            level(Some(Severity::Critical), 60.0),
+            Some(Severity::Warning)
+        );
+        assert_eq!(
+            level(Some(Severity::Critical), 48.5),
+            Some(Severity::Warning)
+        );
+        assert_eq!(level(Some(Severity::Critical), 40.0), None);
+    }
+}
@@ -0,0 +1,343 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AutoTuneConfig, PidConfig};
+
+use std::f32::consts::PI;
+
+/// Loop that can be auto-tuned.
+///
+/// Depth is missing on purpose. The vehicle has no pressure sensor and no
+/// depth hold, so there is no depth loop to oscillate. A depth axis belongs
+/// here once both exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TuneAxis {
+    Roll,
+    Pitch,
+    Yaw,
+}
+
+impl TuneAxis {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            TuneAxis::Roll => 0,
+            TuneAxis::Pitch => 1,
+            TuneAxis::Yaw => 2,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<TuneAxis> {
+        match value {
+            0 => Some(TuneAxis::Roll),
+            1 => Some(TuneAxis::Pitch),
+            2 => Some(TuneAxis::Yaw),
+            _ => None,
+        }
+    }
+}
+
+/// What the relay experiment found.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TuneResult {
+    pub ultimate_gain: f32,
+    /// Period of the oscillation in seconds.
+    pub ultimate_period: f32,
+    /// Gains from the Ziegler-Nichols rules, limits and filter are kept from
+    /// the loop being tuned.
+    pub proposed: PidConfig,
+}
+
+pub enum TuneStep {
+    /// Still oscillating, apply this output on the axis.
+    Running(f32),
+    Done(TuneResult),
+    Failed(String),
+}
+
+/// Relay feedback experiment (Åström-Hägglund).
+///
+/// The axis output is switched between `+amplitude` and `-amplitude` whenever
+/// the measurement crosses the setpoint (with some hysteresis). The loop then
+/// settles into a limit cycle whose period is the ultimate period and whose
+/// amplitude gives the ultimate gain, from which PID gains are derived.
+pub struct AutoTune {
+    axis: TuneAxis,
+    config: AutoTuneConfig,
+    current: PidConfig,
+    output: f32,
+    elapsed: f32,
+    last_switch: Option<f32>,
+    max: f32,
+    min: f32,
+    periods: Vec<f32>,
+    amplitudes: Vec<f32>,
+}
+
+impl AutoTune {
+    /// `current` are the gains in use, the proposal keeps their limits.
+    /// Fails if the configuration cannot give a result.
+    pub fn new(axis: TuneAxis, config: AutoTuneConfig, current: PidConfig) -> Result<Self, String> {
+        if config.cycles < 1 {
+            return Err("auto-tune needs at least one cycle to measure".to_string());
+        }
+        if !(config.amplitude > 0.0 && config.amplitude.is_finite()) {
+            return Err(format!(
+                "auto-tune amplitude {} is not positive",
+                config.amplitude
+            ));
+        }
+        Ok(AutoTune {
+            axis,
+            output: config.amplitude,
+            config,
+            current,
+            elapsed: 0.0,
+            last_switch: None,
+            max: f32::MIN,
+            min: f32::MAX,
+            periods: Vec::new(),
+            amplitudes: Vec::new(),
+        })
+    }
+
+    pub fn axis(&self) -> TuneAxis {
+        self.axis
+    }
+
+    /// Number of full oscillations measured so far.
+    pub fn cycles(&self) -> usize {
+        self.periods.len()
+    }
+
+    /// `offset` is measurement minus setpoint.
+    pub fn update(&mut self, offset: f32, dt: f32) -> TuneStep {
+        self.elapsed += dt;
+        if offset.abs() > self.config.max_offset {
+            return TuneStep::Failed(format!(
+                "oscillation reached {:.3}, limit is {:.3}",
+                offset, self.config.max_offset
+            ));
+        }
+        if self.elapsed > self.config.timeout_s {
+            return TuneStep::Failed(format!(
+                "no steady oscillation after {:.0}s",
+                self.config.timeout_s
+            ));
+        }
+
+        self.max = self.max.max(offset);
+        self.min = self.min.min(offset);
+
+        let hysteresis = self.config.hysteresis;
+        if self.output < 0.0 && offset < -hysteresis {
+            // One full cycle ends each time the relay switches back up.
+            self.output = self.config.amplitude;
+            if let Some(last) = self.last_switch {
+                self.periods.push(self.elapsed - last);
+                self.amplitudes.push((self.max - self.min) / 2.0);
+            }
+            self.last_switch = Some(self.elapsed);
+            self.max = f32::MIN;
+            self.min = f32::MAX;
+        } else if self.output > 0.0 && offset > hysteresis {
+            self.output = -self.config.amplitude;
+        }
+
+        // The first cycle is still settling, it is not used.
+        if self.periods.len() > self.config.cycles.max(1) {
+            return match self.result() {
+                Ok(result) => TuneStep::Done(result),
+                Err(reason) => TuneStep::Failed(reason),
+            };
+        }
+        TuneStep::Running(self.output)
+    }
+
+    /// Gains from the measured cycles, refused unless they are finite, as
+    /// happens when the oscillation is too small to measure.
+    fn result(&self) -> Result<TuneResult, String> {
+        let used = &self.periods[1..];
+        let period = used.iter().sum::<f32>() / used.len() as f32;
+        let amplitudes = &self.amplitudes[1..];
+        let amplitude = amplitudes.iter().sum::<f32>() / amplitudes.len() as f32;
+
+        let h = self.config.hysteresis.min(amplitude * 0.99);
+        let ultimate_gain =
+            4.0 * self.config.amplitude / (PI * (amplitude * amplitude - h * h).sqrt());
+
+        let result = TuneResult {
+            ultimate_gain,
+            ultimate_period: period,
+            proposed: PidConfig {
+                kp: 0.6 * ultimate_gain,
+                ki: 1.2 * ultimate_gain / period,
+                kd: 0.075 * ultimate_gain * period,
+                ..self.current
+            },
+        };
+        let proposed = &result.proposed;
+        if [ultimate_gain, period, proposed.kp, proposed.ki, proposed.kd]
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            Ok(result)
+        } else {
+            Err(format!(
+                "no usable result from an oscillation of {:.3} over {:.2}s",
+                amplitude, period
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    /// Dead time of the plant in [`run`], in seconds.
+    const DELAY: f32 = 0.1;
+
+    fn config(cycles: usize, hysteresis: f32) -> AutoTuneConfig {
+        AutoTuneConfig {
+            cycles,
+            hysteresis,
+            ..AutoTuneConfig::default()
+        }
+    }
+
+    /// Runs the relay on an integrator with a delay, e^(-DELAY s) / s, the
+    /// simplest plant with a limit cycle.
+    fn run(autotune: &mut AutoTune) -> TuneStep {
+        let delay = (DELAY / DT).round() as usize;
+        // The output of a cycle first moves the offset `delay` cycles later.
+        let mut outputs = vec![0.0; delay + 1];
+        let mut offset = 0.0;
+        loop {
+            offset += outputs[outputs.len() - delay - 1] * DT;
+            match autotune.update(offset, DT) {
+                TuneStep::Running(output) => outputs.push(output),
+                done => return done,
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_configurations_without_a_result() {
+        let current = PidConfig::default();
+        assert!(AutoTune::new(TuneAxis::Roll, config(0, 0.02), current).is_err());
+        let still = AutoTuneConfig {
+            amplitude: 0.0,
+            ..AutoTuneConfig::default()
+        };
+        assert!(AutoTune::new(TuneAxis::Roll, still, current).is_err());
+    }
+
+    fn tune(hysteresis: f32, current: PidConfig) -> TuneResult {
+        let mut autotune = AutoTune::new(TuneAxis::Pitch, config(3, hysteresis), current).unwrap();
+        let result = match run(&mut autotune) {
+            TuneStep::Done(result) => result,
+            TuneStep::Failed(reason) => panic!("failed: {}", reason),
+            TuneStep::Running(_) => unreachable!(),
+        };
+        assert_eq!(autotune.cycles(), 4);
+        result
+    }
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32, what: &str) {
+        assert!(
+            (value - expected).abs() <= tolerance * expected,
+            "{} {} instead of {}",
+            what,
+            value,
+            expected
+        );
+    }
+
+    /// Checks the cycle the relay drives the plant of [`run`] into, with
+    /// relay amplitude d and hysteresis h. The integrator ramps at d and
+    /// carries on for the delay L after each switch: a triangle of amplitude
+    /// h + d L and period 4 L + 4 h / d. Switches come up to a cycle late, as
+    /// if L were up to `DT` longer.
+    fn assert_relay_cycle(result: &TuneResult, hysteresis: f32) {
+        let d = AutoTuneConfig::default().amplitude;
+        let gain = |delay: f32| {
+            let amplitude = hysteresis + d * delay;
+            4.0 * d / (PI * (amplitude * amplitude - hysteresis * hysteresis).sqrt())
+        };
+        let period = |delay: f32| 4.0 * delay + 4.0 * hysteresis / d;
+        let late = DELAY + DT;
+        assert!(
+            (period(DELAY) - 1e-4..=period(late) + 1e-4).contains(&result.ultimate_period),
+            "period {} outside {} to {}",
+            result.ultimate_period,
+            period(DELAY),
+            period(late)
+        );
+        assert!(
+            (gain(late)..=gain(DELAY)).contains(&result.ultimate_gain),
+            "gain {} outside {} to {}",
+            result.ultimate_gain,
+            gain(late),
+            gain(DELAY)
+        );
+    }
+
+    #[test]
+    fn measures_a_limit_cycle() {
+        let current = PidConfig {
+            output_max: 0.7,
+            ..PidConfig::default()
+        };
+        let result = tune(0.01, current);
+        assert_relay_cycle(&result, 0.01);
+
+        let proposed = result.proposed;
+        assert_near(proposed.kp, 0.6 * result.ultimate_gain, 1e-6, "kp");
+        assert_near(
+            proposed.ki,
+            1.2 * result.ultimate_gain / result.ultimate_period,
+            1e-6,
+            "ki",
+        );
+        assert_near(
+            proposed.kd,
+            0.075 * result.ultimate_gain * result.ultimate_period,
+            1e-6,
+            "kd",
+        );
+        assert_eq!(proposed.output_max, 0.7);
+    }
+
+    #[test]
+    fn finds_the_phase_crossover_without_hysteresis() {
+        let result = tune(0.0, PidConfig::default());
+        assert_relay_cycle(&result, 0.0);
+        // e^(-L s) / s is at -180 degrees at w = pi / (2 L), so Tu = 4 L and
+        // Ku = pi / (2 L). The describing function of the triangle it
+        // oscillates with gives 4 / (pi L), the usual 20% short.
+        assert_near(result.ultimate_period, 4.0 * DELAY, 0.05, "period");
+        let exact = PI / (2.0 * DELAY);
+        assert!(result.ultimate_gain < exact && result.ultimate_gain > 0.75 * exact);
+    }
+
+    #[test]
+    fn refuses_non_finite_gains() {
+        let mut autotune =
+            AutoTune::new(TuneAxis::Yaw, config(1, 0.0), PidConfig::default()).unwrap();
+        // Crossings too small to measure: the amplitude squared is zero.
+        let mut step = TuneStep::Running(0.0);
+        for i in 0..1000 {
+            let offset = if i % 4 < 2 { 1e-30 } else { -1e-30 };
+            step = autotune.update(offset, DT);
+            if !matches!(step, TuneStep::Running(_)) {
+                break;
+            }
+        }
+        match step {
+            TuneStep::Failed(reason) => assert!(reason.starts_with("no usable result")),
+            _ => panic!("expected a failure"),
+        }
+    }
+}
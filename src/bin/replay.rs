@@ -0,0 +1,167 @@
+use finale::config::Config;
+use finale::recorder::{self, LogReader, Record};
+use finale::replay::{Diff, Overrides, Replay};
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+fn usage() -> String {
+    "usage: finale-replay [--config FILE] [--set PATH=VALUE]... [--csv FILE] LOG|DIR...".to_string()
+}
+
+/// Runs the control pipeline again on flight logs, with changed parameters
+/// if asked, and reports how the outputs differ from the recorded ones.
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut overrides = Overrides::default();
+    let mut csv = None;
+    let mut logs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args.next().ok_or_else(usage)?;
+                overrides.config = Some(Config::load(path)?);
+            }
+            "--set" => {
+                let set = args.next().ok_or_else(usage)?;
+                overrides.set.push(Overrides::parse_set(&set)?);
+            }
+            "--csv" => csv = Some(args.next().ok_or_else(usage)?),
+            _ if arg.starts_with('-') => return Err(usage().into()),
+            _ if Path::new(&arg).is_dir() => logs.extend(recorder::list(Path::new(&arg))?),
+            _ => logs.push(arg.into()),
+        }
+    }
+    if logs.is_empty() {
+        return Err(usage().into());
+    }
+
+    let mut csv = match csv {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut names: Vec<String> = Vec::new();
+    // Sessions in the logs may have different thrusters, each one gets a
+    // header of its own.
+    let mut session = None;
+    let mut header_session = None;
+    let mut replay = Replay::new(overrides);
+    let mut diff = Diff::default();
+    let mut skipped = 0;
+    let mut span: Option<(f64, f64)> = None;
+    for path in &logs {
+        let mut reader = LogReader::open(path)?;
+        for record in reader.by_ref() {
+            if let Record::Start(start) = &record {
+                session = Some(start.unix_ms);
+                names = start
+                    .config()?
+                    .thrusters
+                    .into_iter()
+                    .map(|t| t.name)
+                    .collect();
+            }
+            let step = match replay.feed(record)? {
+                Some(step) => step,
+                None => continue,
+            };
+            diff.add(&step);
+            let time = step.recorded.time;
+            span = Some(span.map_or((time, time), |(first, _)| (first, time)));
+            if let Some(csv) = csv.as_mut() {
+                if header_session != session {
+                    header_session = session;
+                    let mut header = vec!["time".to_string()];
+                    for name in &names {
+                        header.push(format!("{}_recorded", name));
+                        header.push(format!("{}_replayed", name));
+                    }
+                    for axis in ["roll", "pitch", "yaw"] {
+                        header.push(format!("{}_recorded", axis));
+                        header.push(format!("{}_replayed", axis));
+                    }
+                    writeln!(csv, "{}", header.join(","))?;
+                }
+                let mut row = vec![format!("{:.3}", time)];
+                for (old, new) in step
+                    .recorded
+                    .outputs
+                    .thrusters
+                    .iter()
+                    .zip(&step.outputs.thrusters)
+                {
+                    row.push(old.to_string());
+                    row.push(new.to_string());
+                }
+                let old = &step.recorded.attitude;
+                let new = &step.attitude;
+                for (old, new) in [
+                    (old.roll, new.roll),
+                    (old.pitch, new.pitch),
+                    (old.yaw, new.yaw),
+                ] {
+                    row.push(format!("{:.3}", old.to_degrees()));
+                    row.push(format!("{:.3}", new.to_degrees()));
+                }
+                writeln!(csv, "{}", row.join(","))?;
+            }
+        }
+        skipped += reader.skipped();
+    }
+    if let Some(csv) = csv.as_mut() {
+        csv.flush()?;
+    }
+
+    let (first, last) = span.ok_or("no control cycles in the logs")?;
+    println!(
+        "replayed {} cycles from {:.1}s to {:.1}s of {} logs, {} damaged bytes skipped",
+        diff.cycles,
+        first,
+        last,
+        logs.len(),
+        skipped
+    );
+    if diff.gaps > 0 {
+        println!(
+            "{} gaps where cycles were not recorded, expect differences after them",
+            diff.gaps
+        );
+    }
+    match diff.first_difference {
+        Some(time) => println!(
+            "thruster pulses differ in {} cycles, first at {:.2}s",
+            diff.differing, time
+        ),
+        None => println!("thruster pulses match the recording"),
+    }
+    for (index, spread) in diff.thrusters.iter().enumerate() {
+        let name = names.get(index).map_or("?", String::as_str);
+        println!(
+            "  {:<22} rms {:6.2}  max {:4.0}",
+            name,
+            spread.rms(),
+            spread.max()
+        );
+    }
+    let axes = ["surge", "sway", "heave", "roll", "pitch", "yaw"];
+    for (axis, spread) in axes.iter().zip(&diff.wrench) {
+        println!(
+            "  {:<22} rms {:6.3}  max {:6.3}",
+            format!("{} command", axis),
+            spread.rms(),
+            spread.max()
+        );
+    }
+    for (axis, spread) in ["roll", "pitch", "yaw"].iter().zip(&diff.attitude) {
+        println!(
+            "  {:<22} rms {:6.3}° max {:6.3}°",
+            axis,
+            spread.rms().to_degrees(),
+            spread.max().to_degrees()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,809 @@
+use serde::{Deserialize, Serialize};
+
+use crate::leak::LeakResponse;
+use crate::lights::LightDriver;
+use crate::servo::ServoProfile;
+use crate::stabilise::StabiliseMode;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Vehicle configuration, read once at startup from a JSON file.
+///
+/// Every field has a default matching the values that used to be hard coded in
+/// the daemon, so a missing file or a partial file is fine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address the pilot TCP listener binds to.
+    pub listen: String,
+    /// Address the pilot UDP socket binds to, `None` to disable it.
+    pub udp_listen: Option<String>,
+    /// Address of the HTTP telemetry server, `None` to disable it.
+    pub http_listen: Option<String>,
+    /// I2C bus shared by the PCA9685 and the MPU6050.
+    pub i2c_bus: String,
+    /// PCA9685 prescale value, 127 is roughly 50Hz.
+    pub pwm_prescale: u8,
+    /// Control loop frequency in Hz.
+    pub loop_hz: f32,
+    /// Time without a pilot packet after which the thrusters go neutral.
+    pub link_timeout_ms: u64,
+    /// Time the ESCs are held at neutral on startup so they arm.
+    pub esc_arm_ms: u64,
+    /// Whether thrusters respond as soon as the daemon is up. Legacy clients
+    /// cannot send the arm command, so this defaults to true.
+    pub arm_on_start: bool,
+    pub imu: ImuConfig,
+    pub thrusters: Vec<ThrusterConfig>,
+    pub shaping: ShapingConfig,
+    pub heading: HeadingConfig,
+    pub stabilise: StabiliseConfig,
+    pub autotune: AutoTuneConfig,
+    pub leak: LeakConfig,
+    /// ADC on the shared I2C bus, absent by default.
+    pub ads1115: Option<Ads1115Config>,
+    pub power: PowerConfig,
+    pub power_budget: PowerBudgetConfig,
+    pub thermal: ThermalConfig,
+    /// Lights on spare PCA9685 channels, none by default.
+    pub lights: Vec<LightConfig>,
+    /// Servos in the order of the legacy packet fields b0..b4.
+    pub servos: Vec<ServoConfig>,
+    pub servo_presets: Vec<ServoPresetConfig>,
+    pub recorder: RecorderConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "0.0.0.0:12345".to_string(),
+            udp_listen: None,
+            http_listen: Some("0.0.0.0:8080".to_string()),
+            i2c_bus: "/dev/i2c-1".to_string(),
+            pwm_prescale: 127,
+            loop_hz: 50.0,
+            link_timeout_ms: 500,
+            esc_arm_ms: 5000,
+            arm_on_start: true,
+            imu: ImuConfig::default(),
+            thrusters: ThrusterConfig::defaults(),
+            shaping: ShapingConfig::default(),
+            heading: HeadingConfig::default(),
+            stabilise: StabiliseConfig::default(),
+            autotune: AutoTuneConfig::default(),
+            leak: LeakConfig::default(),
+            ads1115: None,
+            power: PowerConfig::default(),
+            power_budget: PowerBudgetConfig::default(),
+            thermal: ThermalConfig::default(),
+            lights: Vec::new(),
+            servos: ServoConfig::defaults(),
+            servo_presets: Vec::new(),
+            recorder: RecorderConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration at `path`, falling back to the defaults when the
+    /// file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn Error>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            println!("no config at {}, using defaults", path.display());
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(path)?;
+        let mut config: Config = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        if let Some(angle_pid) = config.stabilise.angle_pid.take() {
+            println!("stabilise.angle_pid is now roll_pid and pitch_pid");
+            config.stabilise.roll_pid = angle_pid;
+            config.stabilise.pitch_pid = angle_pid;
+        }
+        Ok(config)
+    }
+
+    /// Writes the configuration back, used to persist tuned parameters.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = serde_json::to_string_pretty(self)?;
+        // Write next to the file and rename so a crash never leaves half a file.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImuConfig {
+    /// How long the vehicle must sit still at startup to measure gyro bias and
+    /// level offsets.
+    pub calibration_ms: u64,
+    /// Weight of the integrated gyro in the roll/pitch complementary filter.
+    pub gyro_weight: f32,
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        ImuConfig {
+            calibration_ms: 10000,
+            gyro_weight: 0.98,
+        }
+    }
+}
+
+/// One thruster on the PCA9685.
+///
+/// `mix` holds the pulse offset produced by a full command on each wrench
+/// axis, in the order surge, sway, heave, roll, pitch, yaw.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrusterConfig {
+    pub name: String,
+    pub channel: u8,
+    pub mix: [f32; 6],
+    #[serde(default = "default_neutral")]
+    pub neutral: u16,
+    /// Largest offset from neutral the thruster may be driven to.
+    #[serde(default = "default_max_offset")]
+    pub max_offset: f32,
+}
+
+fn default_neutral() -> u16 {
+    307
+}
+
+fn default_max_offset() -> f32 {
+    100.0
+}
+
+impl ThrusterConfig {
+    fn new(name: &str, channel: u8, mix: [f32; 6]) -> Self {
+        ThrusterConfig {
+            name: name.to_string(),
+            channel,
+            mix,
+            neutral: default_neutral(),
+            max_offset: default_max_offset(),
+        }
+    }
+
+    /// The six thruster layout of the vehicle, with the gains that were tuned
+    /// in the pool.
+    pub fn defaults() -> Vec<ThrusterConfig> {
+        vec![
+            ThrusterConfig::new("front_right", 2, [-60.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            ThrusterConfig::new("front_left", 3, [-60.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            ThrusterConfig::new("rear", 1, [0.0, 0.0, 0.0, 0.0, 0.0, 60.0]),
+            ThrusterConfig::new("vertical_front_left", 4, [0.0, 0.0, -25.0, 20.0, 20.0, 0.0]),
+            ThrusterConfig::new(
+                "vertical_front_right",
+                0,
+                [0.0, 0.0, -25.0, -20.0, 20.0, 0.0],
+            ),
+            ThrusterConfig::new("vertical_rear", 5, [0.0, 0.0, -50.0, 0.0, -30.0, 0.0]),
+        ]
+    }
+}
+
+/// Response of one pilot stick axis.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisShapingConfig {
+    /// Stick values smaller than this read as zero, the rest of the travel is
+    /// rescaled to start from zero.
+    pub deadzone: f32,
+    /// Blend between a linear (0) and a cubic (1) response.
+    pub expo: f32,
+    /// Largest change of the shaped value per second, `None` for no limit.
+    pub max_rate: Option<f32>,
+}
+
+impl Default for AxisShapingConfig {
+    fn default() -> Self {
+        AxisShapingConfig {
+            deadzone: 0.0,
+            expo: 0.0,
+            max_rate: None,
+        }
+    }
+}
+
+/// Shaping of the pilot wrench before it reaches the controllers, done on
+/// the vehicle so every client gets the same feel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShapingConfig {
+    pub surge: AxisShapingConfig,
+    pub sway: AxisShapingConfig,
+    pub heave: AxisShapingConfig,
+    pub roll: AxisShapingConfig,
+    pub pitch: AxisShapingConfig,
+    pub yaw: AxisShapingConfig,
+    /// Scales of the whole wrench the pilot can switch between.
+    pub gears: Vec<f32>,
+    /// Index of the gear selected at startup.
+    pub gear: usize,
+}
+
+impl ShapingConfig {
+    /// Axis settings in the order of [`crate::mixer::Wrench::as_array`].
+    pub fn axes(&self) -> [AxisShapingConfig; 6] {
+        [
+            self.surge, self.sway, self.heave, self.roll, self.pitch, self.yaw,
+        ]
+    }
+}
+
+impl Default for ShapingConfig {
+    fn default() -> Self {
+        ShapingConfig {
+            surge: AxisShapingConfig::default(),
+            sway: AxisShapingConfig::default(),
+            heave: AxisShapingConfig::default(),
+            roll: AxisShapingConfig::default(),
+            pitch: AxisShapingConfig::default(),
+            yaw: AxisShapingConfig::default(),
+            gears: vec![0.25, 0.5, 1.0],
+            gear: 2,
+        }
+    }
+}
+
+/// Gains and limits of a [`crate::pid::Pid`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Fraction of the setpoint seen by the proportional term, 1 is a plain
+    /// PID, lower values soften the reaction to setpoint steps.
+    pub setpoint_weight: f32,
+    /// Time constant of the derivative low-pass filter in seconds, 0 disables
+    /// it.
+    pub derivative_filter: f32,
+    pub output_min: f32,
+    pub output_max: f32,
+    /// How fast the integrator is pulled back while the output is clamped, per
+    /// second.
+    pub tracking_gain: f32,
+}
+
+impl Default for PidConfig {
+    fn default() -> Self {
+        PidConfig {
+            kp: 1.0,
+            ki: 0.0,
+            kd: 0.0,
+            setpoint_weight: 1.0,
+            derivative_filter: 0.05,
+            output_min: -1.0,
+            output_max: 1.0,
+            tracking_gain: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadingConfig {
+    /// Off by default, clients that turn without the yaw axis would have to
+    /// fight the hold.
+    pub enabled: bool,
+    /// Yaw stick values smaller than this are treated as centred.
+    pub deadband: f32,
+    /// Loop on the heading error in radians, output is the yaw command.
+    pub pid: PidConfig,
+    /// Largest change of the yaw command per second.
+    pub max_rate: f32,
+}
+
+impl Default for HeadingConfig {
+    fn default() -> Self {
+        HeadingConfig {
+            enabled: false,
+            deadband: 0.05,
+            pid: PidConfig {
+                kp: 1.0,
+                kd: 0.2,
+                output_min: -0.5,
+                output_max: 0.5,
+                ..PidConfig::default()
+            },
+            max_rate: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StabiliseConfig {
+    /// Mode at startup, the pilot can change it afterwards.
+    pub mode: StabiliseMode,
+    /// Roll angle loop in radians, output is the roll torque.
+    pub roll_pid: PidConfig,
+    /// Pitch angle loop in radians, output is the pitch torque.
+    pub pitch_pid: PidConfig,
+    /// Rate loop in rad/s, output is the roll or pitch torque.
+    pub rate_pid: PidConfig,
+    /// Share of the pilot stick added to the loop output.
+    pub pilot_feed_forward: f32,
+    /// Stick values smaller than this leave the attitude target alone.
+    pub deadband: f32,
+    /// Largest target angle in attitude mode, in radians.
+    pub max_angle: f32,
+    /// Rate commanded by a full stick, in rad/s.
+    pub max_rate: f32,
+    /// Shared roll and pitch loop of older files, moved into `roll_pid` and
+    /// `pitch_pid` on load.
+    #[serde(skip_serializing)]
+    pub angle_pid: Option<PidConfig>,
+}
+
+impl Default for StabiliseConfig {
+    fn default() -> Self {
+        StabiliseConfig {
+            mode: StabiliseMode::default(),
+            roll_pid: PidConfig {
+                kp: 0.64,
+                ki: 0.064,
+                kd: 0.0064,
+                ..PidConfig::default()
+            },
+            pitch_pid: PidConfig {
+                kp: 0.64,
+                ki: 0.064,
+                kd: 0.0064,
+                ..PidConfig::default()
+            },
+            rate_pid: PidConfig {
+                kp: 0.3,
+                ki: 0.1,
+                ..PidConfig::default()
+            },
+            pilot_feed_forward: 1.0,
+            deadband: 0.05,
+            max_angle: 0.6,
+            max_rate: 0.8,
+            angle_pid: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoTuneConfig {
+    /// Relay output applied on the axis, either way.
+    pub amplitude: f32,
+    /// Offset from the setpoint the measurement must cross before the relay
+    /// switches, in the axis unit.
+    pub hysteresis: f32,
+    /// Oscillations averaged for the result, after one settling cycle.
+    pub cycles: usize,
+    /// The experiment is abandoned after this many seconds.
+    pub timeout_s: f32,
+    /// The experiment is abandoned if the measurement strays this far.
+    pub max_offset: f32,
+}
+
+impl Default for AutoTuneConfig {
+    fn default() -> Self {
+        AutoTuneConfig {
+            amplitude: 0.3,
+            hysteresis: 0.02,
+            cycles: 4,
+            timeout_s: 30.0,
+            max_offset: 0.5,
+        }
+    }
+}
+
+/// A leak sensor on a Raspberry Pi GPIO pin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeakSensorConfig {
+    pub name: String,
+    /// BCM pin number.
+    pub pin: u8,
+    /// Level of the pin when the sensor is wet. Active low sensors get the
+    /// internal pull-up, active high ones the pull-down.
+    #[serde(default = "default_active_high")]
+    pub active_high: bool,
+}
+
+fn default_active_high() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeakConfig {
+    pub sensors: Vec<LeakSensorConfig>,
+    /// Time a sensor must stay wet (or dry) before its state changes.
+    pub debounce_ms: u64,
+    pub response: LeakResponse,
+    /// Heave command applied by the surface response.
+    pub surface_heave: f32,
+}
+
+impl Default for LeakConfig {
+    fn default() -> Self {
+        LeakConfig {
+            sensors: Vec::new(),
+            debounce_ms: 200,
+            response: LeakResponse::default(),
+            surface_heave: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ads1115Config {
+    #[serde(default = "default_ads1115_address")]
+    pub address: u8,
+    pub channels: Vec<AdcChannelConfig>,
+}
+
+fn default_ads1115_address() -> u8 {
+    0x48
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdcChannelConfig {
+    pub name: String,
+    /// ADS1115 input, 0 to 3, measured against GND.
+    pub input: u8,
+    /// Multiplier from volts at the pin to the reported value, e.g. the
+    /// voltage divider ratio.
+    #[serde(default = "default_adc_scale")]
+    pub scale: f32,
+}
+
+fn default_adc_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ina219Config {
+    #[serde(default = "default_ina219_address")]
+    pub address: u8,
+    #[serde(default = "default_shunt_ohms")]
+    pub shunt_ohms: f32,
+}
+
+fn default_ina219_address() -> u8 {
+    0x40
+}
+
+fn default_shunt_ohms() -> f32 {
+    0.1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Current monitor on the battery line, absent by default.
+    pub ina219: Option<Ina219Config>,
+    /// ADS1115 channel giving the battery voltage when there is no INA219.
+    pub voltage_channel: Option<String>,
+    /// Time constant of the voltage filter in seconds, so thruster bursts do
+    /// not trip the thresholds.
+    pub voltage_filter_s: f32,
+    pub warn_voltage: f32,
+    pub critical_voltage: f32,
+    /// How far the voltage must recover above a threshold before its alarm
+    /// clears.
+    pub voltage_hysteresis: f32,
+    /// Below this voltage the thrust limit starts to come down.
+    pub limit_start_voltage: f32,
+    /// At and below this voltage the thrust limit is `min_thrust_limit`.
+    pub limit_end_voltage: f32,
+    pub min_thrust_limit: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        // Thresholds for a 4S lithium pack.
+        PowerConfig {
+            ina219: None,
+            voltage_channel: None,
+            voltage_filter_s: 1.0,
+            warn_voltage: 14.0,
+            critical_voltage: 13.2,
+            voltage_hysteresis: 0.2,
+            limit_start_voltage: 13.6,
+            limit_end_voltage: 12.8,
+            min_thrust_limit: 0.3,
+        }
+    }
+}
+
+/// Limit on the total current drawn by the thrusters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerBudgetConfig {
+    pub enabled: bool,
+    /// Current the tether supply can deliver to the thrusters, in amps.
+    pub max_current: f32,
+    /// Current of one thruster against its command, as (fraction of full
+    /// command, amps) points sorted by command, used for both directions.
+    pub current_curve: Vec<[f32; 2]>,
+    /// How long the thrust must stay within the budget before the alarm
+    /// clears, in seconds, so stick bursts do not flap it.
+    pub alarm_hold_s: f32,
+}
+
+impl Default for PowerBudgetConfig {
+    fn default() -> Self {
+        PowerBudgetConfig {
+            enabled: true,
+            max_current: 25.0,
+            current_curve: vec![
+                [0.0, 0.0],
+                [0.25, 0.6],
+                [0.5, 2.8],
+                [0.75, 7.0],
+                [1.0, 13.0],
+            ],
+            alarm_hold_s: 1.0,
+        }
+    }
+}
+
+impl PowerBudgetConfig {
+    /// Current of one thruster at `command` (0 to 1), interpolated on the
+    /// curve.
+    pub fn current(&self, command: f32) -> f32 {
+        let curve = &self.current_curve;
+        let (first, last) = match (curve.first(), curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if command <= first[0] {
+            return first[1];
+        }
+        for pair in curve.windows(2) {
+            let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+            if command <= x1 {
+                if x1 <= x0 {
+                    return y1;
+                }
+                return y0 + (command - x0) / (x1 - x0) * (y1 - y0);
+            }
+        }
+        last[1]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    /// I2C address of the SHT31 in the tube (0x44 or 0x45), absent by
+    /// default.
+    pub sht31_address: Option<u8>,
+    /// Above this temperature the pilot is warned and thrust starts to be
+    /// derated, degrees Celsius.
+    pub warn_temperature: f32,
+    /// At this temperature thrust is down to `min_thrust_limit`.
+    pub critical_temperature: f32,
+    /// How far the temperature must fall below a threshold before its alarm
+    /// clears.
+    pub temperature_hysteresis: f32,
+    pub min_thrust_limit: f32,
+    /// Relative humidity warnings, percent.
+    pub warn_humidity: f32,
+    pub critical_humidity: f32,
+    /// How far the humidity must fall below a threshold before its alarm
+    /// clears.
+    pub humidity_hysteresis: f32,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            sht31_address: None,
+            warn_temperature: 50.0,
+            critical_temperature: 65.0,
+            temperature_hysteresis: 2.0,
+            min_thrust_limit: 0.3,
+            warn_humidity: 70.0,
+            critical_humidity: 85.0,
+            humidity_hysteresis: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightConfig {
+    pub name: String,
+    pub channel: u8,
+    #[serde(default)]
+    pub driver: LightDriver,
+    /// Pulse at 0% for servo style drivers, PCA9685 counts.
+    #[serde(default = "default_light_min_pulse")]
+    pub min_pulse: u16,
+    /// Pulse at 100% for servo style drivers, PCA9685 counts.
+    #[serde(default = "default_light_max_pulse")]
+    pub max_pulse: u16,
+    /// Brightness reached at 100%, below 1 to keep the LEDs cool.
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: f32,
+}
+
+// 1100us and 1900us at the 127 prescale.
+fn default_light_min_pulse() -> u16 {
+    215
+}
+
+fn default_light_max_pulse() -> u16 {
+    371
+}
+
+fn default_max_brightness() -> f32 {
+    0.8
+}
+
+/// A servo on the PCA9685.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServoConfig {
+    pub name: String,
+    pub channel: u8,
+    /// Hard limits of the pulse, PCA9685 counts.
+    pub min_pulse: u16,
+    pub max_pulse: u16,
+    /// Two (degrees, pulse) points measured on the servo, positions in
+    /// degrees are interpolated between them.
+    pub calibration: [[f32; 2]; 2],
+    /// Degrees per second, 0 moves straight to the target.
+    #[serde(default = "default_servo_speed")]
+    pub max_speed: f32,
+    /// Degrees per second squared.
+    #[serde(default = "default_servo_acceleration")]
+    pub max_acceleration: f32,
+    #[serde(default)]
+    pub profile: ServoProfile,
+    /// Stall protection for a servo closing a gripper.
+    #[serde(default)]
+    pub gripper: Option<GripperConfig>,
+}
+
+fn default_servo_speed() -> f32 {
+    120.0
+}
+
+fn default_servo_acceleration() -> f32 {
+    480.0
+}
+
+impl ServoConfig {
+    /// Five servos on channels 8 to 12, roughly +-90 degrees around the 307
+    /// centre used by the 20kg servos.
+    pub fn defaults() -> Vec<ServoConfig> {
+        (0..5)
+            .map(|i| ServoConfig {
+                name: format!("b{}", i),
+                channel: 8 + i as u8,
+                min_pulse: 205,
+                max_pulse: 409,
+                calibration: [[-90.0, 205.0], [90.0, 409.0]],
+                max_speed: default_servo_speed(),
+                max_acceleration: default_servo_acceleration(),
+                profile: ServoProfile::default(),
+                gripper: None,
+            })
+            .collect()
+    }
+
+    /// Pulse for an angle, before clamping to the limits.
+    pub fn pulse(&self, degrees: f32) -> f32 {
+        let [[a0, p0], [a1, p1]] = self.calibration;
+        if a1 == a0 {
+            return p0;
+        }
+        p0 + (degrees - a0) * (p1 - p0) / (a1 - a0)
+    }
+
+    /// Angle of a pulse.
+    pub fn angle(&self, pulse: f32) -> f32 {
+        let [[a0, p0], [a1, p1]] = self.calibration;
+        if p1 == p0 {
+            return a0;
+        }
+        a0 + (pulse - p0) * (a1 - a0) / (p1 - p0)
+    }
+}
+
+/// A servo holding a gripper closed on an object stalls and overheats, so
+/// closing is limited in time and the servo then backs off to a gentler
+/// holding position until the pilot opens it again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GripperConfig {
+    /// Fully closed, degrees.
+    pub closed_angle: f32,
+    /// Position the gripper backs off to, degrees. Targets past it towards
+    /// `closed_angle` count as closing.
+    pub holding_angle: f32,
+    /// How long the gripper may keep closing, 0 for no limit.
+    pub closing_timeout_s: f32,
+    /// ADS1115 channel measuring the servo current, in amps.
+    pub current_channel: Option<String>,
+    /// Current above which the servo counts as stalled.
+    pub stall_current: f32,
+    /// How long the current must stay above `stall_current`.
+    pub stall_time_s: f32,
+}
+
+impl Default for GripperConfig {
+    fn default() -> Self {
+        GripperConfig {
+            closed_angle: 90.0,
+            holding_angle: 60.0,
+            closing_timeout_s: 5.0,
+            current_channel: None,
+            stall_current: 1.5,
+            stall_time_s: 0.3,
+        }
+    }
+}
+
+/// Named set of servo positions, e.g. `gripper_open`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServoPresetConfig {
+    pub name: String,
+    /// Servo name and target in degrees.
+    pub positions: Vec<(String, f32)>,
+}
+
+/// Flight data recorder, see [`crate::recorder`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecorderConfig {
+    /// Directory the flight logs go to, `None` disables the recorder.
+    pub directory: Option<String>,
+    /// Size past which the next log is started, with a snapshot of the
+    /// vehicle.
+    pub max_file_bytes: u64,
+    /// Logs kept, the oldest are deleted beyond this.
+    pub max_files: usize,
+    /// How often written data is forced to the card.
+    pub sync_ms: u64,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        RecorderConfig {
+            directory: Some("logs".to_string()),
+            max_file_bytes: 32 * 1024 * 1024,
+            max_files: 32,
+            sync_ms: 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_the_shared_angle_pid() {
+        let path = std::env::temp_dir().join(format!("finale-config-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"stabilise": {"angle_pid": {"kp": 2.0, "ki": 0.5}}}"#,
+        )
+        .unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        let stabilise = config.unwrap().stabilise;
+        assert_eq!(stabilise.roll_pid.kp, 2.0);
+        assert_eq!(stabilise.pitch_pid.ki, 0.5);
+        assert!(stabilise.angle_pid.is_none());
+        let saved = serde_json::to_value(&stabilise).unwrap();
+        assert!(saved.get("angle_pid").is_none());
+    }
+}
@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+
+use crate::link::{Ack, LinkMonitor, LinkQuality};
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// What a connected client is allowed to drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// May hold every group.
+    Pilot,
+    /// May hold the manipulator and the lights.
+    CoPilot,
+    /// Watches only.
+    Observer,
+}
+
+impl Role {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Role::Pilot => 0,
+            Role::CoPilot => 1,
+            Role::Observer => 2,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Role> {
+        match value {
+            0 => Some(Role::Pilot),
+            1 => Some(Role::CoPilot),
+            2 => Some(Role::Observer),
+            _ => None,
+        }
+    }
+
+    pub fn may_hold(self, group: ControlGroup) -> bool {
+        match self {
+            Role::Pilot => true,
+            Role::CoPilot => group != ControlGroup::Flight,
+            Role::Observer => false,
+        }
+    }
+
+    /// A role may take a group from a client of lower rank.
+    fn rank(self) -> u8 {
+        match self {
+            Role::Pilot => 2,
+            Role::CoPilot => 1,
+            Role::Observer => 0,
+        }
+    }
+}
+
+/// Actuators owned together by one client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlGroup {
+    /// Thrusters, arming, stabilisation and auto-tune.
+    Flight,
+    /// Servos and the gripper.
+    Manipulator,
+    Lights,
+}
+
+impl ControlGroup {
+    pub const ALL: [ControlGroup; 3] = [
+        ControlGroup::Flight,
+        ControlGroup::Manipulator,
+        ControlGroup::Lights,
+    ];
+
+    fn bit(self) -> u8 {
+        match self {
+            ControlGroup::Flight => 1,
+            ControlGroup::Manipulator => 2,
+            ControlGroup::Lights => 4,
+        }
+    }
+
+    /// Bit mask used on the wire.
+    pub fn to_mask(groups: &[ControlGroup]) -> u8 {
+        groups.iter().fold(0, |mask, group| mask | group.bit())
+    }
+
+    pub fn from_mask(mask: u8) -> Vec<ControlGroup> {
+        Self::ALL
+            .into_iter()
+            .filter(|group| mask & group.bit() != 0)
+            .collect()
+    }
+}
+
+/// Role and groups of one client, sent back after a control request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlStatus {
+    pub role: Role,
+    pub groups: Vec<ControlGroup>,
+}
+
+/// A connected client as shown in telemetry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub id: u64,
+    /// Transport and address, e.g. `tcp 10.0.0.2:51234`.
+    pub name: String,
+    pub role: Role,
+    pub groups: Vec<ControlGroup>,
+    /// `None` until the client stamps its messages.
+    pub link: Option<LinkQuality>,
+}
+
+struct Client {
+    name: String,
+    role: Role,
+    /// Set once the client said hello. Until then it is a pilot that takes
+    /// whatever is free, like the legacy clients.
+    explicit: bool,
+    seen: Instant,
+    link: LinkMonitor,
+}
+
+/// Decides which client drives which actuators.
+///
+/// A group has at most one owner. It is handed over when the owner releases
+/// it or disconnects, or taken by a client of higher rank. The groups of a
+/// client silent for longer than the link timeout count as free.
+///
+/// A client that never said hello claims the free groups with each command
+/// or pilot input, see [`Arbiter::claim`]. Stamps and session requests only
+/// keep it alive, so a client that merely measures the link takes nothing.
+pub struct Arbiter {
+    timeout: Duration,
+    next_id: u64,
+    clients: BTreeMap<u64, Client>,
+    owners: HashMap<ControlGroup, u64>,
+}
+
+impl Arbiter {
+    pub fn new(timeout: Duration) -> Self {
+        Arbiter {
+            timeout,
+            next_id: 0,
+            clients: BTreeMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    pub fn connect(&mut self, name: String) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        println!("client {} connected: {}", id, name);
+        self.clients.insert(
+            id,
+            Client {
+                name,
+                role: Role::Pilot,
+                explicit: false,
+                seen: Instant::now(),
+                link: LinkMonitor::default(),
+            },
+        );
+        id
+    }
+
+    pub fn disconnect(&mut self, id: u64) {
+        if let Some(client) = self.clients.remove(&id) {
+            println!("client {} disconnected: {}", id, client.name);
+        }
+        self.owners.retain(|_, owner| *owner != id);
+    }
+
+    /// Records that the client is alive.
+    pub fn seen(&mut self, id: u64) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.seen = Instant::now();
+        }
+    }
+
+    /// Lets a client that never said hello take the groups that are free.
+    /// Called for commands and pilot input only, not for every message.
+    pub fn claim(&mut self, id: u64) {
+        if self.clients.get(&id).is_some_and(|client| !client.explicit) {
+            for group in ControlGroup::ALL {
+                if self.is_free(group) {
+                    self.grant(id, group);
+                }
+            }
+        }
+    }
+
+    /// Sets the role of a client, which then has to take groups explicitly.
+    pub fn hello(&mut self, id: u64, role: Role) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.role = role;
+            client.explicit = true;
+        }
+        self.owners
+            .retain(|group, owner| *owner != id || role.may_hold(*group));
+    }
+
+    /// Grants the groups the client may hold that are free or held by a
+    /// client of lower rank.
+    pub fn take(&mut self, id: u64, groups: &[ControlGroup]) {
+        let role = match self.clients.get(&id) {
+            Some(client) => client.role,
+            None => return,
+        };
+        for &group in groups {
+            if !role.may_hold(group) {
+                continue;
+            }
+            let outranks = self
+                .owner(group)
+                .and_then(|owner| self.clients.get(&owner))
+                .is_none_or(|owner| role.rank() > owner.role.rank());
+            if self.is_free(group) || outranks {
+                self.grant(id, group);
+            }
+        }
+    }
+
+    pub fn release(&mut self, id: u64, groups: &[ControlGroup]) {
+        for group in groups {
+            if self.owners.get(group) == Some(&id) {
+                println!("client {} released {:?}", id, group);
+                self.owners.remove(group);
+            }
+        }
+    }
+
+    /// Records the sequence number and timestamp a client stamped its
+    /// messages with.
+    pub fn stamp(&mut self, id: u64, seq: u32, timestamp_ms: u32) {
+        let client = match self.clients.get_mut(&id) {
+            Some(client) => client,
+            None => return,
+        };
+        let now = Instant::now();
+        client.link.stamp(seq, timestamp_ms, now);
+        if let Some(quality) = client.link.report(now) {
+            println!(
+                "client {} link: {} received, {} lost, jitter {:.1}ms",
+                id, quality.received, quality.lost, quality.jitter_ms
+            );
+        }
+    }
+
+    /// The ack due to a client, see [`LinkMonitor::ack`].
+    pub fn ack(&mut self, id: u64) -> Option<Ack> {
+        self.clients.get_mut(&id)?.link.ack(Instant::now())
+    }
+
+    pub fn owns(&self, id: u64, group: ControlGroup) -> bool {
+        self.owner(group) == Some(id)
+    }
+
+    pub fn status(&self, id: u64) -> ControlStatus {
+        ControlStatus {
+            role: self
+                .clients
+                .get(&id)
+                .map_or(Role::Observer, |client| client.role),
+            groups: self.groups(id),
+        }
+    }
+
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        self.clients
+            .iter()
+            .map(|(&id, client)| ClientInfo {
+                id,
+                name: client.name.clone(),
+                role: client.role,
+                groups: self.groups(id),
+                link: client.link.quality(),
+            })
+            .collect()
+    }
+
+    fn groups(&self, id: u64) -> Vec<ControlGroup> {
+        ControlGroup::ALL
+            .into_iter()
+            .filter(|group| self.owns(id, *group))
+            .collect()
+    }
+
+    /// Owner of a group, unless it has gone silent.
+    fn owner(&self, group: ControlGroup) -> Option<u64> {
+        let owner = *self.owners.get(&group)?;
+        let client = self.clients.get(&owner)?;
+        (client.seen.elapsed() <= self.timeout).then_some(owner)
+    }
+
+    fn is_free(&self, group: ControlGroup) -> bool {
+        self.owner(group).is_none()
+    }
+
+    fn grant(&mut self, id: u64, group: ControlGroup) {
+        if self.owners.insert(group, id) != Some(id) {
+            println!("client {} took {:?}", id, group);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn explicit(arbiter: &mut Arbiter, role: Role) -> u64 {
+        let id = arbiter.connect(format!("{:?}", role));
+        arbiter.hello(id, role);
+        id
+    }
+
+    #[test]
+    fn group_masks() {
+        assert_eq!(ControlGroup::to_mask(&ControlGroup::ALL), 7);
+        for mask in 0..8 {
+            assert_eq!(ControlGroup::to_mask(&ControlGroup::from_mask(mask)), mask);
+        }
+        assert_eq!(
+            ControlGroup::from_mask(0xFA),
+            vec![ControlGroup::Manipulator]
+        );
+    }
+
+    #[test]
+    fn take_and_release() {
+        let mut arbiter = Arbiter::new(TIMEOUT);
+        let a = explicit(&mut arbiter, Role::Pilot);
+        let b = explicit(&mut arbiter, Role::Pilot);
+        assert!(arbiter.groups(a).is_empty());
+
+        arbiter.take(a, &[ControlGroup::Flight, ControlGroup::Lights]);
+        arbiter.take(b, &ControlGroup::ALL);
+        assert_eq!(
+            arbiter.groups(a),
+            vec![ControlGroup::Flight, ControlGroup::Lights]
+        );
+        assert_eq!(arbiter.groups(b), vec![ControlGroup::Manipulator]);
+
+        arbiter.release(b, &[ControlGroup::Flight]);
+        assert!(arbiter.owns(a, ControlGroup::Flight));
+        arbiter.release(a, &[ControlGroup::Flight]);
+        arbiter.take(b, &[ControlGroup::Flight]);
+        assert!(arbiter.owns(b, ControlGroup::Flight));
+
+        arbiter.disconnect(a);
+        assert_eq!(arbiter.status(a).role, Role::Observer);
+        arbiter.take(b, &[ControlGroup::Lights]);
+        assert_eq!(arbiter.groups(b), ControlGroup::ALL.to_vec());
+    }
+
+    #[test]
+    fn roles_and_rank() {
+        let mut arbiter = Arbiter::new(TIMEOUT);
+        let co_pilot = explicit(&mut arbiter, Role::CoPilot);
+        let observer = explicit(&mut arbiter, Role::Observer);
+        arbiter.take(co_pilot, &ControlGroup::ALL);
+        arbiter.take(observer, &ControlGroup::ALL);
+        assert_eq!(
+            arbiter.groups(co_pilot),
+            vec![ControlGroup::Manipulator, ControlGroup::Lights]
+        );
+        assert!(arbiter.groups(observer).is_empty());
+
+        let pilot = explicit(&mut arbiter, Role::Pilot);
+        arbiter.take(pilot, &[ControlGroup::Manipulator]);
+        assert!(arbiter.owns(pilot, ControlGroup::Manipulator));
+        arbiter.take(co_pilot, &[ControlGroup::Manipulator]);
+        assert!(arbiter.owns(pilot, ControlGroup::Manipulator));
+
+        // Stepping down gives up what the new role may not hold.
+        arbiter.take(pilot, &[ControlGroup::Flight]);
+        arbiter.hello(pilot, Role::CoPilot);
+        assert_eq!(arbiter.groups(pilot), vec![ControlGroup::Manipulator]);
+    }
+
+    #[test]
+    fn silent_owners_lose_their_groups() {
+        let mut arbiter = Arbiter::new(TIMEOUT);
+        let a = explicit(&mut arbiter, Role::Pilot);
+        let b = explicit(&mut arbiter, Role::CoPilot);
+        arbiter.take(a, &ControlGroup::ALL);
+        arbiter.take(b, &[ControlGroup::Lights]);
+        assert!(arbiter.owns(a, ControlGroup::Lights));
+
+        thread::sleep(TIMEOUT * 2);
+        arbiter.seen(b);
+        assert!(arbiter.groups(a).is_empty());
+        arbiter.take(b, &[ControlGroup::Lights]);
+        assert!(arbiter.owns(b, ControlGroup::Lights));
+
+        // Flight stays free for a pilot, the co-pilot may not hold it.
+        arbiter.take(b, &[ControlGroup::Flight]);
+        assert!(!arbiter.owns(b, ControlGroup::Flight));
+        arbiter.seen(a);
+        arbiter.take(a, &[ControlGroup::Flight]);
+        assert!(arbiter.owns(a, ControlGroup::Flight));
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::PI;
+
+/// One raw reading of the MPU6050.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImuSample {
+    /// Acceleration in g.
+    pub accel: [f32; 3],
+    /// Angular rate in rad/s.
+    pub gyro: [f32; 3],
+}
+
+impl ImuSample {
+    /// Roll and pitch from gravity alone, in radians.
+    pub fn accel_angles(&self) -> (f32, f32) {
+        let [x, y, z] = self.accel;
+        let roll = y.atan2((x * x + z * z).sqrt());
+        let pitch = (-x).atan2((y * y + z * z).sqrt());
+        (roll, pitch)
+    }
+}
+
+/// Offsets measured while the vehicle sits still at startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub gyro_bias: [f32; 3],
+    pub roll_offset: f32,
+    pub pitch_offset: f32,
+}
+
+impl Calibration {
+    /// Averages the samples taken during calibration.
+    pub fn from_samples(samples: &[ImuSample]) -> Calibration {
+        let mut calibration = Calibration::default();
+        if samples.is_empty() {
+            return calibration;
+        }
+        for sample in samples {
+            let (roll, pitch) = sample.accel_angles();
+            calibration.roll_offset += roll;
+            calibration.pitch_offset += pitch;
+            for i in 0..3 {
+                calibration.gyro_bias[i] += sample.gyro[i];
+            }
+        }
+        let n = samples.len() as f32;
+        calibration.roll_offset /= n;
+        calibration.pitch_offset /= n;
+        for bias in calibration.gyro_bias.iter_mut() {
+            *bias /= n;
+        }
+        calibration
+    }
+}
+
+/// Orientation of the vehicle in radians, and its body rates in rad/s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Attitude {
+    pub roll: f32,
+    pub pitch: f32,
+    /// Heading relative to where the vehicle pointed at startup, in (-pi, pi].
+    pub yaw: f32,
+    pub roll_rate: f32,
+    pub pitch_rate: f32,
+    pub yaw_rate: f32,
+}
+
+/// Complementary filter fusing gyro and accelerometer.
+///
+/// Roll and pitch are corrected by gravity. The MPU6050 has no magnetometer,
+/// so yaw is the bias compensated gyro integrated over time and will drift
+/// slowly.
+pub struct Estimator {
+    calibration: Calibration,
+    gyro_weight: f32,
+    attitude: Attitude,
+}
+
+impl Estimator {
+    pub fn new(calibration: Calibration, gyro_weight: f32) -> Self {
+        Estimator {
+            calibration,
+            gyro_weight,
+            attitude: Attitude::default(),
+        }
+    }
+
+    pub fn attitude(&self) -> Attitude {
+        self.attitude
+    }
+
+    /// Continues from an attitude estimated earlier.
+    pub fn restore(&mut self, attitude: Attitude) {
+        self.attitude = attitude;
+    }
+
+    pub fn update(&mut self, sample: &ImuSample, dt: f32) -> Attitude {
+        let bias = self.calibration.gyro_bias;
+        let roll_rate = sample.gyro[0] - bias[0];
+        let pitch_rate = sample.gyro[1] - bias[1];
+        let yaw_rate = sample.gyro[2] - bias[2];
+
+        let (acc_roll, acc_pitch) = sample.accel_angles();
+        let acc_roll = acc_roll - self.calibration.roll_offset;
+        let acc_pitch = acc_pitch - self.calibration.pitch_offset;
+
+        let a = self.gyro_weight;
+        let previous = self.attitude;
+        self.attitude = Attitude {
+            roll: a * (previous.roll + roll_rate * dt) + (1.0 - a) * acc_roll,
+            pitch: a * (previous.pitch + pitch_rate * dt) + (1.0 - a) * acc_pitch,
+            yaw: wrap_angle(previous.yaw + yaw_rate * dt),
+            roll_rate,
+            pitch_rate,
+            yaw_rate,
+        };
+        self.attitude
+    }
+}
+
+/// Brings an angle back into (-pi, pi].
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
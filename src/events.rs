@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+
+/// Something that happened on the vehicle, kept for telemetry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Increases by one for every event, so clients can ask for newer ones.
+    pub seq: u64,
+    /// Vehicle time in seconds.
+    pub time: f64,
+    /// What raised it, e.g. `gripper`.
+    pub source: String,
+    pub message: String,
+}
+
+/// The most recent events.
+#[derive(Debug, Clone, Default)]
+pub struct Events {
+    recent: VecDeque<Event>,
+    next_seq: u64,
+}
+
+impl Events {
+    /// Number of events kept, older ones are dropped.
+    pub const CAPACITY: usize = 100;
+
+    pub fn push(&mut self, time: f64, source: &str, message: String) {
+        println!("event {}: {}", source, message);
+        if self.recent.len() == Self::CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(Event {
+            seq: self.next_seq,
+            time,
+            source: source.to_string(),
+            message,
+        });
+        self.next_seq += 1;
+    }
+
+    pub fn recent(&self) -> impl Iterator<Item = &Event> {
+        self.recent.iter()
+    }
+
+    /// Events with a sequence number of at least `seq`.
+    pub fn since(&self, seq: u64) -> impl Iterator<Item = &Event> {
+        self.recent.iter().filter(move |event| event.seq >= seq)
+    }
+}
@@ -0,0 +1,287 @@
+use linux_embedded_hal::i2cdev::core::I2CDevice;
+use linux_embedded_hal::i2cdev::linux::LinuxI2CDevice;
+use linux_embedded_hal::I2cdev;
+use linux_embedded_hal_mpu::{Delay as DelayMPU, I2cdev as I2cMPU};
+use mpu6050::Mpu6050;
+use pwm_pca9685::{Address, Channel, Pca9685};
+use rppal::gpio::{Gpio, InputPin};
+
+use crate::config::{AdcChannelConfig, Ads1115Config, Ina219Config, LeakSensorConfig};
+use crate::estimator::ImuSample;
+use crate::sensors::{ClimateReading, PowerReading};
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// The PCA9685 driving thrusters and servos.
+pub struct Pwm {
+    pca: Pca9685<I2cdev>,
+}
+
+impl Pwm {
+    pub fn new(bus: &str, prescale: u8) -> Result<Pwm, Box<dyn Error>> {
+        let i2c_pca =
+            I2cdev::new(bus).map_err(|e| format!("Failed to open I2C device: {:?}", e))?;
+        let pca_address = Address::default(); // default I2C address for PCA9685
+        let mut pca = Pca9685::new(i2c_pca, pca_address)
+            .map_err(|e| format!("Failed to open PCA9685: {:?}", e))?;
+        pca.set_prescale(prescale)
+            .map_err(|e| format!("Failed to set PCA9685 prescale: {:?}", e))?;
+        pca.enable()
+            .map_err(|e| format!("Failed to enable PCA9685: {:?}", e))?;
+        Ok(Pwm { pca })
+    }
+
+    /// Sets the pulse width of `channel`, in PCA9685 counts out of 4096.
+    pub fn set_pulse(&mut self, channel: u8, pulse: u16) -> Result<(), Box<dyn Error>> {
+        let channel = Channel::try_from(channel)
+            .map_err(|_| format!("Invalid PCA9685 channel {}", channel))?;
+        self.pca
+            .set_channel_on_off(channel, 0, pulse)
+            .map_err(|e| format!("Failed to set PCA9685 channel {:?}: {:?}", channel, e))?;
+        Ok(())
+    }
+}
+
+/// The MPU6050 on the electronics board.
+pub struct Imu {
+    mpu: Mpu6050<I2cMPU>,
+}
+
+impl Imu {
+    pub fn new(bus: &str) -> Result<Imu, Box<dyn Error>> {
+        let i2c_mpu =
+            I2cMPU::new(bus).map_err(|e| format!("Failed to open I2C device: {:?}", e))?;
+        let mut mpu = Mpu6050::new(i2c_mpu);
+        let mut delay = DelayMPU;
+        mpu.init(&mut delay)
+            .map_err(|e| format!("Failed to initialise MPU6050: {:?}", e))?;
+        Ok(Imu { mpu })
+    }
+
+    /// Die temperature of the MPU6050 in degrees Celsius.
+    pub fn temperature(&mut self) -> Result<f32, Box<dyn Error>> {
+        let temperature = self
+            .mpu
+            .get_temp()
+            .map_err(|e| format!("Failed to read MPU6050 temperature: {:?}", e))?;
+        Ok(temperature)
+    }
+
+    pub fn read(&mut self) -> Result<ImuSample, Box<dyn Error>> {
+        let accel = self
+            .mpu
+            .get_acc()
+            .map_err(|e| format!("Failed to read accelerometer: {:?}", e))?;
+        let gyro = self
+            .mpu
+            .get_gyro()
+            .map_err(|e| format!("Failed to read gyro: {:?}", e))?;
+        Ok(ImuSample {
+            accel: [accel.x, accel.y, accel.z],
+            gyro: [gyro.x, gyro.y, gyro.z],
+        })
+    }
+}
+
+/// GPIO inputs of the leak sensors.
+pub struct LeakPins {
+    pins: Vec<InputPin>,
+}
+
+impl LeakPins {
+    pub fn new(sensors: &[LeakSensorConfig]) -> Result<LeakPins, Box<dyn Error>> {
+        if sensors.is_empty() {
+            return Ok(LeakPins { pins: Vec::new() });
+        }
+        let gpio = Gpio::new()?;
+        let mut pins = Vec::new();
+        for sensor in sensors {
+            let pin = gpio
+                .get(sensor.pin)
+                .map_err(|e| format!("Failed to get leak pin {}: {}", sensor.pin, e))?;
+            let pin = if sensor.active_high {
+                pin.into_input_pulldown()
+            } else {
+                pin.into_input_pullup()
+            };
+            pins.push(pin);
+        }
+        Ok(LeakPins { pins })
+    }
+
+    /// Level of every pin, true is high.
+    pub fn read(&self) -> Vec<bool> {
+        self.pins.iter().map(|pin| pin.is_high()).collect()
+    }
+}
+
+fn i2c_device(bus: &str, address: u8) -> Result<LinuxI2CDevice, Box<dyn Error>> {
+    LinuxI2CDevice::new(bus, address as u16)
+        .map_err(|e| format!("Failed to open I2C device 0x{:02x}: {:?}", address, e).into())
+}
+
+/// Reads a big endian register, as used by the TI chips below.
+fn read_register(dev: &mut LinuxI2CDevice, register: u8) -> Result<u16, Box<dyn Error>> {
+    let mut buf = [0u8; 2];
+    dev.write(&[register])?;
+    dev.read(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn write_register(
+    dev: &mut LinuxI2CDevice,
+    register: u8,
+    value: u16,
+) -> Result<(), Box<dyn Error>> {
+    let [msb, lsb] = value.to_be_bytes();
+    dev.write(&[register, msb, lsb])?;
+    Ok(())
+}
+
+/// INA219 current monitor, left in its power-on continuous mode.
+pub struct Ina219 {
+    dev: LinuxI2CDevice,
+    shunt_ohms: f32,
+}
+
+impl Ina219 {
+    const SHUNT_VOLTAGE: u8 = 0x01;
+    const BUS_VOLTAGE: u8 = 0x02;
+
+    pub fn new(bus: &str, config: &Ina219Config) -> Result<Ina219, Box<dyn Error>> {
+        Ok(Ina219 {
+            dev: i2c_device(bus, config.address)?,
+            shunt_ohms: config.shunt_ohms,
+        })
+    }
+
+    pub fn read(&mut self) -> Result<PowerReading, Box<dyn Error>> {
+        // Shunt voltage LSB is 10uV, bus voltage sits in bits 15..3 with a 4mV LSB.
+        let shunt = read_register(&mut self.dev, Self::SHUNT_VOLTAGE)? as i16 as f32 * 10e-6;
+        let bus = (read_register(&mut self.dev, Self::BUS_VOLTAGE)? >> 3) as f32 * 4e-3;
+        Ok(PowerReading {
+            voltage: bus,
+            current: shunt / self.shunt_ohms,
+        })
+    }
+}
+
+/// ADS1115 four channel ADC.
+///
+/// Channels are converted one at a time in single shot mode: each call reads
+/// the conversion started by the previous call and starts the next channel,
+/// so the control loop never waits for the ADC.
+pub struct Ads1115 {
+    dev: LinuxI2CDevice,
+    channels: Vec<AdcChannelConfig>,
+    values: Vec<Option<f32>>,
+    converting: Option<usize>,
+}
+
+impl Ads1115 {
+    const CONVERSION: u8 = 0x00;
+    const CONFIG: u8 = 0x01;
+    /// Full scale of the +-4.096V gain setting.
+    const FULL_SCALE: f32 = 4.096;
+
+    pub fn new(bus: &str, config: &Ads1115Config) -> Result<Ads1115, Box<dyn Error>> {
+        Ok(Ads1115 {
+            dev: i2c_device(bus, config.address)?,
+            values: vec![None; config.channels.len()],
+            channels: config.channels.clone(),
+            converting: None,
+        })
+    }
+
+    fn start(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        let input = self.channels[index].input as u16 & 0b11;
+        // Start single shot, AINx against GND, +-4.096V, 128SPS, comparator off.
+        let config = 0x8000 | (0b100 | input) << 12 | 0b001 << 9 | 0x0100 | 0b100 << 5 | 0b11;
+        write_register(&mut self.dev, Self::CONFIG, config)?;
+        self.converting = Some(index);
+        Ok(())
+    }
+
+    /// Latest scaled value of every configured channel.
+    pub fn read(&mut self) -> Result<Vec<Option<f32>>, Box<dyn Error>> {
+        if self.channels.is_empty() {
+            return Ok(Vec::new());
+        }
+        let next = match self.converting {
+            Some(index) => {
+                let raw = read_register(&mut self.dev, Self::CONVERSION)? as i16;
+                let volts = raw as f32 * Self::FULL_SCALE / 32768.0;
+                self.values[index] = Some(volts * self.channels[index].scale);
+                (index + 1) % self.channels.len()
+            }
+            None => 0,
+        };
+        self.start(next)?;
+        Ok(self.values.clone())
+    }
+}
+
+/// SHT31 in periodic mode, one measurement per second.
+pub struct Sht31 {
+    dev: LinuxI2CDevice,
+    last_fetch: Option<Instant>,
+    last: Option<ClimateReading>,
+}
+
+impl Sht31 {
+    const PERIODIC_1MPS_HIGH: [u8; 2] = [0x21, 0x30];
+    const FETCH_DATA: [u8; 2] = [0xE0, 0x00];
+
+    pub fn new(bus: &str, address: u8) -> Result<Sht31, Box<dyn Error>> {
+        let mut dev = i2c_device(bus, address)?;
+        dev.write(&Self::PERIODIC_1MPS_HIGH)?;
+        Ok(Sht31 {
+            dev,
+            last_fetch: None,
+            last: None,
+        })
+    }
+
+    /// CRC-8 of the SHT3x datasheet, polynomial 0x31, init 0xFF.
+    fn crc(data: &[u8]) -> u8 {
+        let mut crc = 0xFFu8;
+        for byte in data {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x31
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc
+    }
+
+    /// Latest measurement. The sensor is only asked once per second, between
+    /// fetches the previous value is returned.
+    pub fn read(&mut self) -> Result<ClimateReading, Box<dyn Error>> {
+        if let (Some(fetched), Some(last)) = (self.last_fetch, self.last) {
+            if fetched.elapsed() < Duration::from_secs(1) {
+                return Ok(last);
+            }
+        }
+        self.last_fetch = Some(Instant::now());
+
+        let mut buf = [0u8; 6];
+        self.dev.write(&Self::FETCH_DATA)?;
+        self.dev.read(&mut buf)?;
+        if Self::crc(&buf[0..2]) != buf[2] || Self::crc(&buf[3..5]) != buf[5] {
+            return Err("SHT31 CRC mismatch".into());
+        }
+        let raw_temperature = u16::from_be_bytes([buf[0], buf[1]]) as f32;
+        let raw_humidity = u16::from_be_bytes([buf[3], buf[4]]) as f32;
+        let reading = ClimateReading {
+            temperature: -45.0 + 175.0 * raw_temperature / 65535.0,
+            humidity: 100.0 * raw_humidity / 65535.0,
+        };
+        self.last = Some(reading);
+        Ok(reading)
+    }
+}
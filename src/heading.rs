@@ -0,0 +1,149 @@
+use crate::config::{HeadingConfig, PidConfig};
+use crate::estimator::{wrap_angle, Attitude};
+use crate::pid::{Pid, PidInput, PidTerms};
+
+/// Keeps the vehicle pointing where it was when the pilot let go of the yaw
+/// stick.
+///
+/// While the stick is outside the deadband the pilot yaws the vehicle directly.
+/// As soon as it is centred the current heading is locked and a PID loop on the
+/// wrapped heading error takes over. The loop output is slew limited so the
+/// rear thruster is never kicked from one side to the other.
+pub struct HeadingHold {
+    config: HeadingConfig,
+    pid: Pid,
+    target: Option<f32>,
+    output: f32,
+}
+
+impl HeadingHold {
+    pub fn new(config: HeadingConfig) -> Self {
+        HeadingHold {
+            pid: Pid::new(config.pid),
+            config,
+            target: None,
+            output: 0.0,
+        }
+    }
+
+    /// Heading being held, `None` while the pilot is turning.
+    pub fn target(&self) -> Option<f32> {
+        self.target
+    }
+
+    pub fn terms(&self) -> PidTerms {
+        self.pid.terms()
+    }
+
+    /// Replaces the heading loop gains, keeping its state.
+    pub fn set_pid(&mut self, config: PidConfig) {
+        self.config.pid = config;
+        self.pid.set_config(config);
+    }
+
+    /// Drops the locked heading, the next centred stick locks a fresh one.
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.output = 0.0;
+        self.pid.reset();
+    }
+
+    /// Starts over holding `target`, as if the hold had just locked it.
+    pub fn restore(&mut self, target: Option<f32>) {
+        self.reset();
+        self.target = target;
+    }
+
+    /// Returns the yaw command to send to the mixer.
+    pub fn update(&mut self, stick: f32, attitude: &Attitude, dt: f32) -> f32 {
+        if !self.config.enabled {
+            return stick;
+        }
+
+        let deadband = self.config.deadband;
+        if stick.abs() > deadband {
+            self.target = None;
+            // Rescale so the command starts from zero at the deadband edge.
+            self.output = stick.signum() * (stick.abs() - deadband) / (1.0 - deadband);
+            return self.output;
+        }
+
+        // The loop regulates the wrapped offset from the locked heading to zero.
+        // It starts without integral, the stick that was just released is no
+        // estimate of the yaw needed to hold still; the slew limit brings the
+        // output down from it.
+        let target = match self.target {
+            Some(target) => target,
+            None => {
+                self.pid.reset();
+                *self.target.insert(attitude.yaw)
+            }
+        };
+        let offset = wrap_angle(attitude.yaw - target);
+        let input = PidInput::new(0.0, offset).rate(attitude.yaw_rate);
+        let command = self.pid.update(input, dt);
+
+        let max_step = self.config.max_rate * dt;
+        self.output += (command - self.output).clamp(-max_step, max_step);
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hold() -> HeadingHold {
+        HeadingHold::new(HeadingConfig {
+            enabled: true,
+            deadband: 0.1,
+            max_rate: 2.0,
+            pid: PidConfig {
+                kp: 1.0,
+                ki: 1.0,
+                ..PidConfig::default()
+            },
+        })
+    }
+
+    fn heading(yaw: f32) -> Attitude {
+        Attitude {
+            yaw,
+            ..Attitude::default()
+        }
+    }
+
+    #[test]
+    fn stick_drives_yaw_outside_the_deadband() {
+        let mut hold = hold();
+        assert_eq!(hold.update(1.0, &heading(0.3), 0.01), 1.0);
+        assert!((hold.update(-0.55, &heading(0.3), 0.01) + 0.5).abs() < 1e-6);
+        assert_eq!(hold.target(), None);
+    }
+
+    #[test]
+    fn releasing_the_stick_locks_without_a_kick() {
+        let mut hold = hold();
+        for _ in 0..100 {
+            hold.update(1.0, &heading(0.3), 0.01);
+        }
+        // On heading, the loop starts from nothing and the slew limit walks
+        // the output down from the released stick.
+        let output = hold.update(0.0, &heading(0.3), 0.01);
+        assert_eq!(hold.target(), Some(0.3));
+        assert_eq!(hold.terms().i, 0.0);
+        assert!((output - 0.98).abs() < 1e-6);
+        for _ in 0..100 {
+            hold.update(0.0, &heading(0.3), 0.01);
+        }
+        assert!(hold.update(0.0, &heading(0.3), 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pushes_back_towards_the_locked_heading() {
+        let mut hold = hold();
+        hold.update(0.0, &heading(3.1), 0.01);
+        // Drifted across the wrap, the short way back is negative yaw.
+        assert!(hold.update(0.0, &heading(-3.1), 0.01) < 0.0);
+    }
+}
@@ -0,0 +1,268 @@
+use async_stream::stream;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::watch;
+use tokio::time;
+
+use crate::pilot::PilotLink;
+use crate::telemetry::Telemetry;
+use crate::websocket;
+
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+/// Single page dashboard fed by the event stream.
+const DASHBOARD: &str = include_str!("../static/dashboard.html");
+
+/// Serves the dashboard and the telemetry published by the control loop.
+///
+/// - `GET /`: the dashboard
+/// - `GET /api/telemetry`: the whole snapshot of the last cycle
+/// - `GET /api/alarms`: active alarms
+/// - `GET /api/events?since=N`: recent events with a sequence of at least N
+/// - `GET /api/stream?rate=HZ&fields=a,b`: server-sent events carrying
+///   telemetry samples, optionally limited to some top level fields
+/// - `GET /ws?rate=HZ`: WebSocket taking pilot inputs and commands and
+///   pushing telemetry back, see [`websocket::upgrade`]
+pub async fn serve(listener: TcpListener, telemetry: watch::Receiver<Telemetry>, link: PilotLink) {
+    let builder = match Server::from_tcp(listener) {
+        Ok(builder) => builder,
+        Err(e) => {
+            println!("http server failed to start: {}", e);
+            return;
+        }
+    };
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let addr = connection.remote_addr();
+        let telemetry = telemetry.clone();
+        let link = link.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request, addr, &telemetry, &link);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    if let Err(e) = builder.serve(make_service).await {
+        println!("http server stopped: {}", e);
+    }
+}
+
+fn handle(
+    request: Request<Body>,
+    addr: SocketAddr,
+    telemetry: &watch::Receiver<Telemetry>,
+    link: &PilotLink,
+) -> Response<Body> {
+    if request.method() != Method::GET {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    match request.uri().path() {
+        "/" | "/index.html" => Response::builder()
+            .header(CONTENT_TYPE, mime::TEXT_HTML_UTF_8.as_ref())
+            .body(Body::from(DASHBOARD))
+            .expect("static response parts are valid"),
+        "/api/telemetry" => json(&*telemetry.borrow()),
+        "/api/alarms" => json(&telemetry.borrow().alarms),
+        "/api/events" => {
+            let since = match query_param(&request, "since")
+                .as_deref()
+                .map(str::parse::<u64>)
+            {
+                Some(Ok(since)) => since,
+                Some(Err(_)) => return status(StatusCode::BAD_REQUEST),
+                None => 0,
+            };
+            let telemetry = telemetry.borrow();
+            let events: Vec<_> = telemetry
+                .events
+                .iter()
+                .filter(|event| event.seq >= since)
+                .collect();
+            json(&events)
+        }
+        "/api/stream" => event_stream(&request, telemetry),
+        "/ws" => {
+            let rate = query_param(&request, "rate");
+            websocket::upgrade(
+                request,
+                rate.as_deref(),
+                addr,
+                link.clone(),
+                telemetry.clone(),
+            )
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Default and highest sample rate of the event stream.
+const STREAM_RATE: f32 = 10.0;
+const MAX_STREAM_RATE: f32 = 50.0;
+
+/// Lowest sample rate a client can ask for.
+const MIN_RATE: f32 = 0.1;
+
+/// Sample rate given as `?rate=HZ`, `default` when there is none. Rates
+/// above `max` are capped, `None` means the rate is below [`MIN_RATE`] or
+/// not a number.
+pub(crate) fn parse_rate(rate: Option<&str>, default: f32, max: f32) -> Option<f32> {
+    match rate.map(str::parse::<f32>) {
+        Some(Ok(rate)) if rate >= MIN_RATE => Some(rate.min(max)),
+        Some(_) => None,
+        None => Some(default),
+    }
+}
+
+/// Streams telemetry as server-sent events.
+///
+/// Every client waits on the latest snapshot at its own rate, so a slow
+/// client skips samples instead of holding up the control loop.
+fn event_stream(request: &Request<Body>, telemetry: &watch::Receiver<Telemetry>) -> Response<Body> {
+    let rate = match parse_rate(
+        query_param(request, "rate").as_deref(),
+        STREAM_RATE,
+        MAX_STREAM_RATE,
+    ) {
+        Some(rate) => rate,
+        None => return status(StatusCode::BAD_REQUEST),
+    };
+    let fields: Option<Vec<String>> =
+        query_param(request, "fields").map(|fields| fields.split(',').map(String::from).collect());
+    if let Some(fields) = &fields {
+        let known = match serde_json::to_value(Telemetry::default()) {
+            Ok(Value::Object(known)) => known,
+            _ => return status(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        if fields.iter().any(|field| !known.contains_key(field)) {
+            return status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let mut telemetry = telemetry.clone();
+    let mut interval = time::interval(Duration::from_secs_f32(1.0 / rate));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let samples = stream! {
+        loop {
+            interval.tick().await;
+            // Ends the stream when the control loop is gone.
+            if telemetry.changed().await.is_err() {
+                break;
+            }
+            let sample = select_fields(&telemetry.borrow_and_update(), fields.as_deref());
+            match sample {
+                Ok(sample) => yield Ok::<_, Infallible>(format!("data: {}\n\n", sample)),
+                Err(e) => {
+                    println!("failed to encode telemetry: {}", e);
+                    break;
+                }
+            }
+        }
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, mime::TEXT_EVENT_STREAM.as_ref())
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(samples))
+        .expect("static response parts are valid")
+}
+
+/// JSON of a telemetry sample, keeping only `fields` and the time if given.
+fn select_fields(
+    telemetry: &Telemetry,
+    fields: Option<&[String]>,
+) -> Result<String, serde_json::Error> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return serde_json::to_string(telemetry),
+    };
+    let mut selected = Map::new();
+    if let Value::Object(mut all) = serde_json::to_value(telemetry)? {
+        for field in fields.iter().map(String::as_str).chain(["time"]) {
+            if let Some(value) = all.remove(field) {
+                selected.insert(field.to_string(), value);
+            }
+        }
+    }
+    serde_json::to_string(&selected)
+}
+
+/// Value of a parameter in the query string, percent-decoded.
+fn query_param(request: &Request<Body>, name: &str) -> Option<String> {
+    let query = request.uri().query()?;
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(body))
+            .expect("static response parts are valid"),
+        Err(e) => {
+            println!("failed to encode telemetry: {}", e);
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub(crate) fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .expect("static response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn query_params_are_decoded() {
+        let request = request("/api/stream?rate=5&fields=time%2Cattitude&note=a+b%21");
+        assert_eq!(query_param(&request, "rate").as_deref(), Some("5"));
+        assert_eq!(
+            query_param(&request, "fields").as_deref(),
+            Some("time,attitude")
+        );
+        assert_eq!(query_param(&request, "note").as_deref(), Some("a b!"));
+        assert_eq!(query_param(&request, "since"), None);
+        assert_eq!(query_param(&self::request("/api/events"), "since"), None);
+    }
+
+    #[test]
+    fn rates_are_bounded() {
+        assert_eq!(parse_rate(None, 10.0, 50.0), Some(10.0));
+        assert_eq!(parse_rate(Some("2.5"), 10.0, 50.0), Some(2.5));
+        assert_eq!(parse_rate(Some("1e9"), 10.0, 50.0), Some(50.0));
+        assert_eq!(parse_rate(Some("inf"), 10.0, 50.0), Some(50.0));
+        for rate in ["0", "-1", "1e-39", "NaN", "fast", ""] {
+            assert_eq!(parse_rate(Some(rate), 10.0, 50.0), None, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn tiny_stream_rates_are_rejected() {
+        let (_tx, telemetry) = watch::channel(Telemetry::default());
+        for uri in [
+            "/api/stream?rate=1e-39",
+            "/api/stream?rate=0",
+            "/api/stream?fields=nope",
+        ] {
+            assert_eq!(
+                event_stream(&request(uri), &telemetry).status(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+}
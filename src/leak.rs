@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::LeakConfig;
+
+/// What the vehicle does on its own when water is detected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeakResponse {
+    /// Only raise the alarm.
+    None,
+    /// Put the thrusters at neutral until the pilot arms again.
+    #[default]
+    Disarm,
+    /// Override the pilot heave with a fixed command while the leak lasts.
+    Surface,
+}
+
+/// Debounces the raw levels of the leak sensors.
+pub struct LeakDetector {
+    config: LeakConfig,
+    /// Time each sensor has disagreed with its debounced state.
+    pending: Vec<f32>,
+    wet: Vec<bool>,
+}
+
+impl LeakDetector {
+    pub fn new(config: LeakConfig) -> Self {
+        let sensors = config.sensors.len();
+        LeakDetector {
+            config,
+            pending: vec![0.0; sensors],
+            wet: vec![false; sensors],
+        }
+    }
+
+    pub fn config(&self) -> &LeakConfig {
+        &self.config
+    }
+
+    /// Names of the sensors currently reporting water.
+    pub fn wet_sensors(&self) -> Vec<&str> {
+        self.config
+            .sensors
+            .iter()
+            .zip(&self.wet)
+            .filter(|(_, wet)| **wet)
+            .map(|(sensor, _)| sensor.name.as_str())
+            .collect()
+    }
+
+    /// Takes the pin levels (true is high) and returns whether any sensor is
+    /// wet. A sensor changes state only after disagreeing for the debounce
+    /// time.
+    pub fn update(&mut self, levels: &[bool], dt: f32) -> bool {
+        let debounce = self.config.debounce_ms as f32 / 1000.0;
+        for (i, sensor) in self.config.sensors.iter().enumerate() {
+            let level = match levels.get(i) {
+                Some(level) => *level,
+                None => continue,
+            };
+            let active = level == sensor.active_high;
+            if active == self.wet[i] {
+                self.pending[i] = 0.0;
+                continue;
+            }
+            self.pending[i] += dt;
+            if self.pending[i] >= debounce {
+                self.wet[i] = active;
+                self.pending[i] = 0.0;
+            }
+        }
+        self.wet.iter().any(|wet| *wet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::LeakSensorConfig;
+
+    /// A quarter of the debounce time, exact in binary so the sums are too.
+    const DT: f32 = 0.03125;
+
+    fn detector() -> LeakDetector {
+        LeakDetector::new(LeakConfig {
+            sensors: vec![
+                LeakSensorConfig {
+                    name: "front".to_string(),
+                    pin: 5,
+                    active_high: true,
+                },
+                LeakSensorConfig {
+                    name: "rear".to_string(),
+                    pin: 6,
+                    active_high: false,
+                },
+            ],
+            debounce_ms: 125,
+            ..LeakConfig::default()
+        })
+    }
+
+    #[test]
+    fn ignores_short_blips() {
+        let mut detector = detector();
+        for _ in 0..4 {
+            assert!(!detector.update(&[true, true], 3.0 * DT));
+            assert!(!detector.update(&[false, true], DT));
+        }
+        assert!(detector.wet_sensors().is_empty());
+    }
+
+    #[test]
+    fn turns_wet_after_debounce() {
+        let mut detector = detector();
+        for _ in 0..3 {
+            assert!(!detector.update(&[false, false], DT));
+        }
+        assert!(detector.update(&[false, false], DT));
+        assert_eq!(detector.wet_sensors(), vec!["rear"]);
+    }
+
+    #[test]
+    fn dries_after_debounce() {
+        let mut detector = detector();
+        assert!(detector.update(&[true, true], 4.0 * DT));
+        assert_eq!(detector.wet_sensors(), vec!["front"]);
+        // A dry spell interrupted by a wet reading starts over.
+        assert!(detector.update(&[false, true], 3.0 * DT));
+        assert!(detector.update(&[true, true], DT));
+        assert!(detector.update(&[false, true], 3.0 * DT));
+        assert!(!detector.update(&[false, true], DT));
+    }
+
+    #[test]
+    fn missing_levels_keep_the_state() {
+        let mut detector = detector();
+        assert!(detector.update(&[true], 4.0 * DT));
+        assert!(detector.update(&[], 1.0));
+        assert_eq!(detector.wet_sensors(), vec!["front"]);
+    }
+}
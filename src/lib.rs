@@ -0,0 +1,29 @@
+pub mod alarms;
+pub mod autotune;
+pub mod config;
+pub mod control;
+pub mod estimator;
+pub mod events;
+#[cfg(feature = "hardware")]
+pub mod hardware;
+pub mod heading;
+pub mod http;
+pub mod leak;
+pub mod lights;
+pub mod link;
+pub mod mixer;
+pub mod pid;
+pub mod pilot;
+pub mod power;
+pub mod protocol;
+pub mod recorder;
+pub mod replay;
+pub mod sensors;
+pub mod servo;
+pub mod shaping;
+pub mod stabilise;
+pub mod telemetry;
+pub mod thermal;
+pub mod udp;
+pub mod vehicle;
+pub mod websocket;
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::LightConfig;
+
+/// How a light driver reads its PCA9685 channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightDriver {
+    /// Duty cycle is brightness, for LEDs switched by a MOSFET.
+    #[default]
+    Pwm,
+    /// Pulse width between `min_pulse` and `max_pulse`, for dimmable drivers
+    /// taking a servo signal.
+    Servo,
+}
+
+/// Lights on spare PCA9685 channels.
+pub struct Lights {
+    configs: Vec<LightConfig>,
+    /// Brightness asked by the pilot, 0 to 1, before the maximum is applied.
+    brightness: Vec<f32>,
+}
+
+impl Lights {
+    /// Sent in place of a light index to address every light.
+    pub const ALL: u8 = 0xFF;
+
+    /// Maximum brightness outside 0 to 1 is clamped, past full duty the
+    /// PCA9685 takes no pulse.
+    pub fn new(mut configs: Vec<LightConfig>) -> Self {
+        for config in &mut configs {
+            let max_brightness = config.max_brightness.clamp(0.0, 1.0);
+            if max_brightness != config.max_brightness {
+                println!(
+                    "light {} max_brightness {} clamped to {}",
+                    config.name, config.max_brightness, max_brightness
+                );
+                config.max_brightness = max_brightness;
+            }
+        }
+        Lights {
+            brightness: vec![0.0; configs.len()],
+            configs,
+        }
+    }
+
+    pub fn configs(&self) -> &[LightConfig] {
+        &self.configs
+    }
+
+    /// Brightness of every light as a fraction, after the maximum is applied.
+    pub fn brightness(&self) -> Vec<f32> {
+        self.configs
+            .iter()
+            .zip(&self.brightness)
+            .map(|(config, brightness)| brightness * config.max_brightness)
+            .collect()
+    }
+
+    /// Brightness asked for every light, in percent.
+    pub fn percent(&self) -> Vec<u8> {
+        self.brightness
+            .iter()
+            .map(|brightness| (brightness * 100.0).round() as u8)
+            .collect()
+    }
+
+    /// Sets one light, or all of them with [`Lights::ALL`], in percent.
+    pub fn set(&mut self, index: u8, percent: u8) {
+        let brightness = percent.min(100) as f32 / 100.0;
+        if index == Self::ALL {
+            self.brightness.iter_mut().for_each(|b| *b = brightness);
+        } else if let Some(b) = self.brightness.get_mut(index as usize) {
+            *b = brightness;
+        } else {
+            println!("no light {}", index);
+        }
+    }
+
+    /// PCA9685 off count of every light, in configuration order.
+    pub fn pulses(&self) -> Vec<u16> {
+        self.configs
+            .iter()
+            .zip(self.brightness())
+            .map(|(config, brightness)| match config.driver {
+                LightDriver::Pwm => (brightness * 4095.0).round() as u16,
+                LightDriver::Servo => {
+                    let span = config.max_pulse as f32 - config.min_pulse as f32;
+                    (config.min_pulse as f32 + brightness * span).round() as u16
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(driver: LightDriver, max_brightness: f32) -> LightConfig {
+        LightConfig {
+            name: "light".to_string(),
+            channel: 8,
+            driver,
+            min_pulse: 215,
+            max_pulse: 371,
+            max_brightness,
+        }
+    }
+
+    #[test]
+    fn pwm_duty_follows_percent() {
+        let mut lights = Lights::new(vec![light(LightDriver::Pwm, 1.0)]);
+        assert_eq!(lights.pulses(), vec![0]);
+        lights.set(0, 50);
+        assert_eq!(lights.pulses(), vec![2048]);
+        lights.set(0, 100);
+        assert_eq!(lights.pulses(), vec![4095]);
+        // Above 100% is full brightness.
+        lights.set(0, 250);
+        assert_eq!(lights.pulses(), vec![4095]);
+        assert_eq!(lights.percent(), vec![100]);
+    }
+
+    #[test]
+    fn servo_drivers_span_their_pulses() {
+        let mut lights = Lights::new(vec![light(LightDriver::Servo, 1.0)]);
+        assert_eq!(lights.pulses(), vec![215]);
+        lights.set(0, 50);
+        assert_eq!(lights.pulses(), vec![293]);
+        lights.set(0, 100);
+        assert_eq!(lights.pulses(), vec![371]);
+    }
+
+    #[test]
+    fn max_brightness_scales_and_is_clamped() {
+        let mut lights = Lights::new(vec![
+            light(LightDriver::Pwm, 0.5),
+            light(LightDriver::Servo, 0.5),
+            light(LightDriver::Pwm, 3.0),
+            light(LightDriver::Pwm, -1.0),
+        ]);
+        lights.set(Lights::ALL, 100);
+        assert_eq!(lights.pulses(), vec![2048, 293, 4095, 0]);
+        assert_eq!(lights.brightness(), vec![0.5, 0.5, 1.0, 0.0]);
+        assert_eq!(lights.configs()[2].max_brightness, 1.0);
+    }
+
+    #[test]
+    fn all_sets_every_light() {
+        let mut lights = Lights::new(vec![
+            light(LightDriver::Pwm, 1.0),
+            light(LightDriver::Servo, 1.0),
+        ]);
+        lights.set(Lights::ALL, 100);
+        assert_eq!(lights.percent(), vec![100, 100]);
+        lights.set(1, 0);
+        assert_eq!(lights.pulses(), vec![4095, 215]);
+        // Unknown lights are ignored.
+        lights.set(2, 40);
+        assert_eq!(lights.percent(), vec![100, 0]);
+    }
+}
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+/// How often the vehicle acknowledges a client that stamps its messages.
+pub const ACK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the vehicle logs the quality of each link.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A jump in sequence numbers larger than this is a restarted client rather
+/// than lost messages.
+const MAX_GAP: u32 = 1000;
+
+/// Acknowledgement sent back to a client, echoing the newest stamp received
+/// from it.
+///
+/// The round trip time is the client clock now, minus `echo_timestamp_ms`,
+/// minus `delay_ms` spent on the vehicle before the ack was sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Ack {
+    /// Counts acks sent to this client, so it can tell when acks are lost.
+    pub seq: u32,
+    pub echo_seq: u32,
+    pub echo_timestamp_ms: u32,
+    pub delay_ms: u32,
+    /// Stamped messages received from the client and missing in between.
+    pub received: u32,
+    pub lost: u32,
+    /// Interarrival jitter of the client messages.
+    pub jitter_ms: f32,
+}
+
+/// Quality of the link from one client, as seen by the vehicle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkQuality {
+    pub received: u32,
+    pub lost: u32,
+    /// Interarrival jitter as defined for RTP (RFC 3550), in milliseconds.
+    pub jitter_ms: f32,
+}
+
+struct Stamp {
+    seq: u32,
+    timestamp_ms: u32,
+    received: Instant,
+}
+
+/// Vehicle side bookkeeping of the stamps one client sends.
+pub struct LinkMonitor {
+    last: Option<Stamp>,
+    quality: LinkQuality,
+    acks: u32,
+    logged: Instant,
+}
+
+impl Default for LinkMonitor {
+    fn default() -> Self {
+        LinkMonitor {
+            last: None,
+            quality: LinkQuality::default(),
+            acks: 0,
+            logged: Instant::now(),
+        }
+    }
+}
+
+impl LinkMonitor {
+    /// Records a stamped message. Duplicates and late arrivals count as
+    /// received but do not move the echo back.
+    pub fn stamp(&mut self, seq: u32, timestamp_ms: u32, now: Instant) {
+        self.quality.received = self.quality.received.wrapping_add(1);
+        if let Some(last) = &self.last {
+            let gap = seq.wrapping_sub(last.seq);
+            if gap == 0 || gap > u32::MAX / 2 {
+                return;
+            }
+            if gap <= MAX_GAP {
+                self.quality.lost = self.quality.lost.wrapping_add(gap - 1);
+                let transit = (now - last.received).as_secs_f32() * 1000.0
+                    - timestamp_ms.wrapping_sub(last.timestamp_ms) as f32;
+                self.quality.jitter_ms += (transit.abs() - self.quality.jitter_ms) / 16.0;
+            }
+        }
+        self.last = Some(Stamp {
+            seq,
+            timestamp_ms,
+            received: now,
+        });
+    }
+
+    pub fn quality(&self) -> Option<LinkQuality> {
+        self.last.as_ref().map(|_| self.quality)
+    }
+
+    /// The next ack, `None` before the first stamp. Transports send one
+    /// every [`ACK_INTERVAL`].
+    pub fn ack(&mut self, now: Instant) -> Option<Ack> {
+        let last = self.last.as_ref()?;
+        self.acks = self.acks.wrapping_add(1);
+        Some(Ack {
+            seq: self.acks,
+            echo_seq: last.seq,
+            echo_timestamp_ms: last.timestamp_ms,
+            delay_ms: (now - last.received).as_millis() as u32,
+            received: self.quality.received,
+            lost: self.quality.lost,
+            jitter_ms: self.quality.jitter_ms,
+        })
+    }
+
+    /// The link quality every [`LOG_INTERVAL`], for the vehicle log.
+    pub fn report(&mut self, now: Instant) -> Option<LinkQuality> {
+        if now - self.logged < LOG_INTERVAL {
+            return None;
+        }
+        self.logged = now;
+        self.quality()
+    }
+}
+
+/// Link quality as seen by the topside.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct RoundTrip {
+    /// Latest round trip time.
+    pub rtt_ms: f32,
+    /// Smoothed variation of the round trip time.
+    pub jitter_ms: f32,
+    /// Fraction of messages to the vehicle that never arrived.
+    pub uplink_loss: f32,
+    /// Fraction of acks from the vehicle that never arrived.
+    pub downlink_loss: f32,
+    acks: u32,
+    last_ack: Option<u32>,
+    missed_acks: u32,
+}
+
+impl RoundTrip {
+    /// Updates from an ack, `now_ms` on the clock used for the stamps.
+    pub fn ack(&mut self, ack: &Ack, now_ms: u32) {
+        let rtt = now_ms
+            .wrapping_sub(ack.echo_timestamp_ms)
+            .saturating_sub(ack.delay_ms) as f32;
+        if let Some(last) = self.last_ack {
+            let gap = ack.seq.wrapping_sub(last);
+            if gap == 0 || gap > u32::MAX / 2 {
+                return;
+            }
+            if gap <= MAX_GAP {
+                self.missed_acks += gap - 1;
+            }
+            self.jitter_ms += ((rtt - self.rtt_ms).abs() - self.jitter_ms) / 16.0;
+        }
+        self.last_ack = Some(ack.seq);
+        self.acks += 1;
+        self.rtt_ms = rtt;
+        self.uplink_loss = loss(ack.lost, ack.received);
+        self.downlink_loss = loss(self.missed_acks, self.acks);
+    }
+}
+
+fn loss(lost: u32, received: u32) -> f32 {
+    let total = lost as f32 + received as f32;
+    if total > 0.0 {
+        lost as f32 / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn counts_lost_stamps() {
+        let start = Instant::now();
+        let mut monitor = LinkMonitor::default();
+        assert_eq!(monitor.quality(), None);
+        for seq in [1, 2, 4, 5, 9, 10] {
+            monitor.stamp(seq, seq * 20, at(start, seq as u64 * 20));
+        }
+        let quality = monitor.quality().unwrap();
+        assert_eq!(quality.received, 6);
+        assert_eq!(quality.lost, 4);
+        assert_eq!(quality.jitter_ms, 0.0);
+    }
+
+    #[test]
+    fn late_and_restarted_stamps_do_not_count_as_lost() {
+        let start = Instant::now();
+        let mut monitor = LinkMonitor::default();
+        monitor.stamp(10, 200, at(start, 200));
+        // Duplicate and late arrival.
+        monitor.stamp(10, 200, at(start, 201));
+        monitor.stamp(8, 160, at(start, 202));
+        // A client that restarted its counters.
+        monitor.stamp(50_000, 0, at(start, 220));
+        monitor.stamp(50_001, 20, at(start, 240));
+        let quality = monitor.quality().unwrap();
+        assert_eq!(quality.received, 5);
+        assert_eq!(quality.lost, 0);
+        let ack = monitor.ack(at(start, 250)).unwrap();
+        assert_eq!(ack.echo_seq, 50_001);
+        assert_eq!(ack.echo_timestamp_ms, 20);
+        assert_eq!(ack.delay_ms, 10);
+    }
+
+    #[test]
+    fn jitter_follows_transit_variation() {
+        let start = Instant::now();
+        let mut monitor = LinkMonitor::default();
+        // Sent every 20ms, arriving alternately 0 and 8ms late.
+        for seq in 0..200u32 {
+            let delay = if seq % 2 == 0 { 0 } else { 8 };
+            monitor.stamp(seq, seq * 20, at(start, seq as u64 * 20 + delay));
+        }
+        let jitter = monitor.quality().unwrap().jitter_ms;
+        assert!((jitter - 8.0).abs() < 0.1, "jitter {}", jitter);
+    }
+
+    #[test]
+    fn acks_count_up() {
+        let start = Instant::now();
+        let mut monitor = LinkMonitor::default();
+        assert_eq!(monitor.ack(start), None);
+        monitor.stamp(1, 0, start);
+        assert_eq!(monitor.ack(start).unwrap().seq, 1);
+        assert_eq!(monitor.ack(start).unwrap().seq, 2);
+    }
+
+    fn ack(seq: u32, echo_timestamp_ms: u32, received: u32, lost: u32) -> Ack {
+        Ack {
+            seq,
+            echo_seq: seq,
+            echo_timestamp_ms,
+            delay_ms: 5,
+            received,
+            lost,
+            jitter_ms: 0.0,
+        }
+    }
+
+    #[test]
+    fn round_trip_from_acks() {
+        let mut round_trip = RoundTrip::default();
+        round_trip.ack(&ack(1, 1000, 9, 1), 1045);
+        assert_eq!(round_trip.rtt_ms, 40.0);
+        assert_eq!(round_trip.jitter_ms, 0.0);
+        assert_eq!(round_trip.uplink_loss, 0.1);
+        assert_eq!(round_trip.downlink_loss, 0.0);
+
+        // Ack 2 and 3 never arrive, 4 is 16ms slower.
+        round_trip.ack(&ack(4, 1300, 18, 2), 1361);
+        assert_eq!(round_trip.rtt_ms, 56.0);
+        assert_eq!(round_trip.jitter_ms, 1.0);
+        assert_eq!(round_trip.uplink_loss, 0.1);
+        assert_eq!(round_trip.downlink_loss, 0.5);
+
+        // A late duplicate changes nothing.
+        let before = round_trip;
+        round_trip.ack(&ack(3, 1200, 17, 2), 1400);
+        assert_eq!(round_trip, before);
+    }
+}
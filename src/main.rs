@@ -1,3 +1,182 @@
-fn main() {
-    println!("Hello!");
-}
\ No newline at end of file
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+
+use finale::config::Config;
+use finale::estimator::Calibration;
+use finale::hardware::{Ads1115, Imu, Ina219, LeakPins, Pwm, Sht31};
+use finale::http;
+use finale::pilot::{self, PilotLink};
+use finale::recorder::{Cycle, Recorder, Snapshot, Start};
+use finale::sensors::Sensors;
+use finale::telemetry::{LinkTelemetry, Telemetry};
+use finale::udp;
+use finale::vehicle::Vehicle;
+
+use std::env;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config_path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "finale.json".to_string());
+    let config = Config::load(&config_path)?;
+
+    let listener = TcpListener::bind(&config.listen).await?;
+    println!("listening on {}", config.listen);
+
+    let mut pwm = Pwm::new(&config.i2c_bus, config.pwm_prescale)?;
+    for thruster in &config.thrusters {
+        pwm.set_pulse(thruster.channel, thruster.neutral)?;
+    }
+    thread::sleep(Duration::from_millis(config.esc_arm_ms));
+    println!("motors enabled");
+
+    let mut imu = Imu::new(&config.i2c_bus)?;
+    let mut samples = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(config.imu.calibration_ms) {
+        samples.push(imu.read()?);
+        thread::sleep(Duration::from_millis(50));
+    }
+    let calibration = Calibration::from_samples(&samples);
+    println!("mpu calibrated: {:?}", calibration);
+
+    let leak_pins = LeakPins::new(&config.leak.sensors)?;
+    let mut ina219 = match &config.power.ina219 {
+        Some(ina) => Some(Ina219::new(&config.i2c_bus, ina)?),
+        None => None,
+    };
+    let mut ads1115 = match &config.ads1115 {
+        Some(ads) => Some(Ads1115::new(&config.i2c_bus, ads)?),
+        None => None,
+    };
+    let mut sht31 = match config.thermal.sht31_address {
+        Some(address) => Some(Sht31::new(&config.i2c_bus, address)?),
+        None => None,
+    };
+
+    let (tx, rx) = watch::channel(None);
+    let (command_tx, mut commands) = mpsc::unbounded_channel();
+    let link_timeout = Duration::from_millis(config.link_timeout_ms);
+    let link = PilotLink::new(tx, command_tx, link_timeout);
+    tokio::spawn(pilot::serve(listener, link.clone()));
+    if let Some(address) = &config.udp_listen {
+        let socket = UdpSocket::bind(address).await?;
+        println!("listening for udp on {}", address);
+        tokio::spawn(udp::serve(socket, link.clone(), link_timeout));
+    }
+
+    let (telemetry_tx, telemetry_rx) = watch::channel(Telemetry::default());
+    if let Some(address) = &config.http_listen {
+        let http_listener = std::net::TcpListener::bind(address)?;
+        println!("http telemetry on {}", address);
+        tokio::spawn(http::serve(http_listener, telemetry_rx, link.clone()));
+    }
+
+    let mut vehicle = Vehicle::new(&config, calibration);
+    // Parameters are written by a task of their own so a slow SD card never
+    // stalls the loop. Changes made while a save runs are saved together.
+    let (save_tx, mut save_rx) = watch::channel(vehicle.config().clone());
+    tokio::spawn(async move {
+        while save_rx.changed().await.is_ok() {
+            let config = save_rx.borrow_and_update().clone();
+            let path = config_path.clone();
+            let saved =
+                tokio::task::spawn_blocking(move || config.save(&path).map_err(|e| e.to_string()))
+                    .await;
+            match saved {
+                Ok(Ok(())) => println!("parameters saved to {}", config_path),
+                Ok(Err(e)) => println!("failed to save parameters: {}", e),
+                Err(e) => println!("failed to save parameters: {}", e),
+            }
+        }
+    });
+    let mut recorder = Recorder::start(&config.recorder, Start::new(&config, calibration))?;
+    let mut next_event = 0;
+    let period = Duration::from_secs_f32(1.0 / config.loop_hz);
+    let mut interval = time::interval(period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let mut last_tick = Instant::now();
+    println!("everything is initialized");
+
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        let dt = (now - last_tick).as_secs_f32();
+        last_tick = now;
+
+        let mut applied = Vec::new();
+        while let Ok(command) = commands.try_recv() {
+            vehicle.handle(&command);
+            applied.push(command);
+        }
+
+        let state = *rx.borrow();
+        let pilot = match state {
+            Some((received, input)) if received.elapsed() < link_timeout => Some(input),
+            _ => None,
+        };
+        let link_telemetry = LinkTelemetry {
+            connected: pilot.is_some(),
+            last_packet_ms: state.map(|(received, _)| received.elapsed().as_millis() as u64),
+            clients: link.clients(),
+        };
+
+        let sensors = Sensors {
+            imu: imu.read()?,
+            leak: leak_pins.read(),
+            // A failed read shows up as a missing value, the vehicle raises
+            // an alarm instead of stopping the loop.
+            power: ina219.as_mut().and_then(|ina| ina.read().ok()),
+            adc: match (&mut ads1115, &config.ads1115) {
+                (Some(ads), Some(ads_config)) => ads
+                    .read()
+                    .unwrap_or_else(|_| vec![None; ads_config.channels.len()]),
+                _ => Vec::new(),
+            },
+            climate: sht31.as_mut().and_then(|sht| sht.read().ok()),
+            imu_temperature: imu.temperature().ok(),
+        };
+        let outputs = vehicle.step(pilot.as_ref(), &sensors, dt);
+        if vehicle.take_config_change() {
+            save_tx.send_replace(vehicle.config().clone());
+        }
+
+        telemetry_tx.send_replace(Telemetry::new(&vehicle, &sensors, &outputs, link_telemetry));
+        if let Some(recorder) = recorder.as_mut() {
+            let events: Vec<_> = vehicle.events().since(next_event).cloned().collect();
+            next_event = events.last().map_or(next_event, |event| event.seq + 1);
+            recorder.record(Cycle {
+                time: vehicle.time(),
+                dt,
+                armed: vehicle.armed(),
+                pilot,
+                commands: applied,
+                sensors: sensors.clone(),
+                attitude: vehicle.attitude(),
+                internals: *vehicle.internals(),
+                outputs: outputs.clone(),
+                events,
+            });
+            if recorder.wants_snapshot() {
+                recorder.snapshot(Snapshot::new(vehicle.config(), vehicle.state()));
+            }
+        }
+
+        for (thruster, pulse) in config.thrusters.iter().zip(&outputs.thrusters) {
+            pwm.set_pulse(thruster.channel, *pulse)?;
+        }
+        for (light, pulse) in config.lights.iter().zip(&outputs.lights) {
+            pwm.set_pulse(light.channel, *pulse)?;
+        }
+        for (servo, pulse) in config.servos.iter().zip(&outputs.servos) {
+            if let Some(pulse) = pulse {
+                pwm.set_pulse(servo.channel, *pulse)?;
+            }
+        }
+    }
+}
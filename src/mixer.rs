@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{PowerBudgetConfig, ThrusterConfig};
+
+/// Force and torque request for the whole vehicle, each axis in [-1, 1].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Wrench {
+    pub surge: f32,
+    pub sway: f32,
+    pub heave: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl Wrench {
+    pub fn as_array(&self) -> [f32; 6] {
+        [
+            self.surge, self.sway, self.heave, self.roll, self.pitch, self.yaw,
+        ]
+    }
+}
+
+/// What the output stage did in the last mix.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MixReport {
+    /// Estimated total thruster current in amps, after limiting.
+    pub estimated_current: f32,
+    /// Scale applied by the power budget, 1 when it is not limiting.
+    pub budget_scale: f32,
+}
+
+impl MixReport {
+    pub fn budget_limited(&self) -> bool {
+        self.budget_scale < 1.0
+    }
+}
+
+impl Default for MixReport {
+    fn default() -> Self {
+        MixReport {
+            estimated_current: 0.0,
+            budget_scale: 1.0,
+        }
+    }
+}
+
+/// Turns a wrench into a PCA9685 pulse for every thruster.
+pub struct Mixer {
+    thrusters: Vec<ThrusterConfig>,
+    thrust_limit: f32,
+    budget: PowerBudgetConfig,
+    report: MixReport,
+}
+
+impl Mixer {
+    pub fn new(thrusters: Vec<ThrusterConfig>, budget: PowerBudgetConfig) -> Self {
+        Mixer {
+            thrusters,
+            thrust_limit: 1.0,
+            budget,
+            report: MixReport::default(),
+        }
+    }
+
+    /// Scales every thruster offset, 1 is full thrust.
+    pub fn set_thrust_limit(&mut self, limit: f32) {
+        self.thrust_limit = limit.clamp(0.0, 1.0);
+    }
+
+    pub fn thrust_limit(&self) -> f32 {
+        self.thrust_limit
+    }
+
+    pub fn report(&self) -> MixReport {
+        self.report
+    }
+
+    pub fn thrusters(&self) -> &[ThrusterConfig] {
+        &self.thrusters
+    }
+
+    /// Pulse for every thruster at rest.
+    pub fn neutral(&mut self) -> Vec<u16> {
+        self.report = MixReport::default();
+        self.thrusters.iter().map(|t| t.neutral).collect()
+    }
+
+    pub fn mix(&mut self, wrench: &Wrench) -> Vec<u16> {
+        let axes = wrench.as_array();
+        // Command of every thruster as a fraction of its full offset.
+        let commands: Vec<f32> = self
+            .thrusters
+            .iter()
+            .map(|t| {
+                let offset: f32 = t.mix.iter().zip(axes.iter()).map(|(m, a)| m * a).sum();
+                let offset = (offset * self.thrust_limit).clamp(-t.max_offset, t.max_offset);
+                offset / t.max_offset
+            })
+            .collect();
+
+        let (scale, estimated_current) = self.budget_scale(&commands);
+        self.report = MixReport {
+            estimated_current,
+            budget_scale: scale,
+        };
+
+        self.thrusters
+            .iter()
+            .zip(commands)
+            .map(|(t, command)| (t.neutral as f32 + command * scale * t.max_offset).round() as u16)
+            .collect()
+    }
+
+    /// Total current the thrusters would draw at these commands.
+    fn estimate_current(&self, commands: &[f32], scale: f32) -> f32 {
+        commands
+            .iter()
+            .map(|command| self.budget.current(command.abs() * scale))
+            .sum()
+    }
+
+    /// Largest common scale keeping the estimated current within budget, and
+    /// the current at that scale.
+    fn budget_scale(&self, commands: &[f32]) -> (f32, f32) {
+        let full = self.estimate_current(commands, 1.0);
+        if !self.budget.enabled || full <= self.budget.max_current {
+            return (1.0, full);
+        }
+        // The curve is monotonic, so bisect on the scale.
+        let (mut low, mut high) = (0.0f32, 1.0f32);
+        for _ in 0..20 {
+            let mid = (low + high) / 2.0;
+            if self.estimate_current(commands, mid) > self.budget.max_current {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        (low, self.estimate_current(commands, low))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thruster(name: &str, mix: [f32; 6]) -> ThrusterConfig {
+        ThrusterConfig {
+            name: name.to_string(),
+            channel: 0,
+            mix,
+            neutral: 300,
+            max_offset: 100.0,
+        }
+    }
+
+    /// Two horizontal thrusters sharing surge and yaw, one vertical.
+    fn mixer(budget: PowerBudgetConfig) -> Mixer {
+        Mixer::new(
+            vec![
+                thruster("left", [100.0, 0.0, 0.0, 0.0, 0.0, 50.0]),
+                thruster("right", [100.0, 0.0, 0.0, 0.0, 0.0, -50.0]),
+                thruster("vertical", [0.0, 0.0, 100.0, 0.0, 0.0, 0.0]),
+            ],
+            budget,
+        )
+    }
+
+    fn wrench(surge: f32, heave: f32, yaw: f32) -> Wrench {
+        Wrench {
+            surge,
+            heave,
+            yaw,
+            ..Wrench::default()
+        }
+    }
+
+    #[test]
+    fn current_curve_interpolates() {
+        let budget = PowerBudgetConfig::default();
+        assert_eq!(budget.current(0.0), 0.0);
+        assert!((budget.current(0.5) - 2.8).abs() < 1e-5);
+        assert!((budget.current(0.875) - 10.0).abs() < 1e-5);
+        assert_eq!(budget.current(2.0), 13.0);
+        let empty = PowerBudgetConfig {
+            current_curve: Vec::new(),
+            ..PowerBudgetConfig::default()
+        };
+        assert_eq!(empty.current(1.0), 0.0);
+    }
+
+    #[test]
+    fn mixes_and_clamps_each_thruster() {
+        let mut mixer = mixer(PowerBudgetConfig {
+            enabled: false,
+            ..PowerBudgetConfig::default()
+        });
+        assert_eq!(mixer.mix(&wrench(0.5, -0.25, 0.2)), vec![360, 340, 275]);
+        assert_eq!(mixer.mix(&wrench(1.0, 0.0, 1.0)), vec![400, 350, 300]);
+        mixer.set_thrust_limit(0.5);
+        assert_eq!(mixer.mix(&wrench(1.0, 1.0, 0.0)), vec![350, 350, 350]);
+        assert!(!mixer.report().budget_limited());
+    }
+
+    #[test]
+    fn budget_scales_all_thrusters_together() {
+        let mut mixer = mixer(PowerBudgetConfig::default());
+        // 39A at full command on all three.
+        let pulses = mixer.mix(&wrench(1.0, 1.0, 0.0));
+        let report = mixer.report();
+        assert!(report.budget_limited());
+        assert!(report.estimated_current <= 25.0);
+        assert!(report.estimated_current > 24.9);
+        // 25A / 3 is reached at 0.75 + 0.25 * (8.33 - 7) / (13 - 7).
+        assert!((report.budget_scale - 0.80556).abs() < 1e-4);
+        assert_eq!(pulses, vec![381, 381, 381]);
+
+        // Within budget nothing changes.
+        mixer.mix(&wrench(0.5, 0.5, 0.0));
+        assert_eq!(mixer.report().budget_scale, 1.0);
+        assert!((mixer.report().estimated_current - 8.4).abs() < 1e-4);
+        mixer.neutral();
+        assert_eq!(mixer.report(), MixReport::default());
+    }
+}
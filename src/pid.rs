@@ -0,0 +1,379 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::PidConfig;
+
+/// What the controller sees in one cycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PidInput {
+    pub setpoint: f32,
+    pub measurement: f32,
+    /// Rate of the measurement when a sensor provides it (gyro), used instead
+    /// of differentiating the measurement.
+    pub rate: Option<f32>,
+    /// Added to the output before clamping.
+    pub feed_forward: f32,
+    /// Leaves the integral where it is for this cycle, apart from the pull
+    /// back while saturated.
+    pub hold_integral: bool,
+}
+
+impl PidInput {
+    pub fn new(setpoint: f32, measurement: f32) -> Self {
+        PidInput {
+            setpoint,
+            measurement,
+            rate: None,
+            feed_forward: 0.0,
+            hold_integral: false,
+        }
+    }
+
+    pub fn rate(mut self, rate: f32) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+
+    pub fn feed_forward(mut self, feed_forward: f32) -> Self {
+        self.feed_forward = feed_forward;
+        self
+    }
+
+    pub fn hold_integral(mut self, hold: bool) -> Self {
+        self.hold_integral = hold;
+        self
+    }
+}
+
+/// Contribution of each term to the last output, kept for logging.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PidTerms {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    pub feed_forward: f32,
+    pub output: f32,
+    pub saturated: bool,
+}
+
+/// PID controller with an explicit time step.
+///
+/// - the proportional term acts on `setpoint_weight * setpoint - measurement`,
+/// - the derivative acts on the measurement only, through a first order
+///   low-pass filter, so setpoint steps do not kick the output,
+/// - the output is clamped and the integrator is pulled back by the amount of
+///   clamping (back-calculation), so it does not wind up while saturated,
+/// - [`Pid::track`] keeps the integrator aligned with an output applied by
+///   someone else, so handing control to the loop is bumpless.
+#[derive(Debug, Clone)]
+pub struct Pid {
+    config: PidConfig,
+    integral: f32,
+    derivative: f32,
+    previous_measurement: Option<f32>,
+    terms: PidTerms,
+}
+
+impl Pid {
+    pub fn new(config: PidConfig) -> Self {
+        Pid {
+            config,
+            integral: 0.0,
+            derivative: 0.0,
+            previous_measurement: None,
+            terms: PidTerms::default(),
+        }
+    }
+
+    pub fn config(&self) -> &PidConfig {
+        &self.config
+    }
+
+    /// Changes the gains without resetting the controller state.
+    pub fn set_config(&mut self, config: PidConfig) {
+        self.config = config;
+        self.integral = self
+            .integral
+            .clamp(self.config.output_min, self.config.output_max);
+    }
+
+    pub fn terms(&self) -> PidTerms {
+        self.terms
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.previous_measurement = None;
+        self.terms = PidTerms::default();
+    }
+
+    /// Follows an output that was applied while the loop was not in charge.
+    pub fn track(&mut self, output: f32, input: PidInput) {
+        let p = self.config.kp * (self.config.setpoint_weight * input.setpoint - input.measurement);
+        if self.config.ki != 0.0 {
+            self.integral = (output - p - input.feed_forward)
+                .clamp(self.config.output_min, self.config.output_max);
+        }
+        self.derivative = 0.0;
+        self.previous_measurement = Some(input.measurement);
+        self.terms = PidTerms {
+            p,
+            i: self.integral,
+            d: 0.0,
+            feed_forward: input.feed_forward,
+            output,
+            saturated: false,
+        };
+    }
+
+    pub fn update(&mut self, input: PidInput, dt: f32) -> f32 {
+        if dt <= 0.0 {
+            return self.terms.output;
+        }
+        let config = &self.config;
+
+        let rate = match (input.rate, self.previous_measurement) {
+            (Some(rate), _) => rate,
+            (None, Some(previous)) => (input.measurement - previous) / dt,
+            (None, None) => 0.0,
+        };
+        self.previous_measurement = Some(input.measurement);
+        let alpha = if config.derivative_filter > 0.0 {
+            dt / (config.derivative_filter + dt)
+        } else {
+            1.0
+        };
+        self.derivative += alpha * (rate - self.derivative);
+
+        let error = input.setpoint - input.measurement;
+        let p = config.kp * (config.setpoint_weight * input.setpoint - input.measurement);
+        let d = -config.kd * self.derivative;
+        let i = self.integral;
+        let unclamped = p + i + d + input.feed_forward;
+        let output = unclamped.clamp(config.output_min, config.output_max);
+
+        let integrated = if input.hold_integral {
+            0.0
+        } else {
+            config.ki * error
+        };
+        self.integral += (integrated + config.tracking_gain * (output - unclamped)) * dt;
+
+        self.terms = PidTerms {
+            p,
+            i,
+            d,
+            feed_forward: input.feed_forward,
+            output,
+            saturated: output != unclamped,
+        };
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    /// First order plant, like the vertical speed of the vehicle:
+    /// tau * dv/dt = gain * u - v + disturbance.
+    struct FirstOrder {
+        tau: f32,
+        gain: f32,
+        disturbance: f32,
+        value: f32,
+    }
+
+    impl FirstOrder {
+        fn new(disturbance: f32) -> Self {
+            FirstOrder {
+                tau: 0.5,
+                gain: 1.0,
+                disturbance,
+                value: 0.0,
+            }
+        }
+
+        fn step(&mut self, u: f32) -> f32 {
+            self.value += (self.gain * u - self.value + self.disturbance) / self.tau * DT;
+            self.value
+        }
+    }
+
+    /// Inertia with drag, like the heading of the vehicle driven by the rear
+    /// thruster: dx/dt = v, dv/dt = gain * u - drag * v.
+    struct Inertia {
+        gain: f32,
+        drag: f32,
+        position: f32,
+        velocity: f32,
+    }
+
+    impl Inertia {
+        fn step(&mut self, u: f32) -> f32 {
+            self.velocity += (self.gain * u - self.drag * self.velocity) * DT;
+            self.position += self.velocity * DT;
+            self.position
+        }
+    }
+
+    /// Small deterministic noise source.
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5
+        }
+    }
+
+    fn pi_config() -> PidConfig {
+        PidConfig {
+            kp: 2.0,
+            ki: 4.0,
+            ..PidConfig::default()
+        }
+    }
+
+    /// Peak and final position of the inertia plant after a setpoint step,
+    /// and the largest integrator value seen on the way.
+    fn step_response(config: PidConfig, setpoint: f32, seconds: f32) -> (f32, f32, f32) {
+        let mut pid = Pid::new(config);
+        let mut plant = Inertia {
+            gain: 4.0,
+            drag: 2.0,
+            position: 0.0,
+            velocity: 0.0,
+        };
+        let mut peak = f32::MIN;
+        let mut integral: f32 = 0.0;
+        let mut y = 0.0;
+        for _ in 0..(seconds / DT) as usize {
+            let u = pid.update(PidInput::new(setpoint, y).rate(plant.velocity), DT);
+            integral = integral.max(pid.terms().i.abs());
+            y = plant.step(u);
+            peak = peak.max(y);
+        }
+        (peak, y, integral)
+    }
+
+    #[test]
+    fn tracks_setpoint_against_disturbance() {
+        let mut pid = Pid::new(pi_config());
+        let mut plant = FirstOrder::new(-0.2);
+        let mut y = 0.0;
+        for _ in 0..(10.0 / DT) as usize {
+            let u = pid.update(PidInput::new(0.3, y), DT);
+            y = plant.step(u);
+        }
+        assert!((y - 0.3).abs() < 0.005, "settled at {}", y);
+    }
+
+    #[test]
+    fn back_calculation_bounds_windup() {
+        let config = PidConfig {
+            kp: 1.5,
+            ki: 1.0,
+            kd: 0.5,
+            ..PidConfig::default()
+        };
+        let (peak_windup, _, integral_windup) = step_response(
+            PidConfig {
+                tracking_gain: 0.0,
+                ..config
+            },
+            3.0,
+            20.0,
+        );
+        let (peak, final_value, integral) = step_response(config, 3.0, 20.0);
+        assert!(peak < peak_windup);
+        assert!(integral < integral_windup);
+        assert!((final_value - 3.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn output_stays_within_limits() {
+        let mut pid = Pid::new(PidConfig {
+            kp: 5.0,
+            ki: 2.0,
+            kd: 1.0,
+            output_min: -0.4,
+            output_max: 0.6,
+            ..PidConfig::default()
+        });
+        let mut noise = Noise(3);
+        for _ in 0..1000 {
+            let u = pid.update(PidInput::new(0.0, 2.0 * noise.next()), DT);
+            assert!((-0.4..=0.6).contains(&u), "output {}", u);
+        }
+    }
+
+    #[test]
+    fn derivative_filter_reduces_noise_swing() {
+        let mut output_swing = Vec::new();
+        for filter in [0.0, 0.1] {
+            let mut pid = Pid::new(PidConfig {
+                kp: 1.0,
+                kd: 0.3,
+                derivative_filter: filter,
+                output_min: -100.0,
+                output_max: 100.0,
+                ..PidConfig::default()
+            });
+            let mut noise = Noise(7);
+            let mut swing: f32 = 0.0;
+            for _ in 0..500 {
+                let u = pid.update(PidInput::new(0.0, 0.01 * noise.next()), DT);
+                swing = swing.max(u.abs());
+            }
+            output_swing.push(swing);
+        }
+        assert!(output_swing[1] < output_swing[0] / 3.0);
+    }
+
+    #[test]
+    fn setpoint_weight_softens_first_reaction() {
+        let mut first_outputs = Vec::new();
+        for weight in [1.0, 0.5] {
+            let mut pid = Pid::new(PidConfig {
+                setpoint_weight: weight,
+                output_min: -10.0,
+                output_max: 10.0,
+                ..pi_config()
+            });
+            first_outputs.push(pid.update(PidInput::new(1.0, 0.0), DT));
+        }
+        assert!(first_outputs[1] < first_outputs[0]);
+    }
+
+    #[test]
+    fn feed_forward_shrinks_initial_error() {
+        let mut errors = Vec::new();
+        for feed_forward in [0.0, 0.4] {
+            let mut pid = Pid::new(pi_config());
+            let mut plant = FirstOrder::new(-0.4);
+            let mut y = 0.0;
+            let mut worst: f32 = 0.0;
+            for _ in 0..(3.0 / DT) as usize {
+                let u = pid.update(PidInput::new(0.0, y).feed_forward(feed_forward), DT);
+                y = plant.step(u);
+                worst = worst.max(y.abs());
+            }
+            errors.push(worst);
+        }
+        assert!(errors[1] < errors[0] / 10.0);
+    }
+
+    #[test]
+    fn tracking_hands_over_bumplessly() {
+        let mut pid = Pid::new(pi_config());
+        let manual = 0.35;
+        for _ in 0..50 {
+            pid.track(manual, PidInput::new(0.2, 0.1));
+        }
+        let first = pid.update(PidInput::new(0.2, 0.1), DT);
+        assert!((first - manual).abs() < 0.01);
+    }
+}
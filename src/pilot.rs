@@ -0,0 +1,406 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+
+use crate::control::{Arbiter, ClientInfo, ControlGroup, ControlStatus};
+use crate::link::{Ack, ACK_INTERVAL};
+use crate::mixer::Wrench;
+use crate::protocol::{self, Command, Message, Reply, Session, MAGIC};
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Size of a pilot packet: eleven little endian f32.
+pub const PACKET_LEN: usize = 44;
+
+/// One packet from the topside.
+///
+/// Layout of the floats: x (sway), y (surge), z (heave), rot (yaw), roll,
+/// pitch, then the raw pulses of the five servos b0..b4. A NaN or 0 servo
+/// pulse leaves that servo where it is, so clients using the servo commands
+/// can still send pilot packets.
+///
+/// The link turns the servo pulses into [`Command::SetServoPulse`] for the
+/// client holding the manipulator, the vehicle ignores `servos`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PilotInput {
+    pub wrench: Wrench,
+    pub servos: [f32; 5],
+}
+
+impl PilotInput {
+    pub fn decode(buf: &[u8; PACKET_LEN]) -> PilotInput {
+        let mut data_points = [0.0f32; 11];
+        for (i, value) in data_points.iter_mut().enumerate() {
+            *value = f32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        PilotInput {
+            wrench: Wrench {
+                sway: data_points[0],
+                surge: data_points[1],
+                heave: data_points[2],
+                yaw: data_points[3],
+                roll: data_points[4],
+                pitch: data_points[5],
+            },
+            servos: [
+                data_points[6],
+                data_points[7],
+                data_points[8],
+                data_points[9],
+                data_points[10],
+            ],
+        }
+    }
+
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let w = &self.wrench;
+        let data_points = [
+            w.sway,
+            w.surge,
+            w.heave,
+            w.yaw,
+            w.roll,
+            w.pitch,
+            self.servos[0],
+            self.servos[1],
+            self.servos[2],
+            self.servos[3],
+            self.servos[4],
+        ];
+        let mut buf = [0u8; PACKET_LEN];
+        for (i, value) in data_points.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Rejects non-finite axes and clamps them to [-1, 1]. NaN servo pulses
+    /// are allowed, they leave the servo alone.
+    pub fn validate(mut self) -> Result<PilotInput, String> {
+        let w = &mut self.wrench;
+        for axis in [
+            &mut w.surge,
+            &mut w.sway,
+            &mut w.heave,
+            &mut w.roll,
+            &mut w.pitch,
+            &mut w.yaw,
+        ] {
+            if !axis.is_finite() {
+                return Err(format!("axis value {} is not finite", axis));
+            }
+            *axis = axis.clamp(-1.0, 1.0);
+        }
+        if let Some(pulse) = self.servos.iter().find(|p| p.is_infinite()) {
+            return Err(format!("servo pulse {} is not finite", pulse));
+        }
+        Ok(self)
+    }
+}
+
+/// Latest pilot packet and when it arrived, `None` until the first one.
+pub type PilotState = Option<(Instant, PilotInput)>;
+
+/// Where every transport delivers what it receives, so they share the same
+/// validation, control arbitration and link watchdog.
+#[derive(Clone)]
+pub struct PilotLink {
+    tx: Arc<watch::Sender<PilotState>>,
+    commands: mpsc::UnboundedSender<Command>,
+    arbiter: Arc<Mutex<Arbiter>>,
+}
+
+impl PilotLink {
+    /// Control held by a client silent for longer than `timeout` can be
+    /// taken by others.
+    pub fn new(
+        tx: watch::Sender<PilotState>,
+        commands: mpsc::UnboundedSender<Command>,
+        timeout: Duration,
+    ) -> Self {
+        PilotLink {
+            tx: Arc::new(tx),
+            commands,
+            arbiter: Arc::new(Mutex::new(Arbiter::new(timeout))),
+        }
+    }
+
+    /// Registers a client, which gives up its control when dropped.
+    pub fn connect(&self, name: String) -> Client {
+        let id = self.arbiter().connect(name);
+        Client {
+            link: self.clone(),
+            id,
+            servos: [f32::NAN; 5],
+        }
+    }
+
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        self.arbiter().clients()
+    }
+
+    /// Forgets the last pilot input once its sender gave up flight, so the
+    /// vehicle does not keep flying on it until the link times out.
+    fn stop(&self) {
+        self.tx.send_replace(None);
+    }
+
+    fn arbiter(&self) -> MutexGuard<'_, Arbiter> {
+        self.arbiter.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// One connected client. Its messages only reach the actuators it controls.
+pub struct Client {
+    link: PilotLink,
+    id: u64,
+    /// Servo pulses last forwarded, so a stream of identical packets does
+    /// not turn into a stream of commands.
+    servos: [f32; 5],
+}
+
+impl Client {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn status(&self) -> ControlStatus {
+        self.link.arbiter().status(self.id)
+    }
+
+    /// Acknowledges the newest stamp, `None` if the client never sent one.
+    pub fn ack(&self) -> Option<Ack> {
+        self.link.arbiter().ack(self.id)
+    }
+
+    /// Applies a message, returning the new control status after a session
+    /// request. Pilot inputs from a client holding neither flight nor the
+    /// manipulator are dropped quietly, commands for a group it does not hold
+    /// are an error. Only commands and pilot inputs claim free groups for a
+    /// client that never said hello.
+    pub fn message(&mut self, message: Message) -> Result<Option<ControlStatus>, String> {
+        let mut arbiter = self.link.arbiter();
+        arbiter.seen(self.id);
+        match message {
+            Message::Session(session) => {
+                let flight = arbiter.owns(self.id, ControlGroup::Flight);
+                match session {
+                    Session::Hello(role) => arbiter.hello(self.id, role),
+                    Session::Take(groups) => arbiter.take(self.id, &groups),
+                    Session::Release(groups) => arbiter.release(self.id, &groups),
+                }
+                if flight && !arbiter.owns(self.id, ControlGroup::Flight) {
+                    self.link.stop();
+                }
+                Ok(Some(arbiter.status(self.id)))
+            }
+            Message::Stamp { seq, timestamp_ms } => {
+                arbiter.stamp(self.id, seq, timestamp_ms);
+                Ok(None)
+            }
+            Message::Command(command) => {
+                arbiter.claim(self.id);
+                let group = command.group();
+                if !arbiter.owns(self.id, group) {
+                    return Err(format!("{:?} needs control of {:?}", command, group));
+                }
+                let _ = self.link.commands.send(command);
+                Ok(None)
+            }
+            Message::Pilot(input) => {
+                let input = input.validate()?;
+                arbiter.claim(self.id);
+                let flight = arbiter.owns(self.id, ControlGroup::Flight);
+                let manipulator = arbiter.owns(self.id, ControlGroup::Manipulator);
+                drop(arbiter);
+
+                if manipulator {
+                    for (index, (last, pulse)) in
+                        self.servos.iter_mut().zip(input.servos).enumerate()
+                    {
+                        if !pulse.is_nan() && pulse != *last {
+                            *last = pulse;
+                            let index = index as u8;
+                            let _ = self
+                                .link
+                                .commands
+                                .send(Command::SetServoPulse { index, pulse });
+                        }
+                    }
+                } else {
+                    self.servos = [f32::NAN; 5];
+                }
+                if flight {
+                    // Servos went as commands above, the link state only
+                    // carries the wrench and feeds the watchdog.
+                    let input = PilotInput {
+                        wrench: input.wrench,
+                        servos: [f32::NAN; 5],
+                    };
+                    self.link.tx.send_replace(Some((Instant::now(), input)));
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let mut arbiter = self.link.arbiter();
+        let flight = arbiter.owns(self.id, ControlGroup::Flight);
+        arbiter.disconnect(self.id);
+        if flight {
+            self.link.stop();
+        }
+    }
+}
+
+/// Accepts topside connections and serves each of them concurrently.
+///
+/// A client that starts with [`MAGIC`] speaks framed messages, anything else
+/// is read as the legacy stream of 44 byte packets.
+pub async fn serve(listener: TcpListener, link: PilotLink) {
+    while let Ok((socket, addr)) = listener.accept().await {
+        let client = link.connect(format!("tcp {}", addr));
+        tokio::spawn(serve_connection(socket, client));
+    }
+}
+
+async fn serve_connection(mut socket: TcpStream, mut client: Client) {
+    let mut buf = [0u8; PACKET_LEN];
+    if socket.read_exact(&mut buf[..4]).await.is_err() {
+        return;
+    }
+    if buf[..4] == MAGIC {
+        serve_framed(socket, client).await;
+        return;
+    }
+    while socket.read_exact(&mut buf[4..]).await.is_ok() {
+        if let Err(e) = client.message(Message::Pilot(PilotInput::decode(&buf))) {
+            println!("client {}: dropped packet: {}", client.id(), e);
+        }
+        if socket.read_exact(&mut buf[..4]).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn serve_framed(socket: TcpStream, mut client: Client) {
+    // Acks are small, do not hold them back to coalesce.
+    let _ = socket.set_nodelay(true);
+    let (mut reader, writer) = socket.into_split();
+    let (replies, rx) = mpsc::unbounded_channel();
+    tokio::spawn(write_replies(writer, rx, client.link.clone(), client.id));
+    loop {
+        match protocol::read_message(&mut reader).await {
+            Ok(message) => match client.message(message) {
+                Ok(Some(status)) => {
+                    if replies.send(Reply::Control(status)).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("client {}: dropped message: {}", client.id(), e),
+            },
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                    println!("client {}: protocol error: {}", client.id(), e);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Writes the replies to one framed client, and an ack every
+/// [`ACK_INTERVAL`] once it stamps its messages.
+async fn write_replies(
+    mut writer: OwnedWriteHalf,
+    mut replies: mpsc::UnboundedReceiver<Reply>,
+    link: PilotLink,
+    id: u64,
+) {
+    let mut interval = time::interval(ACK_INTERVAL);
+    loop {
+        let reply = tokio::select! {
+            reply = replies.recv() => match reply {
+                Some(reply) => reply,
+                None => return,
+            },
+            _ = interval.tick() => match link.arbiter().ack(id) {
+                Some(ack) => Reply::Ack(ack),
+                None => continue,
+            },
+        };
+        if writer.write_all(&reply.encode()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::control::Role;
+
+    fn link() -> (PilotLink, mpsc::UnboundedReceiver<Command>) {
+        let (tx, _) = watch::channel(None);
+        let (commands, rx) = mpsc::unbounded_channel();
+        (PilotLink::new(tx, commands, Duration::from_secs(1)), rx)
+    }
+
+    #[test]
+    fn stamps_and_sessions_do_not_claim() {
+        let (link, _commands) = link();
+        let mut monitor = link.connect("monitor".to_string());
+        let mut pilot = link.connect("pilot".to_string());
+        let stamp = Message::Stamp {
+            seq: 1,
+            timestamp_ms: 0,
+        };
+        assert_eq!(monitor.message(stamp), Ok(None));
+        assert!(monitor.status().groups.is_empty());
+
+        assert_eq!(
+            pilot.message(Message::Pilot(PilotInput::default())),
+            Ok(None)
+        );
+        assert_eq!(pilot.status().groups, ControlGroup::ALL.to_vec());
+        assert!(monitor
+            .message(Message::Command(Command::SetArmed(true)))
+            .is_err());
+    }
+
+    #[test]
+    fn commands_claim_free_groups() {
+        let (link, mut commands) = link();
+        let mut lights = link.connect("lights".to_string());
+        lights
+            .message(Message::Session(Session::Hello(Role::CoPilot)))
+            .unwrap();
+        lights
+            .message(Message::Session(Session::Take(vec![ControlGroup::Lights])))
+            .unwrap();
+
+        let mut implicit = link.connect("implicit".to_string());
+        let arm = Command::SetArmed(true);
+        assert_eq!(implicit.message(Message::Command(arm.clone())), Ok(None));
+        assert_eq!(commands.try_recv(), Ok(arm));
+        assert_eq!(
+            implicit.status().groups,
+            vec![ControlGroup::Flight, ControlGroup::Manipulator]
+        );
+        let light = Command::SetLight {
+            index: 0,
+            percent: 50,
+        };
+        assert!(implicit.message(Message::Command(light)).is_err());
+        assert!(commands.try_recv().is_err());
+    }
+}
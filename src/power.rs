@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+
+use crate::alarms::{self, Alarms, Severity};
+use crate::config::{AdcChannelConfig, PowerConfig};
+use crate::sensors::Sensors;
+
+/// Battery state as seen by the vehicle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PowerState {
+    /// Filtered battery voltage, `None` without a voltage sensor.
+    pub voltage: Option<f32>,
+    /// Battery current in amps, `None` without an INA219.
+    pub current: Option<f32>,
+    /// Charge drawn since the daemon started.
+    pub consumed_mah: f32,
+    /// Scale applied to every thruster, 1 is no limit.
+    pub thrust_limit: f32,
+}
+
+impl Default for PowerState {
+    fn default() -> Self {
+        PowerState {
+            voltage: None,
+            current: None,
+            consumed_mah: 0.0,
+            thrust_limit: 1.0,
+        }
+    }
+}
+
+/// Watches battery voltage and current, warns when the pack runs low and
+/// lowers the thrust limit as the voltage sags so the ESCs do not brown out.
+pub struct PowerMonitor {
+    config: PowerConfig,
+    /// Index of the battery voltage in the ADS1115 channels.
+    voltage_channel: Option<usize>,
+    state: PowerState,
+    battery: Option<Severity>,
+}
+
+impl PowerMonitor {
+    pub fn new(config: PowerConfig, adc_channels: &[AdcChannelConfig]) -> Self {
+        let voltage_channel = config.voltage_channel.as_ref().and_then(|name| {
+            let index = adc_channels.iter().position(|c| &c.name == name);
+            if index.is_none() {
+                println!("power: no ADC channel named {}", name);
+            }
+            index
+        });
+        PowerMonitor {
+            config,
+            voltage_channel,
+            state: PowerState::default(),
+            battery: None,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    fn has_sensor(&self) -> bool {
+        self.config.ina219.is_some() || self.voltage_channel.is_some()
+    }
+
+    pub fn update(&mut self, sensors: &Sensors, dt: f32, alarms: &mut Alarms) -> PowerState {
+        let voltage = match (sensors.power, self.voltage_channel) {
+            (Some(reading), _) => Some(reading.voltage),
+            (None, Some(index)) => sensors.adc.get(index).copied().flatten(),
+            (None, None) => None,
+        };
+        let current = sensors.power.map(|reading| reading.current);
+
+        if self.has_sensor() && voltage.is_none() {
+            alarms.raise(
+                "power_sensor",
+                Severity::Warning,
+                "no battery voltage reading".to_string(),
+            );
+        } else {
+            alarms.clear("power_sensor");
+        }
+
+        self.state.voltage = match (self.state.voltage, voltage) {
+            (Some(filtered), Some(voltage)) => {
+                let alpha = dt / (self.config.voltage_filter_s + dt);
+                Some(filtered + alpha * (voltage - filtered))
+            }
+            (None, voltage) => voltage,
+            (filtered, None) => filtered,
+        };
+        self.state.current = current;
+        if let Some(current) = current {
+            self.state.consumed_mah += current * dt * 1000.0 / 3600.0;
+        }
+
+        self.state.thrust_limit = match self.state.voltage {
+            Some(voltage) => self.thrust_limit(voltage),
+            None => 1.0,
+        };
+
+        // Negated, the battery is bad when low.
+        self.battery = self.state.voltage.and_then(|voltage| {
+            alarms::severity(
+                self.battery,
+                -voltage,
+                -self.config.warn_voltage,
+                -self.config.critical_voltage,
+                self.config.voltage_hysteresis,
+            )
+        });
+        match (self.battery, self.state.voltage) {
+            (Some(Severity::Critical), Some(voltage)) => {
+                alarms.raise(
+                    "battery",
+                    Severity::Critical,
+                    format!("battery critical at {:.2}V", voltage),
+                );
+            }
+            (Some(Severity::Warning), Some(voltage)) => {
+                alarms.raise(
+                    "battery",
+                    Severity::Warning,
+                    format!("battery low at {:.2}V", voltage),
+                );
+            }
+            _ => {
+                alarms.clear("battery");
+            }
+        }
+        self.state
+    }
+
+    /// Linear ramp from no limit at `limit_start_voltage` down to
+    /// `min_thrust_limit` at `limit_end_voltage`.
+    fn thrust_limit(&self, voltage: f32) -> f32 {
+        let start = self.config.limit_start_voltage;
+        let end = self.config.limit_end_voltage;
+        if voltage >= start {
+            return 1.0;
+        }
+        if voltage <= end || start <= end {
+            return self.config.min_thrust_limit;
+        }
+        let fraction = (voltage - end) / (start - end);
+        self.config.min_thrust_limit + fraction * (1.0 - self.config.min_thrust_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Ina219Config;
+    use crate::sensors::PowerReading;
+
+    fn monitor() -> PowerMonitor {
+        PowerMonitor::new(
+            PowerConfig {
+                ina219: Some(Ina219Config {
+                    address: 0x40,
+                    shunt_ohms: 0.1,
+                }),
+                voltage_filter_s: 0.0,
+                ..PowerConfig::default()
+            },
+            &[],
+        )
+    }
+
+    fn reading(voltage: f32, current: f32) -> Sensors {
+        Sensors {
+            power: Some(PowerReading { voltage, current }),
+            ..Sensors::default()
+        }
+    }
+
+    #[test]
+    fn thrust_limit_ramps_down_with_voltage() {
+        let monitor = monitor();
+        assert_eq!(monitor.thrust_limit(16.8), 1.0);
+        assert_eq!(monitor.thrust_limit(13.6), 1.0);
+        assert!((monitor.thrust_limit(13.2) - 0.65).abs() < 1e-5);
+        assert_eq!(monitor.thrust_limit(12.8), 0.3);
+        assert_eq!(monitor.thrust_limit(10.0), 0.3);
+    }
+
+    #[test]
+    fn inverted_ramp_limits_below_start() {
+        let monitor = PowerMonitor::new(
+            PowerConfig {
+                limit_start_voltage: 12.0,
+                limit_end_voltage: 13.0,
+                ..PowerConfig::default()
+            },
+            &[],
+        );
+        assert_eq!(monitor.thrust_limit(12.5), 1.0);
+        assert_eq!(monitor.thrust_limit(11.9), 0.3);
+    }
+
+    #[test]
+    fn filters_voltage_and_counts_charge() {
+        let mut monitor = PowerMonitor::new(PowerConfig::default(), &[]);
+        let mut alarms = Alarms::default();
+        monitor.update(&reading(15.0, 10.0), 0.1, &mut alarms);
+        // A 1V sag for 0.1s moves the 1s filter by 1/11 of it.
+        let state = monitor.update(&reading(14.0, 36.0), 0.1, &mut alarms);
+        assert!((state.voltage.unwrap() - (15.0 - 1.0 / 11.0)).abs() < 1e-4);
+        assert_eq!(state.current, Some(36.0));
+        assert!((state.consumed_mah - 1.2777778).abs() < 1e-4);
+        assert!(alarms.active().is_empty());
+    }
+
+    #[test]
+    fn battery_alarms() {
+        let mut monitor = monitor();
+        let mut alarms = Alarms::default();
+        let state = monitor.update(&reading(13.0, 0.0), 0.1, &mut alarms);
+        assert_eq!(alarms.active()[0].severity, Severity::Critical);
+        assert!((state.thrust_limit - 0.475).abs() < 1e-5);
+        monitor.update(&reading(13.9, 0.0), 0.1, &mut alarms);
+        assert_eq!(alarms.active()[0].severity, Severity::Warning);
+        monitor.update(&Sensors::default(), 0.1, &mut alarms);
+        assert!(alarms.is_active("power_sensor"));
+    }
+
+    #[test]
+    fn battery_alarm_clears_after_recovering() {
+        let mut monitor = monitor();
+        let mut alarms = Alarms::default();
+        monitor.update(&reading(13.95, 0.0), 0.1, &mut alarms);
+        assert!(alarms.is_active("battery"));
+        // Recovering as the thrusters ease off is not enough.
+        monitor.update(&reading(14.1, 0.0), 0.1, &mut alarms);
+        assert!(alarms.is_active("battery"));
+        monitor.update(&reading(14.25, 0.0), 0.1, &mut alarms);
+        assert!(!alarms.is_active("battery"));
+    }
+}
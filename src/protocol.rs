@@ -0,0 +1,637 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::autotune::TuneAxis;
+use crate::control::{ControlGroup, ControlStatus, Role};
+use crate::link::Ack;
+use crate::pilot::{PilotInput, PACKET_LEN};
+use crate::stabilise::StabiliseMode;
+
+use std::io;
+
+/// Sent by a client right after connecting to switch from the legacy 44 byte
+/// packets to framed messages.
+pub const MAGIC: [u8; 4] = *b"ROV2";
+
+const TAG_PILOT: u8 = 0x01;
+const TAG_STABILISE: u8 = 0x02;
+const TAG_START_TUNE: u8 = 0x03;
+const TAG_ABORT_TUNE: u8 = 0x04;
+const TAG_CONFIRM_TUNE: u8 = 0x05;
+const TAG_ARM: u8 = 0x06;
+const TAG_LIGHT: u8 = 0x07;
+const TAG_SERVO: u8 = 0x08;
+const TAG_SERVO_PRESET: u8 = 0x09;
+const TAG_SERVO_PULSE: u8 = 0x0A;
+const TAG_GEAR: u8 = 0x0B;
+const TAG_SHIFT_GEAR: u8 = 0x0C;
+const TAG_HELLO: u8 = 0x10;
+const TAG_TAKE: u8 = 0x11;
+const TAG_RELEASE: u8 = 0x12;
+const TAG_STAMP: u8 = 0x13;
+
+// Sent by the vehicle.
+const TAG_CONTROL_STATUS: u8 = 0x81;
+const TAG_ACK: u8 = 0x82;
+
+/// Discrete request from the pilot, applied once by the control loop.
+///
+/// Its JSON form, used on the WebSocket, is externally tagged in snake case,
+/// e.g. `{"set_armed": true}` or `"abort_tune"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    SetStabilise(StabiliseMode),
+    /// Starts a relay auto-tune experiment on one axis.
+    StartTune(TuneAxis),
+    AbortTune,
+    /// Accepts (true) or discards the gains proposed by the last auto-tune.
+    ConfirmTune(bool),
+    /// Arms (true) or disarms the thrusters.
+    SetArmed(bool),
+    /// Sets a light brightness in percent, light 0xFF addresses all of them.
+    SetLight {
+        index: u8,
+        percent: u8,
+    },
+    /// Moves a servo to a position in degrees.
+    SetServo {
+        index: u8,
+        degrees: f32,
+    },
+    /// Applies a named servo preset.
+    ServoPreset(String),
+    /// Sets a raw servo pulse, as the servo fields of a pilot input do.
+    SetServoPulse {
+        index: u8,
+        pulse: f32,
+    },
+    /// Selects a speed gear by index.
+    SetGear(u8),
+    /// Moves up (positive) or down that many gears.
+    ShiftGear(i8),
+}
+
+impl Command {
+    /// Group a client must hold to send this command.
+    pub fn group(&self) -> ControlGroup {
+        match self {
+            Command::SetStabilise(_)
+            | Command::StartTune(_)
+            | Command::AbortTune
+            | Command::ConfirmTune(_)
+            | Command::SetArmed(_)
+            | Command::SetGear(_)
+            | Command::ShiftGear(_) => ControlGroup::Flight,
+            Command::SetLight { .. } => ControlGroup::Lights,
+            Command::SetServo { .. } | Command::ServoPreset(_) | Command::SetServoPulse { .. } => {
+                ControlGroup::Manipulator
+            }
+        }
+    }
+}
+
+/// Requests about the connection itself, answered by the link with a
+/// [`Reply::Control`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Session {
+    /// Declares the role of the client. Clients that never say hello are
+    /// pilots taking whatever groups are free.
+    Hello(Role),
+    Take(Vec<ControlGroup>),
+    Release(Vec<ControlGroup>),
+}
+
+/// One framed message: a tag byte, a little endian u16 payload length, then
+/// the payload. Unknown tags are skipped so older vehicles ignore newer
+/// messages.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Pilot(PilotInput),
+    Command(Command),
+    Session(Session),
+    /// Sequence number and client clock in milliseconds, both little endian
+    /// u32. Once a client stamps its messages the vehicle acknowledges it
+    /// with [`Reply::Ack`]. Datagrams carry the stamp in their header.
+    Stamp {
+        seq: u32,
+        timestamp_ms: u32,
+    },
+}
+
+/// Framed message sent back by the vehicle.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// Role and groups of the client after a session request.
+    Control(ControlStatus),
+    /// Echo of the newest stamp, sent periodically to clients that stamp
+    /// their messages.
+    Ack(Ack),
+}
+
+fn u32_at(payload: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(payload[index * 4..index * 4 + 4].try_into().unwrap())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn frame(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(3 + payload.len());
+    frame.push(tag);
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn f32_payload(index: u8, value: f32) -> Vec<u8> {
+    let mut payload = vec![index];
+    payload.extend_from_slice(&value.to_le_bytes());
+    payload
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Message::Pilot(input) => (TAG_PILOT, input.encode().to_vec()),
+            Message::Command(Command::SetStabilise(mode)) => (TAG_STABILISE, vec![mode.to_u8()]),
+            Message::Command(Command::StartTune(axis)) => (TAG_START_TUNE, vec![axis.to_u8()]),
+            Message::Command(Command::AbortTune) => (TAG_ABORT_TUNE, vec![]),
+            Message::Command(Command::ConfirmTune(accept)) => {
+                (TAG_CONFIRM_TUNE, vec![*accept as u8])
+            }
+            Message::Command(Command::SetArmed(armed)) => (TAG_ARM, vec![*armed as u8]),
+            Message::Command(Command::SetLight { index, percent }) => {
+                (TAG_LIGHT, vec![*index, *percent])
+            }
+            Message::Command(Command::SetServo { index, degrees }) => {
+                (TAG_SERVO, f32_payload(*index, *degrees))
+            }
+            Message::Command(Command::ServoPreset(name)) => {
+                (TAG_SERVO_PRESET, name.as_bytes().to_vec())
+            }
+            Message::Command(Command::SetServoPulse { index, pulse }) => {
+                (TAG_SERVO_PULSE, f32_payload(*index, *pulse))
+            }
+            Message::Command(Command::SetGear(gear)) => (TAG_GEAR, vec![*gear]),
+            Message::Command(Command::ShiftGear(step)) => (TAG_SHIFT_GEAR, vec![*step as u8]),
+            Message::Session(Session::Hello(role)) => (TAG_HELLO, vec![role.to_u8()]),
+            Message::Session(Session::Take(groups)) => {
+                (TAG_TAKE, vec![ControlGroup::to_mask(groups)])
+            }
+            Message::Session(Session::Release(groups)) => {
+                (TAG_RELEASE, vec![ControlGroup::to_mask(groups)])
+            }
+            Message::Stamp { seq, timestamp_ms } => {
+                let mut payload = seq.to_le_bytes().to_vec();
+                payload.extend_from_slice(&timestamp_ms.to_le_bytes());
+                (TAG_STAMP, payload)
+            }
+        };
+        frame(tag, &payload)
+    }
+
+    /// Decodes a payload, `None` for a tag this vehicle does not know.
+    pub fn decode(tag: u8, payload: &[u8]) -> io::Result<Option<Message>> {
+        let message = match tag {
+            TAG_PILOT => {
+                let buf: &[u8; PACKET_LEN] = payload
+                    .try_into()
+                    .map_err(|_| invalid(format!("pilot payload of {} bytes", payload.len())))?;
+                Message::Pilot(PilotInput::decode(buf))
+            }
+            TAG_STABILISE => {
+                let mode = payload
+                    .first()
+                    .and_then(|value| StabiliseMode::from_u8(*value))
+                    .ok_or_else(|| invalid(format!("bad stabilise payload {:?}", payload)))?;
+                Message::Command(Command::SetStabilise(mode))
+            }
+            TAG_START_TUNE => {
+                let axis = payload
+                    .first()
+                    .and_then(|value| TuneAxis::from_u8(*value))
+                    .ok_or_else(|| invalid(format!("bad tune payload {:?}", payload)))?;
+                Message::Command(Command::StartTune(axis))
+            }
+            TAG_ABORT_TUNE => Message::Command(Command::AbortTune),
+            TAG_CONFIRM_TUNE => {
+                let accept = payload
+                    .first()
+                    .ok_or_else(|| invalid("empty confirm payload".to_string()))?;
+                Message::Command(Command::ConfirmTune(*accept != 0))
+            }
+            TAG_ARM => {
+                let armed = payload
+                    .first()
+                    .ok_or_else(|| invalid("empty arm payload".to_string()))?;
+                Message::Command(Command::SetArmed(*armed != 0))
+            }
+            TAG_LIGHT => match payload {
+                [index, percent] => Message::Command(Command::SetLight {
+                    index: *index,
+                    percent: *percent,
+                }),
+                _ => return Err(invalid(format!("bad light payload {:?}", payload))),
+            },
+            TAG_SERVO => match payload {
+                [index, a, b, c, d] => Message::Command(Command::SetServo {
+                    index: *index,
+                    degrees: f32::from_le_bytes([*a, *b, *c, *d]),
+                }),
+                _ => return Err(invalid(format!("bad servo payload {:?}", payload))),
+            },
+            TAG_SERVO_PRESET => {
+                let name = String::from_utf8(payload.to_vec())
+                    .map_err(|_| invalid("servo preset name is not UTF-8".to_string()))?;
+                Message::Command(Command::ServoPreset(name))
+            }
+            TAG_SERVO_PULSE => match payload {
+                [index, a, b, c, d] => Message::Command(Command::SetServoPulse {
+                    index: *index,
+                    pulse: f32::from_le_bytes([*a, *b, *c, *d]),
+                }),
+                _ => return Err(invalid(format!("bad servo pulse payload {:?}", payload))),
+            },
+            TAG_GEAR => {
+                let gear = payload
+                    .first()
+                    .ok_or_else(|| invalid("empty gear payload".to_string()))?;
+                Message::Command(Command::SetGear(*gear))
+            }
+            TAG_SHIFT_GEAR => {
+                let step = payload
+                    .first()
+                    .ok_or_else(|| invalid("empty shift gear payload".to_string()))?;
+                Message::Command(Command::ShiftGear(*step as i8))
+            }
+            TAG_HELLO => {
+                let role = payload
+                    .first()
+                    .and_then(|value| Role::from_u8(*value))
+                    .ok_or_else(|| invalid(format!("bad hello payload {:?}", payload)))?;
+                Message::Session(Session::Hello(role))
+            }
+            TAG_TAKE | TAG_RELEASE => {
+                let mask = payload
+                    .first()
+                    .ok_or_else(|| invalid("empty control payload".to_string()))?;
+                let groups = ControlGroup::from_mask(*mask);
+                if tag == TAG_TAKE {
+                    Message::Session(Session::Take(groups))
+                } else {
+                    Message::Session(Session::Release(groups))
+                }
+            }
+            TAG_STAMP => {
+                if payload.len() != 8 {
+                    return Err(invalid(format!("stamp payload of {} bytes", payload.len())));
+                }
+                Message::Stamp {
+                    seq: u32_at(payload, 0),
+                    timestamp_ms: u32_at(payload, 1),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(message))
+    }
+}
+
+impl Reply {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Reply::Control(status) => frame(
+                TAG_CONTROL_STATUS,
+                &[status.role.to_u8(), ControlGroup::to_mask(&status.groups)],
+            ),
+            Reply::Ack(ack) => {
+                let mut payload = Vec::with_capacity(28);
+                for value in [
+                    ack.seq,
+                    ack.echo_seq,
+                    ack.echo_timestamp_ms,
+                    ack.delay_ms,
+                    ack.received,
+                    ack.lost,
+                ] {
+                    payload.extend_from_slice(&value.to_le_bytes());
+                }
+                payload.extend_from_slice(&ack.jitter_ms.to_le_bytes());
+                frame(TAG_ACK, &payload)
+            }
+        }
+    }
+
+    /// Decodes a payload, `None` for a tag this client does not know.
+    pub fn decode(tag: u8, payload: &[u8]) -> io::Result<Option<Reply>> {
+        let reply = match tag {
+            TAG_CONTROL_STATUS => match payload {
+                [role, mask] => {
+                    let role = Role::from_u8(*role)
+                        .ok_or_else(|| invalid(format!("bad role {}", role)))?;
+                    Reply::Control(ControlStatus {
+                        role,
+                        groups: ControlGroup::from_mask(*mask),
+                    })
+                }
+                _ => return Err(invalid(format!("bad control payload {:?}", payload))),
+            },
+            TAG_ACK => {
+                if payload.len() != 28 {
+                    return Err(invalid(format!("ack payload of {} bytes", payload.len())));
+                }
+                Reply::Ack(Ack {
+                    seq: u32_at(payload, 0),
+                    echo_seq: u32_at(payload, 1),
+                    echo_timestamp_ms: u32_at(payload, 2),
+                    delay_ms: u32_at(payload, 3),
+                    received: u32_at(payload, 4),
+                    lost: u32_at(payload, 5),
+                    jitter_ms: f32::from_le_bytes(payload[24..28].try_into().unwrap()),
+                })
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(reply))
+    }
+}
+
+/// Decodes a buffer holding whole frames, skipping unknown tags.
+pub fn decode_frames(mut data: &[u8]) -> io::Result<Vec<Message>> {
+    let mut messages = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(invalid(format!("truncated frame header {:?}", data)));
+        }
+        let len = u16::from_le_bytes([data[1], data[2]]) as usize;
+        let payload = data
+            .get(3..3 + len)
+            .ok_or_else(|| invalid(format!("frame of {} bytes is truncated", len)))?;
+        if let Some(message) = Message::decode(data[0], payload)? {
+            messages.push(message);
+        }
+        data = &data[3 + len..];
+    }
+    Ok(messages)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 3];
+    reader.read_exact(&mut header).await?;
+    let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok((header[0], payload))
+}
+
+/// Reads the next message this vehicle understands.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Message> {
+    loop {
+        let (tag, payload) = read_frame(reader).await?;
+        if let Some(message) = Message::decode(tag, &payload)? {
+            return Ok(message);
+        }
+    }
+}
+
+/// Reads the next reply this client understands.
+pub async fn read_reply<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Reply> {
+    loop {
+        let (tag, payload) = read_frame(reader).await?;
+        if let Some(reply) = Reply::decode(tag, &payload)? {
+            return Ok(reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mixer::Wrench;
+
+    fn pilot() -> PilotInput {
+        PilotInput {
+            wrench: Wrench {
+                surge: 0.5,
+                sway: -0.25,
+                heave: 1.0,
+                roll: 0.0,
+                pitch: -1.0,
+                yaw: 0.125,
+            },
+            servos: [1500.0, f32::NAN, 1000.0, 2000.0, 1234.5],
+        }
+    }
+
+    /// Pilot inputs hold NaN servo pulses, which never compare equal.
+    fn same_pilot(a: &PilotInput, b: &PilotInput) -> bool {
+        a.encode() == b.encode()
+    }
+
+    /// Encodes and decodes a message, alone and as a frame.
+    fn round_trip(message: Message) {
+        let frame = message.encode();
+        assert_eq!(
+            frame.len(),
+            3 + u16::from_le_bytes([frame[1], frame[2]]) as usize
+        );
+        let decoded = Message::decode(frame[0], &frame[3..]).unwrap();
+        assert_eq!(decoded.as_ref(), Some(&message));
+        assert_eq!(decode_frames(&frame).unwrap(), vec![message]);
+    }
+
+    fn assert_rejected(tag: u8, payloads: &[&[u8]]) {
+        for payload in payloads {
+            assert!(
+                Message::decode(tag, payload).is_err(),
+                "tag {:#x} accepted {:?}",
+                tag,
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_packet_round_trip() {
+        let input = pilot();
+        let packet = input.encode();
+        assert_eq!(packet.len(), PACKET_LEN);
+        assert_eq!(&packet[4..8], &0.5f32.to_le_bytes(), "surge is second");
+        assert!(same_pilot(&PilotInput::decode(&packet), &input));
+    }
+
+    #[test]
+    fn pilot_and_stabilise_round_trip() {
+        for mode in [
+            StabiliseMode::Off,
+            StabiliseMode::Level,
+            StabiliseMode::Attitude,
+            StabiliseMode::Rate,
+        ] {
+            round_trip(Message::Command(Command::SetStabilise(mode)));
+        }
+        let frame = Message::Pilot(pilot()).encode();
+        match Message::decode(frame[0], &frame[3..]).unwrap() {
+            Some(Message::Pilot(input)) => assert!(same_pilot(&input, &pilot())),
+            other => panic!("decoded {:?}", other),
+        }
+        assert_rejected(TAG_PILOT, &[&[0; PACKET_LEN - 1], &[0; PACKET_LEN + 1]]);
+        assert_rejected(TAG_STABILISE, &[&[], &[4], &[200]]);
+    }
+
+    #[test]
+    fn frames_decode_in_sequence_skipping_unknown_tags() {
+        let messages = vec![
+            Message::Command(Command::SetStabilise(StabiliseMode::Rate)),
+            Message::Command(Command::SetStabilise(StabiliseMode::Off)),
+        ];
+        let mut data = Vec::new();
+        for message in &messages {
+            data.extend(message.encode());
+            data.extend(frame(0x7F, &[1, 2, 3]));
+        }
+        assert_eq!(decode_frames(&data).unwrap(), messages);
+    }
+
+    #[test]
+    fn rejects_short_frames() {
+        let frame = Message::Command(Command::SetStabilise(StabiliseMode::Level)).encode();
+        assert!(decode_frames(&frame[..2]).is_err());
+        assert!(decode_frames(&frame[..3]).is_err());
+        let mut data = frame.clone();
+        data.extend_from_slice(&frame[..frame.len() - 1]);
+        assert!(decode_frames(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_messages_from_a_stream() {
+        let level = Message::Command(Command::SetStabilise(StabiliseMode::Level));
+        let rate = Message::Command(Command::SetStabilise(StabiliseMode::Rate));
+        let mut data = frame(0x7F, &[]);
+        data.extend(level.encode());
+        data.extend(rate.encode());
+        let mut reader = &data[..];
+        assert_eq!(read_message(&mut reader).await.unwrap(), level);
+        assert_eq!(read_message(&mut reader).await.unwrap(), rate);
+        let error = read_message(&mut reader).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut truncated = &level.encode()[..3];
+        assert!(read_message(&mut truncated).await.is_err());
+    }
+
+    #[test]
+    fn tune_commands_round_trip() {
+        for axis in [TuneAxis::Roll, TuneAxis::Pitch, TuneAxis::Yaw] {
+            round_trip(Message::Command(Command::StartTune(axis)));
+        }
+        round_trip(Message::Command(Command::AbortTune));
+        round_trip(Message::Command(Command::ConfirmTune(true)));
+        round_trip(Message::Command(Command::ConfirmTune(false)));
+        assert_rejected(TAG_START_TUNE, &[&[], &[9]]);
+        assert_rejected(TAG_CONFIRM_TUNE, &[&[]]);
+    }
+
+    #[test]
+    fn arm_command_round_trip() {
+        round_trip(Message::Command(Command::SetArmed(true)));
+        round_trip(Message::Command(Command::SetArmed(false)));
+        assert_rejected(TAG_ARM, &[&[]]);
+    }
+
+    #[test]
+    fn light_command_round_trip() {
+        round_trip(Message::Command(Command::SetLight {
+            index: 1,
+            percent: 75,
+        }));
+        round_trip(Message::Command(Command::SetLight {
+            index: crate::lights::Lights::ALL,
+            percent: 40,
+        }));
+        assert_rejected(TAG_LIGHT, &[&[], &[1]]);
+    }
+
+    #[test]
+    fn servo_commands_round_trip() {
+        round_trip(Message::Command(Command::SetServo {
+            index: 2,
+            degrees: -32.5,
+        }));
+        round_trip(Message::Command(Command::ServoPreset("stow".to_string())));
+        assert_rejected(TAG_SERVO, &[&[1, 0, 0, 0]]);
+        assert_rejected(TAG_SERVO_PRESET, &[&[0xFF, 0xFE]]);
+    }
+
+    #[test]
+    fn session_messages_round_trip() {
+        round_trip(Message::Command(Command::SetServoPulse {
+            index: 1,
+            pulse: 1750.0,
+        }));
+        for role in [Role::Pilot, Role::CoPilot, Role::Observer] {
+            round_trip(Message::Session(Session::Hello(role)));
+        }
+        round_trip(Message::Session(Session::Take(vec![
+            ControlGroup::Manipulator,
+            ControlGroup::Lights,
+        ])));
+        round_trip(Message::Session(Session::Release(vec![
+            ControlGroup::Flight,
+        ])));
+        assert_rejected(TAG_SERVO_PULSE, &[&[1, 0, 0, 0, 0, 0]]);
+        assert_rejected(TAG_HELLO, &[&[], &[9]]);
+        assert_rejected(TAG_TAKE, &[&[]]);
+        assert_rejected(TAG_RELEASE, &[&[]]);
+
+        let reply = Reply::Control(ControlStatus {
+            role: Role::Pilot,
+            groups: vec![ControlGroup::Flight, ControlGroup::Lights],
+        });
+        let frame = reply.encode();
+        assert_eq!(Reply::decode(frame[0], &frame[3..]).unwrap(), Some(reply));
+        assert!(Reply::decode(TAG_CONTROL_STATUS, &[9, 0]).is_err());
+    }
+
+    #[test]
+    fn stamps_and_acks_round_trip() {
+        round_trip(Message::Stamp {
+            seq: 70_000,
+            timestamp_ms: u32::MAX,
+        });
+        assert_rejected(TAG_STAMP, &[&[0; 7], &[0; 9]]);
+
+        let reply = Reply::Ack(Ack {
+            seq: 1,
+            echo_seq: 2,
+            echo_timestamp_ms: 3,
+            delay_ms: 4,
+            received: 5,
+            lost: 6,
+            jitter_ms: 7.5,
+        });
+        let frame = reply.encode();
+        assert_eq!(Reply::decode(frame[0], &frame[3..]).unwrap(), Some(reply));
+        assert!(Reply::decode(TAG_ACK, &[0; 27]).is_err());
+    }
+
+    #[test]
+    fn gear_commands_round_trip() {
+        round_trip(Message::Command(Command::SetGear(2)));
+        round_trip(Message::Command(Command::ShiftGear(-1)));
+        assert_eq!(
+            Message::Command(Command::SetGear(3)).encode(),
+            vec![TAG_GEAR, 1, 0, 3]
+        );
+        assert_eq!(
+            Message::Command(Command::ShiftGear(-1)).encode(),
+            vec![TAG_SHIFT_GEAR, 1, 0, 0xFF]
+        );
+        assert_rejected(TAG_GEAR, &[&[]]);
+        assert_rejected(TAG_SHIFT_GEAR, &[&[]]);
+    }
+}
@@ -0,0 +1,607 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, RecorderConfig};
+use crate::estimator::{Attitude, Calibration};
+use crate::events::Event;
+use crate::pilot::PilotInput;
+use crate::protocol::Command;
+use crate::sensors::Sensors;
+use crate::vehicle::{Internals, Outputs, VehicleState};
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// First bytes of every flight log, the last one is the format version.
+pub const MAGIC: [u8; 8] = *b"FINALOG\x01";
+
+/// Starts every frame so a reader can find the next one after damage.
+const SYNC: [u8; 2] = [0xF1, 0x7E];
+
+/// Sync word, little endian u32 payload length and CRC32 of the payload.
+const HEADER_LEN: usize = 10;
+
+/// Frames claiming more than this are taken for damage.
+const MAX_FRAME: usize = 1024 * 1024;
+
+/// Records waiting for the writer before new ones are dropped.
+const QUEUE: usize = 500;
+
+/// What the vehicle started with, written first in every file. Files after
+/// the first follow it with a [`Snapshot`], so each one can be analysed on
+/// its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Start {
+    /// Wall clock at startup, milliseconds since the Unix epoch. The Pi has
+    /// no real time clock, so this is only right once it has synced.
+    pub unix_ms: u64,
+    /// Configuration as JSON, which stays readable as fields are added.
+    pub config: String,
+    pub calibration: Calibration,
+}
+
+impl Start {
+    pub fn new(config: &Config, calibration: Calibration) -> Start {
+        Start {
+            unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            config: serde_json::to_string(config).unwrap_or_default(),
+            calibration,
+        }
+    }
+
+    pub fn config(&self) -> Result<Config, Box<dyn Error>> {
+        Ok(serde_json::from_str(&self.config)?)
+    }
+}
+
+/// Where the vehicle was when the recorder moved on to a new file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Parameters in use as JSON, with the gains tuned since startup.
+    pub config: String,
+    pub state: VehicleState,
+}
+
+impl Snapshot {
+    pub fn new(config: &Config, state: VehicleState) -> Snapshot {
+        Snapshot {
+            config: serde_json::to_string(config).unwrap_or_default(),
+            state,
+        }
+    }
+
+    pub fn config(&self) -> Result<Config, Box<dyn Error>> {
+        Ok(serde_json::from_str(&self.config)?)
+    }
+}
+
+/// One control cycle: what went in, what the controllers made of it and
+/// what went out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cycle {
+    /// Vehicle time in seconds, at the end of the cycle.
+    pub time: f64,
+    pub dt: f32,
+    pub armed: bool,
+    /// `None` while the link is down.
+    pub pilot: Option<PilotInput>,
+    /// Commands applied before the cycle ran.
+    pub commands: Vec<Command>,
+    pub sensors: Sensors,
+    pub attitude: Attitude,
+    pub internals: Internals,
+    pub outputs: Outputs,
+    /// Events raised during the cycle.
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Record {
+    Start(Start),
+    Cycle(Box<Cycle>),
+    Snapshot(Box<Snapshot>),
+}
+
+fn frame(record: &Record) -> Vec<u8> {
+    let payload = bincode::serialize(record).expect("records always serialize");
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&SYNC);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Writes flight logs from a thread of its own, so a slow card never holds
+/// up the control loop.
+///
+/// Logs are append only files of framed records, `flight-000001.flog` and
+/// up. Each frame carries its length and a CRC, so a log cut short by a
+/// crash or power loss reads fine up to the last whole frame.
+///
+/// Once a file is full the recorder asks for a [`Snapshot`] through
+/// [`Recorder::wants_snapshot`] and moves on to the next file when it gets
+/// one.
+pub struct Recorder {
+    records: mpsc::SyncSender<Record>,
+    full: Arc<AtomicBool>,
+    dropped: u64,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Opens the next log in the configured directory, `None` when the
+    /// recorder is disabled.
+    pub fn start(config: &RecorderConfig, start: Start) -> io::Result<Option<Recorder>> {
+        let directory = match &config.directory {
+            Some(directory) => PathBuf::from(directory),
+            None => return Ok(None),
+        };
+        fs::create_dir_all(&directory)?;
+        let full = Arc::new(AtomicBool::new(false));
+        let mut writer = Writer::new(directory, config.clone(), start, full.clone())?;
+        let (records, rx) = mpsc::sync_channel(QUEUE);
+        let writer = thread::spawn(move || {
+            for record in rx {
+                if let Err(e) = writer.write(&record) {
+                    println!("flight recorder stopped: {}", e);
+                    return;
+                }
+            }
+            let _ = writer.sync();
+        });
+        Ok(Some(Recorder {
+            records,
+            full,
+            dropped: 0,
+            writer: Some(writer),
+        }))
+    }
+
+    /// True when the current file is full and the next one waits for a
+    /// snapshot of the vehicle.
+    pub fn wants_snapshot(&self) -> bool {
+        self.full.load(Ordering::Relaxed)
+    }
+
+    /// Starts the next file with `snapshot`.
+    pub fn snapshot(&mut self, snapshot: Snapshot) {
+        self.full.store(false, Ordering::Relaxed);
+        if let Err(mpsc::TrySendError::Full(_)) =
+            self.records.try_send(Record::Snapshot(Box::new(snapshot)))
+        {
+            // Asked for again on the next cycle.
+            self.full.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record(&mut self, cycle: Cycle) {
+        match self.records.try_send(Record::Cycle(Box::new(cycle))) {
+            Ok(()) if self.dropped > 0 => {
+                println!("flight recorder dropped {} cycles", self.dropped);
+                self.dropped = 0;
+            }
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => self.dropped += 1,
+            Err(mpsc::TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+impl Drop for Recorder {
+    /// Waits for the writer to finish what is queued.
+    fn drop(&mut self) {
+        let (closed, _) = mpsc::sync_channel(0);
+        drop(std::mem::replace(&mut self.records, closed));
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    directory: PathBuf,
+    config: RecorderConfig,
+    start: Start,
+    /// Raised once per file when it is full, the recorder lowers it when it
+    /// sends the snapshot.
+    full: Arc<AtomicBool>,
+    /// The file is full and the snapshot for the next one is on its way.
+    /// Cycles queued before it still go to this file without asking again.
+    rotating: bool,
+    file: BufWriter<File>,
+    written: u64,
+    synced: Instant,
+}
+
+impl Writer {
+    fn new(
+        directory: PathBuf,
+        config: RecorderConfig,
+        start: Start,
+        full: Arc<AtomicBool>,
+    ) -> io::Result<Writer> {
+        let (path, file) = open_next(&directory)?;
+        println!("recording to {}", path.display());
+        let mut writer = Writer {
+            directory,
+            config,
+            start,
+            full,
+            rotating: false,
+            file,
+            written: 0,
+            synced: Instant::now(),
+        };
+        writer.begin()?;
+        Ok(writer)
+    }
+
+    /// Writes the header and start record of a fresh file.
+    fn begin(&mut self) -> io::Result<()> {
+        self.file.write_all(&MAGIC)?;
+        let frame = frame(&Record::Start(self.start.clone()));
+        self.file.write_all(&frame)?;
+        self.written = (MAGIC.len() + frame.len()) as u64;
+        prune(&self.directory, self.config.max_files)
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        // The snapshot asked for once the file was full opens the next one,
+        // the cycles written while it was on its way end this one.
+        if let Record::Snapshot(_) = record {
+            self.sync()?;
+            let (path, file) = open_next(&self.directory)?;
+            println!("recording to {}", path.display());
+            self.file = file;
+            self.rotating = false;
+            self.begin()?;
+        }
+        let frame = frame(record);
+        self.file.write_all(&frame)?;
+        self.file.flush()?;
+        self.written += frame.len() as u64;
+        if self.synced.elapsed() >= Duration::from_millis(self.config.sync_ms) {
+            self.sync()?;
+        }
+        if self.written >= self.config.max_file_bytes && !self.rotating {
+            self.rotating = true;
+            self.full.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.synced = Instant::now();
+        Ok(())
+    }
+}
+
+/// Number of a log file named `flight-NNNNNN.flog`.
+fn log_number(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("flight-")?
+        .strip_suffix(".flog")?
+        .parse()
+        .ok()
+}
+
+/// Logs in a directory, oldest first. Numbers rather than dates order
+/// them, as the clock of the Pi can be off.
+pub fn list(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut logs: Vec<(u64, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            Some((log_number(&path)?, path))
+        })
+        .collect();
+    logs.sort();
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+fn open_next(directory: &Path) -> io::Result<(PathBuf, BufWriter<File>)> {
+    let next = list(directory)?
+        .last()
+        .and_then(|path| log_number(path))
+        .map_or(1, |number| number + 1);
+    let path = directory.join(format!("flight-{:06}.flog", next));
+    let file = File::options().create_new(true).append(true).open(&path)?;
+    Ok((path, BufWriter::new(file)))
+}
+
+fn prune(directory: &Path, max_files: usize) -> io::Result<()> {
+    let logs = list(directory)?;
+    for path in logs
+        .iter()
+        .take(logs.len().saturating_sub(max_files.max(1)))
+    {
+        println!("deleting old flight log {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Records of a flight log, read back in order.
+///
+/// Damaged frames are skipped up to the next sync word that starts an
+/// intact frame, and a frame cut short at the end of the file ends it.
+/// [`LogReader::skipped`] tells how many bytes were passed over.
+pub struct LogReader {
+    data: Vec<u8>,
+    position: usize,
+    skipped: usize,
+}
+
+impl LogReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<LogReader> {
+        let path = path.as_ref();
+        LogReader::from_bytes(fs::read(path)?)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads a log already in memory, header included.
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<LogReader> {
+        if !data.starts_with(&MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a flight log",
+            ));
+        }
+        Ok(LogReader {
+            data,
+            position: MAGIC.len(),
+            skipped: 0,
+        })
+    }
+
+    /// Bytes that did not form an intact frame.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// The record of the frame at `position`, if it is intact.
+    fn frame_at(&self, position: usize) -> Option<(Record, usize)> {
+        let header = self.data.get(position..position + HEADER_LEN)?;
+        if header[..2] != SYNC {
+            return None;
+        }
+        let len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[6..10].try_into().unwrap());
+        if len > MAX_FRAME {
+            return None;
+        }
+        let start = position + HEADER_LEN;
+        let payload = self.data.get(start..start + len)?;
+        if crc32fast::hash(payload) != crc {
+            return None;
+        }
+        let record = bincode::deserialize(payload).ok()?;
+        Some((record, start + len))
+    }
+}
+
+impl Iterator for LogReader {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        while self.position < self.data.len() {
+            if let Some((record, end)) = self.frame_at(self.position) {
+                self.position = end;
+                return Some(record);
+            }
+            self.position += 1;
+            self.skipped += 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(unix_ms: u64) -> Record {
+        Record::Start(Start {
+            unix_ms,
+            config: "{}".to_string(),
+            calibration: Calibration {
+                gyro_bias: [0.1, -0.2, 0.3],
+                roll_offset: 0.01,
+                pitch_offset: -0.02,
+            },
+        })
+    }
+
+    /// A log of three records and where each frame starts.
+    fn log() -> (Vec<u8>, Vec<usize>) {
+        let mut data = MAGIC.to_vec();
+        let mut offsets = Vec::new();
+        for unix_ms in 1..=3 {
+            offsets.push(data.len());
+            data.extend(frame(&start(unix_ms)));
+        }
+        (data, offsets)
+    }
+
+    fn read(data: Vec<u8>) -> (Vec<Record>, usize) {
+        let mut reader = LogReader::from_bytes(data).unwrap();
+        let records = reader.by_ref().collect();
+        (records, reader.skipped())
+    }
+
+    #[test]
+    fn reads_back_what_was_framed() {
+        let (data, _) = log();
+        let (records, skipped) = read(data);
+        assert_eq!(records, vec![start(1), start(2), start(3)]);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn rejects_files_without_magic() {
+        let (mut data, _) = log();
+        data[7] = 0x02;
+        assert!(LogReader::from_bytes(data).is_err());
+        assert!(LogReader::from_bytes(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn resyncs_after_a_bad_crc() {
+        let (mut data, offsets) = log();
+        // Last byte of the second payload.
+        data[offsets[2] - 1] ^= 0x40;
+        let (records, skipped) = read(data);
+        assert_eq!(records, vec![start(1), start(3)]);
+        assert_eq!(skipped, offsets[2] - offsets[1]);
+    }
+
+    #[test]
+    fn resyncs_after_a_damaged_header() {
+        let (mut data, offsets) = log();
+        // A length far beyond the end of the file.
+        data[offsets[0] + 5] = 0x7F;
+        let (records, skipped) = read(data);
+        assert_eq!(records, vec![start(2), start(3)]);
+        assert_eq!(skipped, offsets[1] - offsets[0]);
+    }
+
+    #[test]
+    fn ends_at_a_truncated_tail() {
+        let (data, offsets) = log();
+        let cut = offsets[2] + HEADER_LEN + 3;
+        let (records, skipped) = read(data[..cut].to_vec());
+        assert_eq!(records, vec![start(1), start(2)]);
+        assert_eq!(skipped, cut - offsets[2]);
+    }
+
+    #[test]
+    fn log_numbers() {
+        assert_eq!(log_number(Path::new("logs/flight-000012.flog")), Some(12));
+        assert_eq!(log_number(Path::new("flight-12.flog.tmp")), None);
+        assert_eq!(log_number(Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn rotates_on_the_snapshot_asked_for() {
+        let directory =
+            std::env::temp_dir().join(format!("finale-recorder-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let config = RecorderConfig {
+            directory: None,
+            max_file_bytes: 1,
+            ..RecorderConfig::default()
+        };
+        let Record::Start(first) = start(1) else {
+            unreachable!()
+        };
+        let full = Arc::new(AtomicBool::new(false));
+        let mut writer = Writer::new(directory.clone(), config, first, full.clone()).unwrap();
+        writer.write(&start(2)).unwrap();
+        assert!(full.load(Ordering::Relaxed));
+        // Records written before the snapshot arrives end the full file.
+        writer.write(&start(3)).unwrap();
+        let snapshot = Record::Snapshot(Box::new(Snapshot::new(
+            &Config::default(),
+            crate::vehicle::Vehicle::new(&Config::default(), Calibration::default()).state(),
+        )));
+        writer.write(&snapshot).unwrap();
+        writer.sync().unwrap();
+
+        let logs = list(&directory).unwrap();
+        let records: Vec<Vec<Record>> = logs
+            .iter()
+            .map(|path| LogReader::open(path).unwrap().collect())
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], vec![start(1), start(2), start(3)]);
+        assert_eq!(records[1], vec![start(1), snapshot]);
+    }
+
+    fn cycle(time: f64) -> Cycle {
+        Cycle {
+            time,
+            dt: 0.02,
+            armed: false,
+            pilot: None,
+            commands: Vec::new(),
+            sensors: Sensors::default(),
+            attitude: Attitude::default(),
+            internals: Internals::default(),
+            outputs: Outputs {
+                thrusters: Vec::new(),
+                servos: Vec::new(),
+                lights: Vec::new(),
+            },
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn rotates_once_per_full_file() {
+        let directory =
+            std::env::temp_dir().join(format!("finale-recorder-once-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let config = RecorderConfig {
+            directory: Some(directory.to_string_lossy().into_owned()),
+            max_file_bytes: 16 * 1024,
+            max_files: 1000,
+            ..RecorderConfig::default()
+        };
+        let Record::Start(first) = start(1) else {
+            unreachable!()
+        };
+        let vehicle = crate::vehicle::Vehicle::new(&Config::default(), Calibration::default());
+        let mut recorder = Recorder::start(&config, first).unwrap().unwrap();
+        // As the control loop does it, the snapshot goes in right behind
+        // the cycle that may still be queued for the full file.
+        let mut snapshots = 0;
+        for i in 0..300 {
+            thread::sleep(Duration::from_millis(1));
+            recorder.record(cycle(i as f64 * 0.02));
+            if recorder.wants_snapshot() {
+                recorder.snapshot(Snapshot::new(vehicle.config(), vehicle.state()));
+                snapshots += 1;
+            }
+        }
+        drop(recorder);
+
+        let logs = list(&directory).unwrap();
+        let sizes: Vec<u64> = logs
+            .iter()
+            .map(|path| fs::metadata(path).unwrap().len())
+            .collect();
+        let records: Vec<Vec<Record>> = logs
+            .iter()
+            .map(|path| LogReader::open(path).unwrap().collect())
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(snapshots > 1);
+        assert_eq!(logs.len(), snapshots + 1);
+        // Only a file that filled up was left for the next one.
+        for size in &sizes[..sizes.len() - 1] {
+            assert!(*size >= config.max_file_bytes, "{:?}", sizes);
+        }
+        // The last snapshot may have come with the last cycle.
+        for records in &records[1..records.len() - 1] {
+            assert!(matches!(records[1], Record::Snapshot(_)));
+            assert!(matches!(records[2], Record::Cycle(_)));
+        }
+        let cycles = records
+            .iter()
+            .flatten()
+            .filter(|record| matches!(record, Record::Cycle(_)))
+            .count();
+        assert_eq!(cycles, 300);
+    }
+}
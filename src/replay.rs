@@ -0,0 +1,405 @@
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::estimator::{wrap_angle, Attitude, Calibration};
+use crate::protocol::Command;
+use crate::recorder::{Cycle, Record};
+use crate::vehicle::{Internals, Outputs, Vehicle};
+
+/// Parameters changed for a replay, applied to the configuration recorded
+/// at the start of every session.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Replaces the recorded configuration as a whole.
+    pub config: Option<Config>,
+    /// Dotted paths into the configuration and their new JSON values, e.g.
+    /// `stabilise.roll_pid.kp` and `0.8`.
+    pub set: Vec<(String, Value)>,
+}
+
+impl Overrides {
+    /// Parses a `PATH=VALUE` argument. Values that are not JSON are taken
+    /// as strings.
+    pub fn parse_set(arg: &str) -> Result<(String, Value), String> {
+        let (path, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not PATH=VALUE", arg))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        Ok((path.to_string(), value))
+    }
+
+    pub fn apply(&self, recorded: Config) -> Result<Config, String> {
+        let config = self.config.clone().unwrap_or(recorded);
+        if self.set.is_empty() {
+            return Ok(config);
+        }
+        let mut json = serde_json::to_value(&config).map_err(|e| e.to_string())?;
+        for (path, value) in &self.set {
+            let mut field = &mut json;
+            for key in path.split('.') {
+                field = match field {
+                    Value::Object(map) => map.get_mut(key),
+                    Value::Array(items) => key.parse().ok().and_then(|i: usize| items.get_mut(i)),
+                    _ => None,
+                }
+                .ok_or_else(|| format!("no parameter {}", path))?;
+            }
+            *field = value.clone();
+        }
+        serde_json::from_value(json).map_err(|e| format!("bad override: {}", e))
+    }
+}
+
+/// A recorded cycle and what the vehicle makes of it this time.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub recorded: Cycle,
+    pub attitude: Attitude,
+    pub internals: Internals,
+    pub outputs: Outputs,
+}
+
+/// Runs the control pipeline again on the inputs of flight logs.
+///
+/// A start record opens a session with a fresh vehicle, unless it repeats
+/// the start of the running session, as it does at the top of every file
+/// after a rotation. The snapshot after it puts a fresh vehicle where the
+/// recorded one was, so a later file replays on its own; a running session
+/// carries on with its own state instead.
+///
+/// The sensors are fed as recorded, so the vehicle never moves in response
+/// to changed outputs: a replay shows what a retune would have commanded
+/// during the dive, not how the vehicle would have flown.
+pub struct Replay {
+    overrides: Overrides,
+    vehicle: Option<Vehicle>,
+    /// Startup time of the running session.
+    session: Option<u64>,
+    calibration: Calibration,
+    /// Whether the running session has seen a cycle yet.
+    started: bool,
+    /// Whether the running session was picked up from a snapshot.
+    restored: bool,
+}
+
+impl Replay {
+    pub fn new(overrides: Overrides) -> Self {
+        Replay {
+            overrides,
+            vehicle: None,
+            session: None,
+            calibration: Calibration::default(),
+            started: false,
+            restored: false,
+        }
+    }
+
+    /// Takes the next record, returning the replayed cycle if it was one.
+    /// Cycles before the first start record are skipped.
+    pub fn feed(&mut self, record: Record) -> Result<Option<Step>, String> {
+        let cycle = match record {
+            Record::Start(start) => {
+                if self.session != Some(start.unix_ms) {
+                    let config = start.config().map_err(|e| e.to_string())?;
+                    let config = self.overrides.apply(config)?;
+                    self.vehicle = Some(Vehicle::new(&config, start.calibration));
+                    self.session = Some(start.unix_ms);
+                    self.calibration = start.calibration;
+                    self.started = false;
+                    self.restored = false;
+                }
+                return Ok(None);
+            }
+            Record::Snapshot(snapshot) => {
+                if self.session.is_some() && !self.started {
+                    let config = snapshot.config().map_err(|e| e.to_string())?;
+                    let config = self.overrides.apply(config)?;
+                    let mut vehicle = Vehicle::new(&config, self.calibration);
+                    vehicle.restore(&snapshot.state);
+                    self.vehicle = Some(vehicle);
+                    self.restored = true;
+                }
+                return Ok(None);
+            }
+            Record::Cycle(cycle) => *cycle,
+        };
+        let vehicle = match self.vehicle.as_mut() {
+            Some(vehicle) => vehicle,
+            None => return Ok(None),
+        };
+        if !self.started && !self.restored && cycle.time > 1.5 * cycle.dt as f64 {
+            println!(
+                "replay starts {:.1}s into a session without a snapshot, controllers start from rest and tuned gains are missing",
+                cycle.time
+            );
+        }
+        // A log that starts mid-dive starts with the vehicle as it was.
+        if !self.started && vehicle.armed() != cycle.armed {
+            vehicle.handle(&Command::SetArmed(cycle.armed));
+        }
+        self.started = true;
+        for command in &cycle.commands {
+            vehicle.handle(command);
+        }
+        let outputs = vehicle.step(cycle.pilot.as_ref(), &cycle.sensors, cycle.dt);
+        Ok(Some(Step {
+            attitude: vehicle.attitude(),
+            internals: *vehicle.internals(),
+            outputs,
+            recorded: cycle,
+        }))
+    }
+}
+
+/// Spread of the difference between replayed and recorded values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Spread {
+    count: usize,
+    sum_squares: f64,
+    max: f64,
+}
+
+impl Spread {
+    pub fn add(&mut self, difference: f64) {
+        self.count += 1;
+        self.sum_squares += difference * difference;
+        self.max = self.max.max(difference.abs());
+    }
+
+    pub fn rms(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.sum_squares / self.count as f64).sqrt()
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+}
+
+/// How far a replay strayed from the recording.
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub cycles: usize,
+    /// Cycles where any thruster pulse differs.
+    pub differing: usize,
+    /// Vehicle time of the first of them.
+    pub first_difference: Option<f64>,
+    /// Places where recorded cycles are missing, which the replay runs
+    /// across as if they were not.
+    pub gaps: usize,
+    last_time: Option<f64>,
+    /// Pulse difference of each thruster.
+    pub thrusters: Vec<Spread>,
+    /// Difference of the wrench handed to the mixer, per axis.
+    pub wrench: [Spread; 6],
+    /// Roll, pitch and yaw difference in radians.
+    pub attitude: [Spread; 3],
+}
+
+impl Diff {
+    pub fn add(&mut self, step: &Step) {
+        self.cycles += 1;
+        let time = step.recorded.time;
+        if self
+            .last_time
+            .is_some_and(|last| time - last > 1.5 * step.recorded.dt as f64)
+        {
+            self.gaps += 1;
+        }
+        self.last_time = Some(time);
+        let recorded = &step.recorded.outputs.thrusters;
+        let replayed = &step.outputs.thrusters;
+        if self.thrusters.len() < replayed.len() {
+            self.thrusters.resize(replayed.len(), Spread::default());
+        }
+        for ((spread, old), new) in self.thrusters.iter_mut().zip(recorded).zip(replayed) {
+            spread.add(*new as f64 - *old as f64);
+        }
+        if recorded != replayed {
+            self.differing += 1;
+            self.first_difference.get_or_insert(time);
+        }
+        let old = step.recorded.internals.wrench.as_array();
+        let new = step.internals.wrench.as_array();
+        for ((spread, old), new) in self.wrench.iter_mut().zip(old).zip(new) {
+            spread.add((new - old) as f64);
+        }
+        let old = &step.recorded.attitude;
+        let new = &step.attitude;
+        for (spread, difference) in self.attitude.iter_mut().zip([
+            new.roll - old.roll,
+            new.pitch - old.pitch,
+            wrap_angle(new.yaw - old.yaw),
+        ]) {
+            spread.add(difference as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recorder::{Snapshot, Start};
+    use crate::sensors::Sensors;
+    use crate::stabilise::StabiliseMode;
+    use crate::vehicle::VehicleState;
+
+    fn overrides(sets: &[&str]) -> Overrides {
+        Overrides {
+            config: None,
+            set: sets
+                .iter()
+                .map(|arg| Overrides::parse_set(arg).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_set_arguments() {
+        assert_eq!(
+            Overrides::parse_set("stabilise.roll_pid.kp=0.8").unwrap(),
+            ("stabilise.roll_pid.kp".to_string(), serde_json::json!(0.8))
+        );
+        assert_eq!(
+            Overrides::parse_set("stabilise.mode=rate").unwrap().1,
+            Value::String("rate".to_string())
+        );
+        assert_eq!(
+            Overrides::parse_set("listen=\"0.0.0.0:1\"").unwrap().1,
+            Value::String("0.0.0.0:1".to_string())
+        );
+        assert!(Overrides::parse_set("stabilise.roll_pid.kp").is_err());
+    }
+
+    #[test]
+    fn applies_dotted_paths() {
+        let config = overrides(&[
+            "stabilise.roll_pid.kp=0.8",
+            "stabilise.mode=rate",
+            "thrusters.1.neutral=310",
+            "shaping.gears=[0.5,1.0]",
+        ])
+        .apply(Config::default())
+        .unwrap();
+        assert_eq!(config.stabilise.roll_pid.kp, 0.8);
+        assert_eq!(config.stabilise.mode, StabiliseMode::Rate);
+        assert_eq!(config.thrusters[1].neutral, 310);
+        assert_eq!(config.thrusters[0].neutral, 307);
+        assert_eq!(config.shaping.gears, vec![0.5, 1.0]);
+    }
+
+    #[test]
+    fn later_settings_win() {
+        let config = overrides(&["loop_hz=100", "loop_hz=25"])
+            .apply(Config::default())
+            .unwrap();
+        assert_eq!(config.loop_hz, 25.0);
+    }
+
+    #[test]
+    fn rejects_unknown_paths_and_bad_values() {
+        for set in [
+            "stabilise.roll_pid.kq=1",
+            "thrusters.99.neutral=1",
+            "loop_hz.value=1",
+        ] {
+            let error = overrides(&[set]).apply(Config::default()).unwrap_err();
+            assert!(error.starts_with("no parameter"), "{}: {}", set, error);
+        }
+        let error = overrides(&["loop_hz=fast"])
+            .apply(Config::default())
+            .unwrap_err();
+        assert!(error.starts_with("bad override"), "{}", error);
+    }
+
+    #[test]
+    fn replacement_config_comes_before_settings() {
+        let replacement = Config {
+            loop_hz: 200.0,
+            ..Config::default()
+        };
+        let overrides = Overrides {
+            config: Some(replacement),
+            ..overrides(&["stabilise.rate_pid.kp=4"])
+        };
+        let recorded = Config {
+            loop_hz: 10.0,
+            ..Config::default()
+        };
+        let config = overrides.apply(recorded).unwrap();
+        assert_eq!(config.loop_hz, 200.0);
+        assert_eq!(config.stabilise.rate_pid.kp, 4.0);
+    }
+
+    fn start() -> Record {
+        Record::Start(Start {
+            unix_ms: 1_700_000_000_000,
+            ..Start::new(&Config::default(), Calibration::default())
+        })
+    }
+
+    fn cycle(time: f64) -> Record {
+        Record::Cycle(Box::new(Cycle {
+            time,
+            dt: 0.02,
+            armed: false,
+            pilot: None,
+            commands: Vec::new(),
+            sensors: Sensors::default(),
+            attitude: Attitude::default(),
+            internals: Internals::default(),
+            outputs: Outputs {
+                thrusters: Vec::new(),
+                servos: Vec::new(),
+                lights: Vec::new(),
+            },
+            events: Vec::new(),
+        }))
+    }
+
+    /// Vehicle of a session an hour in, with retuned roll gains.
+    fn snapshot() -> (Record, VehicleState) {
+        let mut config = Config::default();
+        config.stabilise.roll_pid.kp = 2.0;
+        let mut vehicle = Vehicle::new(&config, Calibration::default());
+        vehicle.handle(&Command::SetStabilise(StabiliseMode::Rate));
+        vehicle.handle(&Command::SetArmed(true));
+        let mut state = vehicle.state();
+        state.time = 3600.0;
+        let record = Record::Snapshot(Box::new(Snapshot::new(&config, state.clone())));
+        (record, state)
+    }
+
+    #[test]
+    fn later_file_starts_from_the_snapshot() {
+        let (snapshot, state) = snapshot();
+        let mut replay = Replay::new(overrides(&["stabilise.pitch_pid.kp=0.5"]));
+        for record in [start(), snapshot] {
+            assert!(replay.feed(record).unwrap().is_none());
+        }
+        let vehicle = replay.vehicle.as_ref().unwrap();
+        assert_eq!(vehicle.state(), state);
+        assert_eq!(vehicle.config().stabilise.roll_pid.kp, 2.0);
+        assert_eq!(vehicle.config().stabilise.pitch_pid.kp, 0.5);
+        replay.feed(cycle(3600.02)).unwrap().unwrap();
+        assert!((replay.vehicle.unwrap().time() - 3600.02).abs() < 1e-6);
+    }
+
+    #[test]
+    fn running_session_ignores_the_snapshot() {
+        let (snapshot, _) = snapshot();
+        let mut replay = Replay::new(Overrides::default());
+        replay.feed(start()).unwrap();
+        replay.feed(cycle(0.02)).unwrap().unwrap();
+        // The rotation: the start repeats, then the snapshot.
+        replay.feed(start()).unwrap();
+        replay.feed(snapshot).unwrap();
+        let vehicle = replay.vehicle.as_ref().unwrap();
+        assert!(vehicle.time() < 1.0);
+        assert_eq!(vehicle.config().stabilise.roll_pid.kp, 0.64);
+    }
+}
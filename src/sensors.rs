@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::estimator::ImuSample;
+
+/// Raw sensor values read by the control loop in one cycle.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sensors {
+    pub imu: ImuSample,
+    /// Pin level of each leak sensor, true is high.
+    pub leak: Vec<bool>,
+    /// INA219 on the battery line, `None` if absent or unreadable.
+    pub power: Option<PowerReading>,
+    /// Scaled value of each ADS1115 channel, `None` until first converted.
+    pub adc: Vec<Option<f32>>,
+    /// Air in the electronics tube, `None` if absent or unreadable.
+    pub climate: Option<ClimateReading>,
+    /// Die temperature of the MPU6050 in degrees Celsius.
+    pub imu_temperature: Option<f32>,
+}
+
+/// Voltage and current of the battery line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerReading {
+    /// Bus voltage in volts.
+    pub voltage: f32,
+    /// Current in amps.
+    pub current: f32,
+}
+
+/// Air temperature and relative humidity in the electronics tube.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClimateReading {
+    /// Degrees Celsius.
+    pub temperature: f32,
+    /// Percent.
+    pub humidity: f32,
+}
@@ -0,0 +1,710 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AdcChannelConfig, GripperConfig, ServoConfig, ServoPresetConfig};
+
+/// How a servo moves towards a new target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServoProfile {
+    /// Accelerates, cruises at the maximum speed and brakes in time to stop
+    /// on the target. Follows a target that keeps moving, like a stick.
+    #[default]
+    Trapezoid,
+    /// Polynomial ending at rest with no jerk, planned from the current
+    /// position, velocity and acceleration whenever the target changes.
+    MinimumJerk,
+}
+
+/// Minimum jerk move in progress: the position is a quintic in the time
+/// since the plan was made.
+#[derive(Debug, Clone, Copy)]
+struct Plan {
+    coefficients: [f32; 6],
+    duration: f32,
+    elapsed: f32,
+}
+
+/// Where a servo is and where it is heading, in degrees.
+#[derive(Debug, Clone, Copy)]
+struct Motion {
+    position: f32,
+    velocity: f32,
+    acceleration: f32,
+    target: f32,
+    plan: Option<Plan>,
+}
+
+/// Stall protection state of a gripper servo.
+#[derive(Debug, Clone, Copy, Default)]
+struct Grip {
+    closing_time: f32,
+    stall_time: f32,
+    /// Closing targets are replaced by the holding position until the
+    /// pilot opens the gripper.
+    backed_off: bool,
+}
+
+impl GripperConfig {
+    /// True for targets past the holding position towards closed.
+    fn is_closing(&self, degrees: f32) -> bool {
+        (degrees - self.holding_angle) * (self.closed_angle - self.holding_angle) > 0.0
+    }
+}
+
+/// Servo outputs of the manipulator and camera.
+///
+/// Positions are asked in degrees and turned into pulses through a two point
+/// calibration. Every pulse, including the raw ones of legacy packets, is kept
+/// within the servo's limits so a bad value cannot drive it into its end stop.
+/// Commands only set a target, [`Servos::update`] moves the servos there within
+/// their speed and acceleration limits.
+pub struct Servos {
+    configs: Vec<ServoConfig>,
+    presets: Vec<ServoPresetConfig>,
+    grips: Vec<Grip>,
+    /// Index of the current of every gripper in the ADS1115 channels.
+    current_channels: Vec<Option<usize>>,
+    events: Vec<String>,
+    /// `None` until the servo is first commanded. Its position is unknown
+    /// until then, so the first command is not smoothed.
+    motions: Vec<Option<Motion>>,
+}
+
+impl Servos {
+    pub fn new(
+        configs: Vec<ServoConfig>,
+        presets: Vec<ServoPresetConfig>,
+        adc_channels: &[AdcChannelConfig],
+    ) -> Self {
+        let current_channels = configs
+            .iter()
+            .map(|config| {
+                let name = config.gripper.as_ref()?.current_channel.as_ref()?;
+                let index = adc_channels.iter().position(|c| &c.name == name);
+                if index.is_none() {
+                    println!("servo {}: no ADC channel named {}", config.name, name);
+                }
+                index
+            })
+            .collect();
+        Servos {
+            motions: vec![None; configs.len()],
+            grips: vec![Grip::default(); configs.len()],
+            current_channels,
+            events: Vec::new(),
+            configs,
+            presets,
+        }
+    }
+
+    pub fn configs(&self) -> &[ServoConfig] {
+        &self.configs
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.configs.iter().position(|c| c.name == name)
+    }
+
+    /// Pulse of every servo, `None` until it is first commanded.
+    pub fn pulses(&self) -> Vec<Option<u16>> {
+        self.configs
+            .iter()
+            .zip(&self.motions)
+            .map(|(config, motion)| {
+                motion.map(|m| {
+                    let pulse = config.pulse(m.position);
+                    pulse
+                        .clamp(config.min_pulse as f32, config.max_pulse as f32)
+                        .round() as u16
+                })
+            })
+            .collect()
+    }
+
+    /// Current position of every servo in degrees.
+    pub fn angles(&self) -> Vec<Option<f32>> {
+        self.motions
+            .iter()
+            .map(|motion| motion.map(|m| m.position))
+            .collect()
+    }
+
+    /// Target of every servo in degrees.
+    pub fn targets(&self) -> Vec<Option<f32>> {
+        self.motions
+            .iter()
+            .map(|motion| motion.map(|m| m.target))
+            .collect()
+    }
+
+    /// Grippers currently backed off to their holding position.
+    pub fn backed_off(&self) -> Vec<bool> {
+        self.grips.iter().map(|grip| grip.backed_off).collect()
+    }
+
+    /// Puts the servos back where they were, heading for their targets,
+    /// with the grippers backed off as they were.
+    pub fn restore(
+        &mut self,
+        angles: &[Option<f32>],
+        targets: &[Option<f32>],
+        backed_off: &[bool],
+    ) {
+        for (index, motion) in self.motions.iter_mut().enumerate() {
+            *motion = angles.get(index).copied().flatten().map(|position| Motion {
+                position,
+                velocity: 0.0,
+                acceleration: 0.0,
+                target: targets.get(index).copied().flatten().unwrap_or(position),
+                plan: None,
+            });
+            self.grips[index] = Grip {
+                backed_off: backed_off.get(index).copied().unwrap_or(false),
+                ..Grip::default()
+            };
+        }
+    }
+
+    /// Gripper events since the last call, for telemetry.
+    pub fn take_events(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Sets a raw pulse as target, clamped to the servo limits. A pulse of 0,
+    /// what older topsides send for a servo they leave alone, and non-finite
+    /// pulses are ignored.
+    pub fn set_pulse(&mut self, index: usize, pulse: f32) {
+        if !pulse.is_finite() || pulse == 0.0 {
+            return;
+        }
+        if let Some(config) = self.configs.get(index) {
+            let pulse = pulse.clamp(config.min_pulse as f32, config.max_pulse as f32);
+            let degrees = config.angle(pulse);
+            self.set_target(index, degrees);
+        }
+    }
+
+    pub fn set_angle(&mut self, index: usize, degrees: f32) {
+        match self.configs.get(index) {
+            Some(config) => {
+                // Clamp through the pulse so the limits hold whichever way
+                // the calibration runs.
+                let pulse = config.pulse(degrees);
+                self.set_pulse(index, pulse);
+            }
+            None => println!("no servo {}", index),
+        }
+    }
+
+    /// Moves the servos listed in a named preset.
+    pub fn apply_preset(&mut self, name: &str) {
+        let preset = match self.presets.iter().find(|p| p.name == name) {
+            Some(preset) => preset.clone(),
+            None => {
+                println!("no servo preset {}", name);
+                return;
+            }
+        };
+        for (servo, degrees) in &preset.positions {
+            match self.index(servo) {
+                Some(index) => self.set_angle(index, *degrees),
+                None => println!("preset {}: no servo {}", name, servo),
+            }
+        }
+    }
+
+    fn set_target(&mut self, index: usize, mut degrees: f32) {
+        if let Some(gripper) = &self.configs[index].gripper {
+            let grip = &mut self.grips[index];
+            if grip.backed_off {
+                if gripper.is_closing(degrees) {
+                    degrees = gripper.holding_angle;
+                } else {
+                    grip.backed_off = false;
+                    self.events
+                        .push(format!("{} released", self.configs[index].name));
+                }
+            }
+        }
+        let slot = match self.motions.get_mut(index) {
+            Some(slot) => slot,
+            None => return,
+        };
+        match slot {
+            Some(motion) => {
+                if (motion.target - degrees).abs() > 1e-3 {
+                    motion.target = degrees;
+                    motion.plan = None;
+                }
+            }
+            None => {
+                *slot = Some(Motion {
+                    position: degrees,
+                    velocity: 0.0,
+                    acceleration: 0.0,
+                    target: degrees,
+                    plan: None,
+                });
+            }
+        }
+    }
+
+    /// Backs a gripper off to its holding position when it has been closing
+    /// for too long or its current shows a stall.
+    fn check_grip(&mut self, index: usize, dt: f32, adc: &[Option<f32>]) {
+        let (gripper, motion) = match (&self.configs[index].gripper, self.motions[index]) {
+            (Some(gripper), Some(motion)) => (gripper, motion),
+            _ => return,
+        };
+        let grip = &mut self.grips[index];
+        if grip.backed_off || !gripper.is_closing(motion.target) {
+            grip.closing_time = 0.0;
+            grip.stall_time = 0.0;
+            return;
+        }
+
+        grip.closing_time += dt;
+        let current =
+            self.current_channels[index].and_then(|channel| adc.get(channel).copied().flatten());
+        match current {
+            Some(current) if current > gripper.stall_current => grip.stall_time += dt,
+            _ => grip.stall_time = 0.0,
+        }
+
+        let timed_out =
+            gripper.closing_timeout_s > 0.0 && grip.closing_time >= gripper.closing_timeout_s;
+        let reason = match current {
+            Some(current)
+                if current > gripper.stall_current && grip.stall_time >= gripper.stall_time_s =>
+            {
+                format!("stalled at {:.2}A", current)
+            }
+            _ if timed_out => format!("closing for {:.1}s", grip.closing_time),
+            _ => return,
+        };
+        let holding = gripper.holding_angle;
+        self.events.push(format!(
+            "{} {}, backed off to {:.0} degrees",
+            self.configs[index].name, reason, holding
+        ));
+        self.set_target(index, holding);
+        self.grips[index].backed_off = true;
+    }
+
+    /// Moves every servo one step towards its target. `adc` holds the
+    /// ADS1115 readings used for gripper current.
+    pub fn update(&mut self, dt: f32, adc: &[Option<f32>]) {
+        for index in 0..self.configs.len() {
+            self.check_grip(index, dt, adc);
+        }
+        for (config, motion) in self.configs.iter().zip(self.motions.iter_mut()) {
+            if let Some(motion) = motion {
+                if config.max_speed <= 0.0 {
+                    motion.position = motion.target;
+                    motion.velocity = 0.0;
+                    continue;
+                }
+                match config.profile {
+                    ServoProfile::Trapezoid => trapezoid(config, motion, dt),
+                    ServoProfile::MinimumJerk => minimum_jerk(config, motion, dt),
+                }
+            }
+        }
+    }
+}
+
+fn trapezoid(config: &ServoConfig, motion: &mut Motion, dt: f32) {
+    let error = motion.target - motion.position;
+    // Fastest speed that still stops on the target.
+    let mut speed = config.max_speed;
+    if config.max_acceleration > 0.0 {
+        speed = speed.min((2.0 * config.max_acceleration * error.abs()).sqrt());
+    }
+    let desired = speed * error.signum();
+    motion.velocity = if config.max_acceleration > 0.0 {
+        let step = config.max_acceleration * dt;
+        motion.velocity + (desired - motion.velocity).clamp(-step, step)
+    } else {
+        desired
+    };
+    motion.position += motion.velocity * dt;
+
+    let remaining = motion.target - motion.position;
+    if remaining == 0.0 || remaining.signum() != error.signum() {
+        motion.position = motion.target;
+        motion.velocity = 0.0;
+    }
+}
+
+/// Factor a minimum jerk move is lengthened by while it breaks the limits,
+/// and how often at most.
+const STRETCH: f32 = 1.1;
+const MAX_STRETCHES: usize = 40;
+
+/// Rounding allowed on the limits, the durations from rest meet them
+/// exactly.
+const LIMIT_TOLERANCE: f32 = 1.001;
+
+/// Samples taken to find the peak speed of a plan.
+const SPEED_SAMPLES: usize = 64;
+
+/// Largest speed and acceleration of a quintic over `duration`.
+fn peaks(c: &[f32; 6], duration: f32) -> (f32, f32) {
+    let velocity =
+        |t: f32| c[1] + t * (2.0 * c[2] + t * (3.0 * c[3] + t * (4.0 * c[4] + t * 5.0 * c[5])));
+    let acceleration = |t: f32| 2.0 * c[2] + t * (6.0 * c[3] + t * (12.0 * c[4] + t * 20.0 * c[5]));
+    let speed = (0..=SPEED_SAMPLES)
+        .map(|i| velocity(duration * i as f32 / SPEED_SAMPLES as f32).abs())
+        .fold(0.0, f32::max);
+    // The acceleration is a cubic, its peaks are at the ends or where the
+    // jerk 6 c3 + 24 c4 t + 60 c5 t^2 is zero.
+    let (a, b, k) = (60.0 * c[5], 24.0 * c[4], 6.0 * c[3]);
+    let mut times = vec![0.0, duration];
+    if a != 0.0 {
+        let discriminant = b * b - 4.0 * a * k;
+        if discriminant >= 0.0 {
+            let root = discriminant.sqrt();
+            times.extend([(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]);
+        }
+    } else if b != 0.0 {
+        times.push(-k / b);
+    }
+    let peak = times
+        .into_iter()
+        .filter(|t| (0.0..=duration).contains(t))
+        .map(|t| acceleration(t).abs())
+        .fold(0.0, f32::max);
+    (speed, peak)
+}
+
+fn minimum_jerk(config: &ServoConfig, motion: &mut Motion, dt: f32) {
+    if motion.plan.is_none() {
+        if motion.position == motion.target && motion.velocity == 0.0 {
+            return;
+        }
+        let distance = motion.target - motion.position;
+        // From rest the peak speed of the profile is 1.875 D/T and its peak
+        // acceleration 5.77 D/T^2, pick the shortest duration respecting
+        // both. A move that starts at speed also has to brake first.
+        let mut reach = distance.abs();
+        if config.max_acceleration > 0.0 {
+            reach += motion.velocity * motion.velocity / (2.0 * config.max_acceleration);
+        }
+        let mut duration = 1.875 * reach / config.max_speed;
+        if config.max_acceleration > 0.0 {
+            duration = duration.max((5.7735 * reach / config.max_acceleration).sqrt());
+        }
+        if duration <= 0.0 {
+            motion.position = motion.target;
+            motion.velocity = 0.0;
+            motion.acceleration = 0.0;
+            return;
+        }
+        let plan = |duration| {
+            quintic(
+                motion.position,
+                motion.velocity,
+                motion.acceleration,
+                distance,
+                duration,
+            )
+        };
+        // The bounds above are exact from rest. A move replanned on the way
+        // is stretched until it fits the limits, which it does once long
+        // enough as the acceleration it starts with is within them.
+        let mut coefficients = plan(duration);
+        for _ in 0..MAX_STRETCHES {
+            let (speed, acceleration) = peaks(&coefficients, duration);
+            if speed <= config.max_speed * LIMIT_TOLERANCE
+                && (config.max_acceleration <= 0.0
+                    || acceleration <= config.max_acceleration * LIMIT_TOLERANCE)
+            {
+                break;
+            }
+            duration *= STRETCH;
+            coefficients = plan(duration);
+        }
+        motion.plan = Some(Plan {
+            coefficients,
+            duration,
+            elapsed: 0.0,
+        });
+    }
+    let plan = motion.plan.as_mut().expect("plan was just made");
+    plan.elapsed += dt;
+    let t = plan.elapsed.min(plan.duration);
+    let c = plan.coefficients;
+    motion.position = c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * c[5]))));
+    motion.velocity =
+        c[1] + t * (2.0 * c[2] + t * (3.0 * c[3] + t * (4.0 * c[4] + t * 5.0 * c[5])));
+    motion.acceleration = 2.0 * c[2] + t * (6.0 * c[3] + t * (12.0 * c[4] + t * 20.0 * c[5]));
+    if plan.elapsed >= plan.duration {
+        motion.position = motion.target;
+        motion.velocity = 0.0;
+        motion.acceleration = 0.0;
+        motion.plan = None;
+    }
+}
+
+/// Coefficients of the quintic that starts at `position` with `velocity`
+/// and `acceleration` and comes to rest `distance` further after `duration`.
+fn quintic(
+    position: f32,
+    velocity: f32,
+    acceleration: f32,
+    distance: f32,
+    duration: f32,
+) -> [f32; 6] {
+    let t = duration;
+    let (v, a) = (velocity * t, acceleration * t * t);
+    [
+        position,
+        velocity,
+        acceleration / 2.0,
+        (20.0 * distance - 12.0 * v - 3.0 * a) / (2.0 * t.powi(3)),
+        (-30.0 * distance + 16.0 * v + 3.0 * a) / (2.0 * t.powi(4)),
+        (12.0 * distance - 6.0 * v - a) / (2.0 * t.powi(5)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    fn servos(profile: ServoProfile) -> Servos {
+        let config = ServoConfig {
+            profile,
+            ..ServoConfig::defaults().remove(0)
+        };
+        Servos::new(vec![config], Vec::new(), &[])
+    }
+
+    /// Runs a servo until it reaches its target, returning the positions on
+    /// the way.
+    fn run(servos: &mut Servos, seconds: f32) -> Vec<f32> {
+        (0..(seconds / DT) as usize)
+            .map(|_| {
+                servos.update(DT, &[]);
+                servos.angles()[0].unwrap()
+            })
+            .collect()
+    }
+
+    /// Largest speed and acceleration along a path sampled every `DT`.
+    fn limits(start: f32, path: &[f32]) -> (f32, f32) {
+        let mut previous = (start, 0.0f32);
+        let (mut speed, mut acceleration) = (0.0f32, 0.0f32);
+        for position in path {
+            let velocity = (position - previous.0) / DT;
+            speed = speed.max(velocity.abs());
+            acceleration = acceleration.max(((velocity - previous.1) / DT).abs());
+            previous = (*position, velocity);
+        }
+        (speed, acceleration)
+    }
+
+    #[test]
+    fn zero_pulse_leaves_the_servo() {
+        let mut servos = servos(ServoProfile::Trapezoid);
+        servos.set_pulse(0, 0.0);
+        assert_eq!(servos.pulses(), vec![None]);
+        servos.set_angle(0, 45.0);
+        servos.set_pulse(0, 0.0);
+        servos.set_pulse(0, f32::NAN);
+        assert_eq!(servos.targets(), vec![Some(45.0)]);
+    }
+
+    #[test]
+    fn first_command_jumps() {
+        let mut servos = servos(ServoProfile::Trapezoid);
+        assert_eq!(servos.pulses(), vec![None]);
+        servos.set_angle(0, 45.0);
+        assert_eq!(servos.angles(), vec![Some(45.0)]);
+        assert_eq!(servos.pulses(), vec![Some(358)]);
+    }
+
+    #[test]
+    fn trapezoid_respects_limits() {
+        let mut servos = servos(ServoProfile::Trapezoid);
+        servos.set_angle(0, -45.0);
+        servos.set_angle(0, 45.0);
+        let target = servos.targets()[0].unwrap();
+        let path = run(&mut servos, 1.5);
+        assert!(path.iter().all(|p| *p <= target));
+        // 90 degrees at 120/s with 0.25s to reach speed: 1s.
+        let arrived = path.iter().position(|p| *p == target).unwrap();
+        assert!((95..=110).contains(&arrived), "arrived after {}", arrived);
+        // The last step snaps onto the target from a crawl.
+        let (speed, acceleration) = limits(-45.0, &path[..arrived]);
+        assert!(speed <= 120.0 + 1e-3, "speed {}", speed);
+        assert!(
+            acceleration <= 480.0 * 1.01,
+            "acceleration {}",
+            acceleration
+        );
+    }
+
+    #[test]
+    fn trapezoid_follows_a_moving_target() {
+        let mut servos = servos(ServoProfile::Trapezoid);
+        servos.set_angle(0, 0.0);
+        let mut path = Vec::new();
+        for step in 1..=100 {
+            servos.set_angle(0, step as f32 * 0.5);
+            servos.update(DT, &[]);
+            path.push(servos.angles()[0].unwrap());
+        }
+        assert!(path.windows(2).all(|pair| pair[1] >= pair[0]));
+        // Braking distance at 50/s is 50^2 / (2 * 480), 2.6 degrees.
+        assert!((path[99] - 50.0).abs() < 3.0, "lags at {}", path[99]);
+    }
+
+    #[test]
+    fn minimum_jerk_retargets_within_limits() {
+        // Turned back while speeding up, then at full speed.
+        for (after, back) in [(0.2, -45.0), (0.7, -45.0), (0.4, -20.0)] {
+            let mut servos = servos(ServoProfile::MinimumJerk);
+            servos.set_angle(0, -45.0);
+            servos.set_angle(0, 45.0);
+            let mut path = run(&mut servos, after);
+            servos.set_angle(0, back);
+            let target = servos.targets()[0].unwrap();
+            path.extend(run(&mut servos, 3.0));
+            let (speed, acceleration) = limits(-45.0, &path);
+            assert!(speed <= 120.0 + 0.5, "speed {} after {}", speed, after);
+            assert!(
+                acceleration <= 480.0 + 0.5,
+                "acceleration {} after {}",
+                acceleration,
+                after
+            );
+            assert_eq!(*path.last().unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn minimum_jerk_moves_rest_to_rest() {
+        let mut servos = servos(ServoProfile::MinimumJerk);
+        servos.set_angle(0, -45.0);
+        servos.set_angle(0, 45.0);
+        let path = run(&mut servos, 2.0);
+        let (speed, acceleration) = limits(-45.0, &path);
+        assert!(speed <= 120.0 + 1e-3, "speed {}", speed);
+        assert!(acceleration <= 480.0, "acceleration {}", acceleration);
+        assert!(path.windows(2).all(|pair| pair[1] >= pair[0]));
+        // Speed bound: 1.875 * 90 / 120.
+        let arrived = path
+            .iter()
+            .position(|p| Some(*p) == servos.targets()[0])
+            .unwrap();
+        assert!((139..=141).contains(&arrived), "arrived after {}", arrived);
+        // Gentle at both ends.
+        assert!(path[0] - -45.0 < 0.01);
+        assert!(45.0 - path[arrived - 1] < 0.01);
+    }
+
+    #[test]
+    fn zero_speed_moves_straight_to_the_target() {
+        let mut servos = Servos::new(
+            vec![ServoConfig {
+                max_speed: 0.0,
+                ..ServoConfig::defaults().remove(0)
+            }],
+            Vec::new(),
+            &[],
+        );
+        servos.set_angle(0, 0.0);
+        servos.set_angle(0, 80.0);
+        servos.update(DT, &[]);
+        assert_eq!(servos.angles(), servos.targets());
+    }
+
+    fn gripper() -> Servos {
+        let config = ServoConfig {
+            max_speed: 0.0,
+            gripper: Some(GripperConfig {
+                current_channel: Some("grip".to_string()),
+                closing_timeout_s: 1.0,
+                stall_time_s: 0.25,
+                ..GripperConfig::default()
+            }),
+            ..ServoConfig::defaults().remove(0)
+        };
+        let channels = [AdcChannelConfig {
+            name: "grip".to_string(),
+            input: 0,
+            scale: 1.0,
+        }];
+        Servos::new(vec![config], Vec::new(), &channels)
+    }
+
+    fn run_grip(servos: &mut Servos, seconds: f32, current: f32) {
+        for _ in 0..(seconds / DT).round() as usize {
+            servos.update(DT, &[Some(current)]);
+        }
+    }
+
+    fn target(servos: &Servos) -> f32 {
+        servos.targets()[0].unwrap()
+    }
+
+    #[test]
+    fn gripper_backs_off_on_stall() {
+        let mut servos = gripper();
+        servos.set_angle(0, 90.0);
+        run_grip(&mut servos, 0.5, 0.5);
+        run_grip(&mut servos, 0.2, 2.0);
+        assert!(!servos.backed_off()[0]);
+        // A dip below the stall current starts the stall time over.
+        run_grip(&mut servos, 0.01, 0.5);
+        run_grip(&mut servos, 0.2, 2.0);
+        assert!(!servos.backed_off()[0]);
+        run_grip(&mut servos, 0.06, 2.0);
+        assert!(servos.backed_off()[0]);
+        assert!((target(&servos) - 60.0).abs() < 1.0);
+        assert_eq!(
+            servos.take_events(),
+            vec!["b0 stalled at 2.00A, backed off to 60 degrees".to_string()]
+        );
+    }
+
+    #[test]
+    fn gripper_backs_off_after_closing_timeout() {
+        let mut servos = gripper();
+        servos.set_angle(0, 80.0);
+        run_grip(&mut servos, 0.95, 0.0);
+        assert!(!servos.backed_off()[0]);
+        run_grip(&mut servos, 0.1, 0.0);
+        assert!(servos.backed_off()[0]);
+        assert_eq!(servos.take_events().len(), 1);
+    }
+
+    #[test]
+    fn backed_off_gripper_holds_until_opened() {
+        let mut servos = gripper();
+        servos.set_angle(0, 90.0);
+        run_grip(&mut servos, 1.1, 0.0);
+        servos.take_events();
+
+        // Closing again is held at the holding position.
+        servos.set_angle(0, 90.0);
+        assert!((target(&servos) - 60.0).abs() < 1.0);
+        run_grip(&mut servos, 2.0, 0.0);
+        assert!(servos.take_events().is_empty());
+
+        servos.set_angle(0, 20.0);
+        assert!(!servos.backed_off()[0]);
+        assert!((target(&servos) - 20.0).abs() < 1.0);
+        assert_eq!(servos.take_events(), vec!["b0 released".to_string()]);
+    }
+
+    #[test]
+    fn open_gripper_is_never_backed_off() {
+        let mut servos = gripper();
+        servos.set_angle(0, 30.0);
+        run_grip(&mut servos, 5.0, 3.0);
+        assert!(!servos.backed_off()[0]);
+    }
+}
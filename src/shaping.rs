@@ -0,0 +1,214 @@
+use crate::config::{AxisShapingConfig, ShapingConfig};
+use crate::mixer::Wrench;
+
+/// Turns the pilot sticks into the wrench the controllers act on.
+///
+/// Each axis goes through its deadzone and expo curve, is scaled by the
+/// selected gear and then slew limited, so a low gear gives fine control
+/// over the whole stick travel.
+pub struct Shaper {
+    axes: [AxisShapingConfig; 6],
+    gears: Vec<f32>,
+    gear: usize,
+    output: [f32; 6],
+}
+
+impl Shaper {
+    pub fn new(config: &ShapingConfig) -> Self {
+        let gears = if config.gears.is_empty() {
+            vec![1.0]
+        } else {
+            config.gears.clone()
+        };
+        Shaper {
+            axes: config.axes(),
+            gear: config.gear.min(gears.len() - 1),
+            gears,
+            output: [0.0; 6],
+        }
+    }
+
+    /// Index of the selected gear.
+    pub fn gear(&self) -> usize {
+        self.gear
+    }
+
+    pub fn gears(&self) -> &[f32] {
+        &self.gears
+    }
+
+    pub fn set_gear(&mut self, gear: usize) {
+        if gear >= self.gears.len() {
+            println!("no gear {}, there are {}", gear, self.gears.len());
+            return;
+        }
+        if gear != self.gear {
+            println!("gear {} ({:.0}%)", gear, self.gears[gear] * 100.0);
+        }
+        self.gear = gear;
+    }
+
+    /// Moves `step` gears up or down, stopping at the first and last.
+    pub fn shift(&mut self, step: i8) {
+        let gear = (self.gear as isize + step as isize).clamp(0, self.gears.len() as isize - 1);
+        self.set_gear(gear as usize);
+    }
+
+    /// Drops the rate limited state so the next update starts from rest.
+    pub fn reset(&mut self) {
+        self.output = [0.0; 6];
+    }
+
+    pub fn update(&mut self, sticks: &Wrench, dt: f32) -> Wrench {
+        let scale = self.gears[self.gear];
+        for ((output, stick), config) in self
+            .output
+            .iter_mut()
+            .zip(sticks.as_array())
+            .zip(&self.axes)
+        {
+            let target = curve(stick, config) * scale;
+            *output = match config.max_rate {
+                Some(rate) => {
+                    let max_step = rate * dt;
+                    *output + (target - *output).clamp(-max_step, max_step)
+                }
+                None => target,
+            };
+        }
+        let [surge, sway, heave, roll, pitch, yaw] = self.output;
+        Wrench {
+            surge,
+            sway,
+            heave,
+            roll,
+            pitch,
+            yaw,
+        }
+    }
+}
+
+fn curve(stick: f32, config: &AxisShapingConfig) -> f32 {
+    let stick = stick.clamp(-1.0, 1.0);
+    let deadzone = config.deadzone.clamp(0.0, 0.99);
+    if stick.abs() <= deadzone {
+        return 0.0;
+    }
+    let magnitude = (stick.abs() - deadzone) / (1.0 - deadzone);
+    let expo = config.expo.clamp(0.0, 1.0);
+    ((1.0 - expo) * magnitude + expo * magnitude.powi(3)).copysign(stick)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn axis(deadzone: f32, expo: f32) -> AxisShapingConfig {
+        AxisShapingConfig {
+            deadzone,
+            expo,
+            max_rate: None,
+        }
+    }
+
+    fn surge(surge: f32) -> Wrench {
+        Wrench {
+            surge,
+            ..Wrench::default()
+        }
+    }
+
+    #[test]
+    fn linear_curve_passes_through() {
+        let config = axis(0.0, 0.0);
+        for stick in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            assert_eq!(curve(stick, &config), stick);
+        }
+        assert_eq!(curve(1.5, &config), 1.0);
+        assert_eq!(curve(-7.0, &config), -1.0);
+    }
+
+    #[test]
+    fn deadzone_rescales_the_rest_of_the_travel() {
+        let config = axis(0.2, 0.0);
+        assert_eq!(curve(0.2, &config), 0.0);
+        assert_eq!(curve(-0.1, &config), 0.0);
+        assert!((curve(0.6, &config) - 0.5).abs() < 1e-6);
+        assert!((curve(-0.6, &config) + 0.5).abs() < 1e-6);
+        assert_eq!(curve(1.0, &config), 1.0);
+    }
+
+    #[test]
+    fn expo_softens_the_centre() {
+        let config = axis(0.0, 1.0);
+        assert!((curve(0.5, &config) - 0.125).abs() < 1e-6);
+        assert!((curve(-0.5, &config) + 0.125).abs() < 1e-6);
+        assert_eq!(curve(1.0, &config), 1.0);
+        let half = axis(0.0, 0.5);
+        assert!((curve(0.5, &half) - 0.3125).abs() < 1e-6);
+        // Out of range settings are clamped.
+        assert_eq!(curve(0.5, &axis(0.0, 3.0)), curve(0.5, &config));
+        assert_eq!(curve(0.98, &axis(1.5, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn gears_scale_and_shift() {
+        let mut shaper = Shaper::new(&ShapingConfig::default());
+        assert_eq!(shaper.gear(), 2);
+        assert_eq!(shaper.update(&surge(0.8), 0.02).surge, 0.8);
+        shaper.shift(-1);
+        assert_eq!(shaper.update(&surge(0.8), 0.02).surge, 0.4);
+        shaper.shift(-5);
+        assert_eq!(shaper.gear(), 0);
+        shaper.shift(1);
+        shaper.shift(1);
+        shaper.shift(1);
+        assert_eq!(shaper.gear(), 2);
+        shaper.set_gear(7);
+        assert_eq!(shaper.gear(), 2);
+    }
+
+    #[test]
+    fn empty_gears_mean_full_scale() {
+        let shaper = Shaper::new(&ShapingConfig {
+            gears: Vec::new(),
+            gear: 4,
+            ..ShapingConfig::default()
+        });
+        assert_eq!(shaper.gears(), &[1.0]);
+        assert_eq!(shaper.gear(), 0);
+    }
+
+    #[test]
+    fn rate_limit_slews_and_resets() {
+        let mut shaper = Shaper::new(&ShapingConfig {
+            surge: AxisShapingConfig {
+                max_rate: Some(2.0),
+                ..AxisShapingConfig::default()
+            },
+            ..ShapingConfig::default()
+        });
+        let mut outputs = Vec::new();
+        for _ in 0..30 {
+            outputs.push(shaper.update(&surge(1.0), 0.02).surge);
+        }
+        assert!((outputs[0] - 0.04).abs() < 1e-6);
+        assert!((outputs[24] - 1.0).abs() < 1e-4);
+        assert_eq!(outputs[29], 1.0);
+        // Other axes are not limited.
+        assert_eq!(
+            shaper
+                .update(
+                    &Wrench {
+                        yaw: 1.0,
+                        ..surge(1.0)
+                    },
+                    0.02
+                )
+                .yaw,
+            1.0
+        );
+        shaper.reset();
+        assert!((shaper.update(&surge(-1.0), 0.02).surge + 0.04).abs() < 1e-6);
+    }
+}
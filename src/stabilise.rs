@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{PidConfig, StabiliseConfig};
+use crate::estimator::Attitude;
+use crate::pid::{Pid, PidInput, PidTerms};
+
+/// How roll and pitch sticks are interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StabiliseMode {
+    /// Sticks go straight to the mixer.
+    Off,
+    /// The vehicle returns to level, sticks push against it.
+    #[default]
+    Level,
+    /// Sticks move a target angle that is held when they are released.
+    Attitude,
+    /// Sticks command an angular rate, zero rate when released.
+    Rate,
+}
+
+impl StabiliseMode {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            StabiliseMode::Off => 0,
+            StabiliseMode::Level => 1,
+            StabiliseMode::Attitude => 2,
+            StabiliseMode::Rate => 3,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<StabiliseMode> {
+        match value {
+            0 => Some(StabiliseMode::Off),
+            1 => Some(StabiliseMode::Level),
+            2 => Some(StabiliseMode::Attitude),
+            3 => Some(StabiliseMode::Rate),
+            _ => None,
+        }
+    }
+}
+
+/// Roll and pitch torque request for the mixer, each in [-1, 1].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Torque {
+    pub roll: f32,
+    pub pitch: f32,
+}
+
+/// One axis of the stabiliser: an angle loop and a rate loop.
+struct Axis {
+    angle: Pid,
+    rate: Pid,
+    target: f32,
+}
+
+impl Axis {
+    fn reset(&mut self, angle: f32) {
+        self.angle.reset();
+        self.rate.reset();
+        self.target = angle;
+    }
+
+    fn update(
+        &mut self,
+        mode: StabiliseMode,
+        config: &StabiliseConfig,
+        stick: f32,
+        angle: f32,
+        rate: f32,
+        dt: f32,
+    ) -> f32 {
+        let feed_forward = stick * config.pilot_feed_forward;
+        match mode {
+            StabiliseMode::Off => {
+                self.target = angle;
+                stick
+            }
+            StabiliseMode::Level => {
+                // A pilot tilting the vehicle on purpose is not an error to
+                // integrate away, the integral waits for the stick to centre.
+                let input = PidInput::new(0.0, angle)
+                    .rate(rate)
+                    .feed_forward(feed_forward)
+                    .hold_integral(stick.abs() > config.deadband);
+                self.angle.update(input, dt)
+            }
+            StabiliseMode::Attitude => {
+                if stick.abs() > config.deadband {
+                    self.target += stick * config.max_rate * dt;
+                    self.target = self.target.clamp(-config.max_angle, config.max_angle);
+                }
+                let input = PidInput::new(self.target, angle)
+                    .rate(rate)
+                    .feed_forward(feed_forward);
+                self.angle.update(input, dt)
+            }
+            StabiliseMode::Rate => {
+                let input = PidInput::new(stick * config.max_rate, rate).feed_forward(feed_forward);
+                self.rate.update(input, dt)
+            }
+        }
+    }
+}
+
+/// Roll and pitch stabilisation.
+///
+/// The output is a torque request that replaces the pilot roll/pitch axes of
+/// the wrench. The pilot sticks are always part of it, either directly as a
+/// feed-forward or as the setpoint, so the pilot can pitch the vehicle on
+/// purpose in every mode.
+pub struct Stabiliser {
+    config: StabiliseConfig,
+    mode: StabiliseMode,
+    roll: Axis,
+    pitch: Axis,
+}
+
+impl Stabiliser {
+    pub fn new(config: StabiliseConfig) -> Self {
+        let axis = |angle_pid| Axis {
+            angle: Pid::new(angle_pid),
+            rate: Pid::new(config.rate_pid),
+            target: 0.0,
+        };
+        Stabiliser {
+            mode: config.mode,
+            roll: axis(config.roll_pid),
+            pitch: axis(config.pitch_pid),
+            config,
+        }
+    }
+
+    pub fn mode(&self) -> StabiliseMode {
+        self.mode
+    }
+
+    /// Target roll and pitch of the attitude hold, in radians.
+    pub fn target(&self) -> (f32, f32) {
+        (self.roll.target, self.pitch.target)
+    }
+
+    /// Terms of the roll and pitch loops in charge in the current mode.
+    pub fn terms(&self) -> [PidTerms; 2] {
+        [&self.roll, &self.pitch].map(|axis| match self.mode {
+            StabiliseMode::Rate => axis.rate.terms(),
+            _ => axis.angle.terms(),
+        })
+    }
+
+    /// Switches mode, starting the new one from the current attitude.
+    pub fn set_mode(&mut self, mode: StabiliseMode, attitude: &Attitude) {
+        if mode == self.mode {
+            return;
+        }
+        println!("stabilisation {:?} -> {:?}", self.mode, mode);
+        self.mode = mode;
+        self.reset(attitude);
+    }
+
+    /// Replaces the roll angle loop gains, keeping its state.
+    pub fn set_roll_pid(&mut self, config: PidConfig) {
+        self.config.roll_pid = config;
+        self.roll.angle.set_config(config);
+    }
+
+    /// Replaces the pitch angle loop gains, keeping its state.
+    pub fn set_pitch_pid(&mut self, config: PidConfig) {
+        self.config.pitch_pid = config;
+        self.pitch.angle.set_config(config);
+    }
+
+    /// Continues in `mode` with the given roll and pitch targets, the loops
+    /// starting over.
+    pub fn restore(&mut self, mode: StabiliseMode, target: (f32, f32), attitude: &Attitude) {
+        self.mode = mode;
+        self.reset(attitude);
+        self.roll.target = target.0;
+        self.pitch.target = target.1;
+    }
+
+    pub fn reset(&mut self, attitude: &Attitude) {
+        self.roll.reset(attitude.roll);
+        self.pitch.reset(attitude.pitch);
+    }
+
+    pub fn update(&mut self, pilot: Torque, attitude: &Attitude, dt: f32) -> Torque {
+        Torque {
+            roll: self.roll.update(
+                self.mode,
+                &self.config,
+                pilot.roll,
+                attitude.roll,
+                attitude.roll_rate,
+                dt,
+            ),
+            pitch: self.pitch.update(
+                self.mode,
+                &self.config,
+                pilot.pitch,
+                attitude.pitch,
+                attitude.pitch_rate,
+                dt,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn tilted(roll: f32) -> Attitude {
+        Attitude {
+            roll,
+            ..Attitude::default()
+        }
+    }
+
+    fn stick(roll: f32) -> Torque {
+        Torque { roll, pitch: 0.0 }
+    }
+
+    #[test]
+    fn off_passes_the_sticks_through() {
+        let mut stabiliser = Stabiliser::new(StabiliseConfig {
+            mode: StabiliseMode::Off,
+            ..StabiliseConfig::default()
+        });
+        for _ in 0..100 {
+            assert_eq!(stabiliser.update(stick(0.4), &tilted(0.3), DT).roll, 0.4);
+        }
+        assert_eq!(stabiliser.target(), (0.3, 0.0));
+        stabiliser.set_mode(StabiliseMode::Level, &tilted(0.3));
+        stabiliser.update(stick(0.0), &tilted(0.3), DT);
+        assert_eq!(stabiliser.terms()[0].i, 0.0);
+    }
+
+    #[test]
+    fn level_freezes_the_integral_while_the_pilot_tilts() {
+        let mut stabiliser = Stabiliser::new(StabiliseConfig::default());
+        for _ in 0..50 {
+            stabiliser.update(stick(0.0), &tilted(0.1), DT);
+        }
+        // Terms show the integral a cycle started from.
+        stabiliser.update(stick(0.3), &tilted(0.2), DT);
+        let integral = stabiliser.terms()[0].i;
+        assert!(integral < 0.0);
+        for _ in 0..200 {
+            stabiliser.update(stick(0.3), &tilted(0.2), DT);
+            assert!(!stabiliser.terms()[0].saturated);
+            assert_eq!(stabiliser.terms()[0].i, integral);
+        }
+        // Inside the deadband the tilt is an error again.
+        stabiliser.update(stick(0.03), &tilted(0.1), DT);
+        stabiliser.update(stick(0.03), &tilted(0.1), DT);
+        assert!(stabiliser.terms()[0].i < integral);
+    }
+
+    #[test]
+    fn off_resets_the_integral() {
+        let mut stabiliser = Stabiliser::new(StabiliseConfig::default());
+        for _ in 0..50 {
+            stabiliser.update(stick(0.0), &tilted(0.1), DT);
+        }
+        stabiliser.update(stick(0.0), &tilted(0.1), DT);
+        assert!(stabiliser.terms()[0].i < 0.0);
+        stabiliser.set_mode(StabiliseMode::Off, &tilted(0.1));
+        assert_eq!(stabiliser.terms()[0], PidTerms::default());
+        for _ in 0..50 {
+            assert_eq!(stabiliser.update(stick(0.4), &tilted(0.1), DT).roll, 0.4);
+        }
+        stabiliser.set_mode(StabiliseMode::Level, &tilted(0.0));
+        assert_eq!(stabiliser.update(stick(0.0), &tilted(0.0), DT).roll, 0.0);
+        assert_eq!(stabiliser.terms()[0].i, 0.0);
+    }
+
+    #[test]
+    fn attitude_moves_the_target_and_holds_it() {
+        let config = StabiliseConfig {
+            mode: StabiliseMode::Attitude,
+            ..StabiliseConfig::default()
+        };
+        let mut stabiliser = Stabiliser::new(config.clone());
+        for _ in 0..25 {
+            stabiliser.update(stick(1.0), &tilted(0.0), DT);
+        }
+        let target = stabiliser.target().0;
+        assert!((target - config.max_rate * 0.5).abs() < 1e-5, "{}", target);
+        // Released or inside the deadband, the target stays and the loop
+        // pushes towards it.
+        assert!(stabiliser.update(stick(0.0), &tilted(0.0), DT).roll > 0.0);
+        stabiliser.update(stick(0.03), &tilted(0.0), DT);
+        assert_eq!(stabiliser.target().0, target);
+        assert!(stabiliser.update(stick(0.0), &tilted(0.6), DT).roll < 0.0);
+        for _ in 0..200 {
+            stabiliser.update(stick(1.0), &tilted(0.0), DT);
+        }
+        assert_eq!(stabiliser.target().0, config.max_angle);
+        for _ in 0..400 {
+            stabiliser.update(stick(-1.0), &tilted(0.0), DT);
+        }
+        assert_eq!(stabiliser.target(), (-config.max_angle, 0.0));
+    }
+
+    #[test]
+    fn rate_follows_the_stick() {
+        let mut stabiliser = Stabiliser::new(StabiliseConfig {
+            mode: StabiliseMode::Rate,
+            ..StabiliseConfig::default()
+        });
+        let turning = |roll_rate| Attitude {
+            roll: 0.3,
+            roll_rate,
+            ..Attitude::default()
+        };
+        // No stick is no rate, whatever the angle.
+        assert_eq!(stabiliser.update(stick(0.0), &turning(0.0), DT).roll, 0.0);
+        assert!(stabiliser.update(stick(0.0), &turning(0.2), DT).roll < 0.0);
+        stabiliser.reset(&turning(0.0));
+        // At the commanded rate only the feed-forward is left.
+        assert_eq!(stabiliser.update(stick(0.5), &turning(0.4), DT).roll, 0.5);
+        assert_eq!(stabiliser.terms()[0].p, 0.0);
+        assert!(stabiliser.update(stick(0.5), &turning(0.0), DT).roll > 0.5);
+    }
+}
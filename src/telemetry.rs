@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use crate::alarms::Alarm;
+use crate::autotune::{TuneAxis, TuneResult};
+use crate::control::ClientInfo;
+use crate::estimator::Attitude;
+use crate::events::Event;
+use crate::mixer::MixReport;
+use crate::power::PowerState;
+use crate::sensors::Sensors;
+use crate::stabilise::StabiliseMode;
+use crate::thermal::ThermalState;
+use crate::vehicle::{Outputs, Vehicle};
+
+/// State of the pilot link.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkTelemetry {
+    /// True while pilot packets arrive within the link timeout.
+    pub connected: bool,
+    /// Age of the last pilot packet, `None` before the first one.
+    pub last_packet_ms: Option<u64>,
+    /// Connected clients and the groups they control.
+    pub clients: Vec<ClientInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThrusterTelemetry {
+    pub name: String,
+    pub pulse: u16,
+    /// Offset from neutral as a fraction of the full offset, in [-1, 1].
+    pub output: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServoTelemetry {
+    pub name: String,
+    /// `None` until the servo is first commanded.
+    pub pulse: Option<u16>,
+    /// Position and target in degrees.
+    pub angle: Option<f32>,
+    pub target: Option<f32>,
+    /// True while a gripper holds its backed off position.
+    pub backed_off: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightTelemetry {
+    pub name: String,
+    /// Fraction of full brightness.
+    pub brightness: f32,
+}
+
+/// Snapshot of the vehicle after one control cycle.
+///
+/// Values computed on the vehicle are made finite, JSON has no NaN or
+/// infinity and clients would fail to read the sample.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Telemetry {
+    /// Vehicle time in seconds.
+    pub time: f64,
+    pub armed: bool,
+    pub link: LinkTelemetry,
+    pub stabilise: StabiliseMode,
+    /// Selected speed gear, an index into `gears`.
+    pub gear: usize,
+    pub gears: Vec<f32>,
+    /// Axis being auto-tuned.
+    pub tuning: Option<TuneAxis>,
+    /// Auto-tune result waiting for confirmation.
+    pub pending_tune: Option<(TuneAxis, TuneResult)>,
+    pub attitude: Attitude,
+    pub thrusters: Vec<ThrusterTelemetry>,
+    pub mix: MixReport,
+    pub servos: Vec<ServoTelemetry>,
+    pub lights: Vec<LightTelemetry>,
+    pub sensors: Sensors,
+    pub power: PowerState,
+    pub thermal: ThermalState,
+    pub alarms: Vec<Alarm>,
+    /// Most recent events, oldest first.
+    pub events: Vec<Event>,
+}
+
+impl Telemetry {
+    pub fn new(
+        vehicle: &Vehicle,
+        sensors: &Sensors,
+        outputs: &Outputs,
+        link: LinkTelemetry,
+    ) -> Telemetry {
+        let servos = vehicle.servos();
+        Telemetry {
+            time: vehicle.time(),
+            armed: vehicle.armed(),
+            link,
+            stabilise: vehicle.stabiliser().mode(),
+            gear: vehicle.shaper().gear(),
+            gears: vehicle
+                .shaper()
+                .gears()
+                .iter()
+                .copied()
+                .map(finite)
+                .collect(),
+            tuning: vehicle.tuning(),
+            pending_tune: vehicle.pending_tune(),
+            attitude: finite_attitude(vehicle.attitude()),
+            thrusters: vehicle
+                .mixer()
+                .thrusters()
+                .iter()
+                .zip(&outputs.thrusters)
+                .map(|(thruster, pulse)| ThrusterTelemetry {
+                    name: thruster.name.clone(),
+                    pulse: *pulse,
+                    output: finite((*pulse as f32 - thruster.neutral as f32) / thruster.max_offset),
+                })
+                .collect(),
+            mix: {
+                let mix = vehicle.mixer().report();
+                MixReport {
+                    estimated_current: finite(mix.estimated_current),
+                    budget_scale: finite(mix.budget_scale),
+                }
+            },
+            servos: servos
+                .configs()
+                .iter()
+                .zip(servos.pulses())
+                .zip(servos.angles())
+                .zip(servos.targets())
+                .zip(servos.backed_off())
+                .map(
+                    |((((config, pulse), angle), target), backed_off)| ServoTelemetry {
+                        name: config.name.clone(),
+                        pulse,
+                        angle,
+                        target,
+                        backed_off,
+                    },
+                )
+                .collect(),
+            lights: vehicle
+                .lights()
+                .configs()
+                .iter()
+                .zip(vehicle.lights().brightness())
+                .map(|(config, brightness)| LightTelemetry {
+                    name: config.name.clone(),
+                    brightness: finite(brightness),
+                })
+                .collect(),
+            sensors: sensors.clone(),
+            power: PowerState {
+                consumed_mah: finite(vehicle.power().consumed_mah),
+                thrust_limit: finite(vehicle.power().thrust_limit),
+                ..vehicle.power()
+            },
+            thermal: ThermalState {
+                thrust_limit: finite(vehicle.thermal().thrust_limit),
+                ..vehicle.thermal()
+            },
+            alarms: vehicle.alarms().active().to_vec(),
+            events: vehicle.events().recent().cloned().collect(),
+        }
+    }
+}
+
+/// NaN and infinities become 0. Optional values need nothing, they arrive
+/// as `None`.
+fn finite(value: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        0.0
+    }
+}
+
+fn finite_attitude(attitude: Attitude) -> Attitude {
+    Attitude {
+        roll: finite(attitude.roll),
+        pitch: finite(attitude.pitch),
+        yaw: finite(attitude.yaw),
+        roll_rate: finite(attitude.roll_rate),
+        pitch_rate: finite(attitude.pitch_rate),
+        yaw_rate: finite(attitude.yaw_rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, ThrusterConfig};
+    use crate::estimator::Calibration;
+
+    #[test]
+    fn non_finite_values_survive_json() {
+        let config = Config {
+            thrusters: vec![ThrusterConfig {
+                max_offset: 0.0,
+                ..ThrusterConfig::defaults()[0].clone()
+            }],
+            ..Config::default()
+        };
+        let vehicle = Vehicle::new(&config, Calibration::default());
+        let outputs = Outputs {
+            thrusters: vec![config.thrusters[0].neutral + 10],
+            servos: Vec::new(),
+            lights: Vec::new(),
+        };
+        let telemetry = Telemetry::new(
+            &vehicle,
+            &Sensors::default(),
+            &outputs,
+            LinkTelemetry::default(),
+        );
+        assert_eq!(telemetry.thrusters[0].output, 0.0);
+        let json = serde_json::to_string(&telemetry).unwrap();
+        assert_eq!(serde_json::from_str::<Telemetry>(&json).unwrap(), telemetry);
+    }
+}
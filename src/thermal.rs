@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use crate::alarms::{self, Alarms, Severity};
+use crate::config::ThermalConfig;
+use crate::sensors::Sensors;
+
+/// Conditions inside the electronics tube.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThermalState {
+    /// Air temperature from the SHT31, degrees Celsius.
+    pub tube_temperature: Option<f32>,
+    /// Relative humidity from the SHT31, percent.
+    pub humidity: Option<f32>,
+    /// MPU6050 die temperature, degrees Celsius.
+    pub imu_temperature: Option<f32>,
+    /// Scale applied to every thruster, 1 is no derating.
+    pub thrust_limit: f32,
+}
+
+impl Default for ThermalState {
+    fn default() -> Self {
+        ThermalState {
+            tube_temperature: None,
+            humidity: None,
+            imu_temperature: None,
+            thrust_limit: 1.0,
+        }
+    }
+}
+
+/// Warns about heat and condensation in the tube, and derates thrust as the
+/// temperature climbs from the warning to the critical threshold.
+pub struct ThermalMonitor {
+    config: ThermalConfig,
+    state: ThermalState,
+    temperature: Option<Severity>,
+    humidity: Option<Severity>,
+}
+
+impl ThermalMonitor {
+    pub fn new(config: ThermalConfig) -> Self {
+        ThermalMonitor {
+            config,
+            state: ThermalState::default(),
+            temperature: None,
+            humidity: None,
+        }
+    }
+
+    pub fn state(&self) -> ThermalState {
+        self.state
+    }
+
+    pub fn update(&mut self, sensors: &Sensors, alarms: &mut Alarms) -> ThermalState {
+        let config = &self.config;
+        self.state.tube_temperature = sensors.climate.map(|c| c.temperature);
+        self.state.humidity = sensors.climate.map(|c| c.humidity);
+        self.state.imu_temperature = sensors.imu_temperature;
+
+        if config.sht31_address.is_some() && sensors.climate.is_none() {
+            alarms.raise(
+                "climate_sensor",
+                Severity::Warning,
+                "no reading from the tube temperature sensor".to_string(),
+            );
+        } else {
+            alarms.clear("climate_sensor");
+        }
+
+        // The hottest of the two sensors decides.
+        let temperature = match (self.state.tube_temperature, self.state.imu_temperature) {
+            (Some(tube), Some(imu)) => Some(tube.max(imu)),
+            (tube, imu) => tube.or(imu),
+        };
+
+        // Derating follows the temperature, only the alarm holds.
+        self.state.thrust_limit = match temperature {
+            Some(t) if t >= config.critical_temperature => config.min_thrust_limit,
+            Some(t) if t >= config.warn_temperature => {
+                let span = config.critical_temperature - config.warn_temperature;
+                let fraction = if span > 0.0 {
+                    (t - config.warn_temperature) / span
+                } else {
+                    1.0
+                };
+                1.0 - fraction * (1.0 - config.min_thrust_limit)
+            }
+            _ => 1.0,
+        };
+        self.temperature = temperature.and_then(|t| {
+            alarms::severity(
+                self.temperature,
+                t,
+                config.warn_temperature,
+                config.critical_temperature,
+                config.temperature_hysteresis,
+            )
+        });
+        match (self.temperature, temperature) {
+            (Some(Severity::Critical), Some(t)) => {
+                alarms.raise(
+                    "temperature",
+                    Severity::Critical,
+                    format!("tube at {:.1}C, thrust derated", t),
+                );
+            }
+            (Some(Severity::Warning), Some(t)) => {
+                alarms.raise(
+                    "temperature",
+                    Severity::Warning,
+                    format!(
+                        "tube at {:.1}C, thrust at {:.0}%",
+                        t,
+                        self.state.thrust_limit * 100.0
+                    ),
+                );
+            }
+            _ => {
+                alarms.clear("temperature");
+            }
+        }
+
+        self.humidity = self.state.humidity.and_then(|h| {
+            alarms::severity(
+                self.humidity,
+                h,
+                config.warn_humidity,
+                config.critical_humidity,
+                config.humidity_hysteresis,
+            )
+        });
+        match (self.humidity, self.state.humidity) {
+            (Some(Severity::Critical), Some(h)) => {
+                alarms.raise(
+                    "humidity",
+                    Severity::Critical,
+                    format!("tube humidity {:.0}%, condensation likely", h),
+                );
+            }
+            (Some(Severity::Warning), Some(h)) => {
+                alarms.raise(
+                    "humidity",
+                    Severity::Warning,
+                    format!("tube humidity {:.0}%", h),
+                );
+            }
+            _ => {
+                alarms.clear("humidity");
+            }
+        }
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sensors::ClimateReading;
+
+    fn climate(temperature: f32, humidity: f32) -> Sensors {
+        Sensors {
+            climate: Some(ClimateReading {
+                temperature,
+                humidity,
+            }),
+            ..Sensors::default()
+        }
+    }
+
+    #[test]
+    fn derates_between_the_thresholds() {
+        let mut monitor = ThermalMonitor::new(ThermalConfig::default());
+        let mut alarms = Alarms::default();
+        assert_eq!(
+            monitor
+                .update(&climate(40.0, 50.0), &mut alarms)
+                .thrust_limit,
+            1.0
+        );
+        let state = monitor.update(&climate(57.5, 50.0), &mut alarms);
+        assert!((state.thrust_limit - 0.65).abs() < 1e-5);
+        assert_eq!(alarms.active()[0].severity, Severity::Warning);
+        assert_eq!(
+            monitor
+                .update(&climate(70.0, 50.0), &mut alarms)
+                .thrust_limit,
+            0.3
+        );
+        assert_eq!(alarms.active()[0].severity, Severity::Critical);
+    }
+
+    #[test]
+    fn alarms_clear_past_the_hysteresis() {
+        let mut monitor = ThermalMonitor::new(ThermalConfig::default());
+        let mut alarms = Alarms::default();
+        monitor.update(&climate(50.5, 86.0), &mut alarms);
+        assert!(alarms.is_active("temperature"));
+        assert!(alarms.is_active("humidity"));
+        let state = monitor.update(&climate(49.0, 83.0), &mut alarms);
+        assert_eq!(state.thrust_limit, 1.0);
+        assert!(alarms.is_active("temperature"));
+        assert_eq!(alarms.active()[1].severity, Severity::Critical);
+        monitor.update(&climate(47.5, 81.0), &mut alarms);
+        assert!(!alarms.is_active("temperature"));
+        assert_eq!(alarms.active()[0].severity, Severity::Warning);
+    }
+}
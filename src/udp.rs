@@ -0,0 +1,258 @@
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use crate::link::ACK_INTERVAL;
+use crate::pilot::{Client, PilotLink};
+use crate::protocol::{self, Message, Reply, MAGIC};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// [`MAGIC`], then the sequence number and the sender clock in milliseconds,
+/// both little endian u32.
+pub const HEADER_LEN: usize = 12;
+
+/// Largest datagram read, anything longer is truncated and rejected.
+const MAX_DATAGRAM: usize = 1500;
+
+/// Builds a datagram carrying framed messages.
+pub fn encode_datagram(seq: u32, timestamp_ms: u32, messages: &[Message]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_LEN + 64);
+    datagram.extend_from_slice(&MAGIC);
+    datagram.extend_from_slice(&seq.to_le_bytes());
+    datagram.extend_from_slice(&timestamp_ms.to_le_bytes());
+    for message in messages {
+        datagram.extend_from_slice(&message.encode());
+    }
+    datagram
+}
+
+/// Splits a datagram into sequence number, timestamp and messages.
+pub fn decode_datagram(datagram: &[u8]) -> io::Result<(u32, u32, Vec<Message>)> {
+    if datagram.len() < HEADER_LEN || datagram[..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "datagram without header",
+        ));
+    }
+    let seq = u32::from_le_bytes(datagram[4..8].try_into().unwrap());
+    let timestamp_ms = u32::from_le_bytes(datagram[8..12].try_into().unwrap());
+    let messages = protocol::decode_frames(&datagram[HEADER_LEN..])?;
+    Ok((seq, timestamp_ms, messages))
+}
+
+/// What was last accepted from one sender.
+struct Freshness {
+    seq: u32,
+    timestamp_ms: u32,
+    /// Smallest difference between our clock and the sender's, the one way
+    /// delay of the fastest packet plus the unknown clock offset.
+    min_offset_ms: f64,
+    accepted: Instant,
+}
+
+impl Freshness {
+    /// `offset_ms` is our clock minus the sender's when the datagram arrived.
+    fn new(seq: u32, timestamp_ms: u32, offset_ms: f64, now: Instant) -> Self {
+        Freshness {
+            seq,
+            timestamp_ms,
+            min_offset_ms: offset_ms,
+            accepted: now,
+        }
+    }
+
+    /// A sender quiet for longer than the timeout may have restarted its
+    /// counters, and is started over.
+    fn expired(&self, now: Instant, max_age: Duration) -> bool {
+        now - self.accepted > max_age
+    }
+
+    /// Takes a datagram newer than the last one accepted and delayed by no
+    /// more than `max_age` compared to the fastest seen.
+    fn accept(
+        &mut self,
+        seq: u32,
+        timestamp_ms: u32,
+        offset_ms: f64,
+        now: Instant,
+        max_age: Duration,
+    ) -> bool {
+        if (seq.wrapping_sub(self.seq) as i32) <= 0
+            || (timestamp_ms.wrapping_sub(self.timestamp_ms) as i32) < 0
+        {
+            return false;
+        }
+        // Let the reference drift by up to 1ms/s so clocks running at
+        // slightly different rates are not taken for delay.
+        let drift = (now - self.accepted).as_secs_f64();
+        self.min_offset_ms = offset_ms.min(self.min_offset_ms + drift);
+        if offset_ms - self.min_offset_ms > max_age.as_secs_f64() * 1000.0 {
+            return false;
+        }
+        self.seq = seq;
+        self.timestamp_ms = timestamp_ms;
+        self.accepted = now;
+        true
+    }
+}
+
+struct Sender {
+    freshness: Freshness,
+    acked: Option<Instant>,
+    client: Client,
+}
+
+/// Receives pilot datagrams and applies only the newest one.
+///
+/// TCP holds back every update behind a lost segment. Here a datagram older
+/// than the last one accepted from the same sender is dropped, as is one
+/// delayed by more than `max_age` compared to the fastest seen, so a burst
+/// after a dropout never replays stale sticks. Commands travel in the same
+/// datagrams and are not retransmitted, clients repeat them until they see
+/// the effect. Session requests are answered with a bare [`Reply`] frame,
+/// as are the acks sent at most every [`ACK_INTERVAL`] while datagrams
+/// arrive. Senders quiet for longer than `max_age` are dropped, releasing
+/// their control groups.
+pub async fn serve(socket: UdpSocket, link: PilotLink, max_age: Duration) {
+    let start = Instant::now();
+    let mut senders: HashMap<SocketAddr, Sender> = HashMap::new();
+    let mut buf = [0u8; MAX_DATAGRAM];
+    let mut prune = time::interval(max_age.max(Duration::from_millis(1)));
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            _ = prune.tick() => {
+                let now = Instant::now();
+                senders.retain(|_, sender| !sender.freshness.expired(now, max_age));
+                continue;
+            }
+        };
+        let (len, addr) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                println!("udp receive failed: {}", e);
+                continue;
+            }
+        };
+        let (seq, timestamp_ms, messages) = match decode_datagram(&buf[..len]) {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                println!("bad datagram from {}: {}", addr, e);
+                continue;
+            }
+        };
+
+        let now = Instant::now();
+        let offset_ms = (now - start).as_secs_f64() * 1000.0 - timestamp_ms as f64;
+        match senders.get_mut(&addr) {
+            Some(sender) if !sender.freshness.expired(now, max_age) => {
+                if !sender
+                    .freshness
+                    .accept(seq, timestamp_ms, offset_ms, now, max_age)
+                {
+                    continue;
+                }
+            }
+            _ => {
+                let sender = Sender {
+                    freshness: Freshness::new(seq, timestamp_ms, offset_ms, now),
+                    acked: None,
+                    client: link.connect(format!("udp {}", addr)),
+                };
+                senders.insert(addr, sender);
+            }
+        }
+
+        let sender = senders.get_mut(&addr).expect("sender was just added");
+        let client = &mut sender.client;
+        let stamp = Message::Stamp { seq, timestamp_ms };
+        for message in std::iter::once(stamp).chain(messages) {
+            match client.message(message) {
+                Ok(Some(status)) => {
+                    let _ = socket.send_to(&Reply::Control(status).encode(), addr).await;
+                }
+                Ok(None) => {}
+                Err(e) => println!("client {}: dropped message: {}", client.id(), e),
+            }
+        }
+        if sender.acked.is_none_or(|acked| now - acked >= ACK_INTERVAL) {
+            if let Some(ack) = client.ack() {
+                sender.acked = Some(now);
+                let _ = socket.send_to(&Reply::Ack(ack).encode(), addr).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::Command;
+
+    const MAX_AGE: Duration = Duration::from_millis(200);
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn datagram_round_trip() {
+        let messages = vec![
+            Message::Command(Command::SetArmed(true)),
+            Message::Command(Command::SetGear(1)),
+        ];
+        let datagram = encode_datagram(7, 1234, &messages);
+        assert_eq!(decode_datagram(&datagram).unwrap(), (7, 1234, messages));
+        assert_eq!(decode_datagram(&datagram[..HEADER_LEN]).unwrap().2, vec![]);
+    }
+
+    #[test]
+    fn rejects_bad_datagrams() {
+        let datagram = encode_datagram(7, 1234, &[Message::Command(Command::AbortTune)]);
+        assert!(decode_datagram(&datagram[..HEADER_LEN - 1]).is_err());
+        let mut bad_magic = datagram.clone();
+        bad_magic[0] = b'X';
+        assert!(decode_datagram(&bad_magic).is_err());
+        let mut truncated = encode_datagram(7, 1234, &[Message::Command(Command::SetArmed(true))]);
+        truncated.pop();
+        assert!(decode_datagram(&truncated).is_err());
+    }
+
+    #[test]
+    fn drops_out_of_order_and_duplicate_datagrams() {
+        let start = Instant::now();
+        let mut freshness = Freshness::new(10, 1000, 5.0, start);
+        assert!(!freshness.accept(10, 1000, 5.0, ms(start, 1), MAX_AGE));
+        assert!(!freshness.accept(9, 980, 5.0, ms(start, 2), MAX_AGE));
+        assert!(freshness.accept(12, 1040, 5.0, ms(start, 40), MAX_AGE));
+        // Arrived after 12, though sent before it.
+        assert!(!freshness.accept(11, 1020, 25.0, ms(start, 45), MAX_AGE));
+        // A newer sequence number with an older clock is a confused sender.
+        assert!(!freshness.accept(13, 1030, 20.0, ms(start, 50), MAX_AGE));
+        assert!(freshness.accept(13, 1060, 5.0, ms(start, 60), MAX_AGE));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        let start = Instant::now();
+        let mut freshness = Freshness::new(u32::MAX, 1000, 5.0, start);
+        assert!(freshness.accept(0, 1020, 5.0, ms(start, 20), MAX_AGE));
+        assert!(!freshness.accept(u32::MAX, 1000, 5.0, ms(start, 21), MAX_AGE));
+    }
+
+    #[test]
+    fn drops_stale_datagrams() {
+        let start = Instant::now();
+        let mut freshness = Freshness::new(1, 1000, 5.0, start);
+        // A burst after a dropout: sent 20ms apart, all arriving at once.
+        assert!(!freshness.accept(2, 1020, 305.0, ms(start, 320), MAX_AGE));
+        assert!(!freshness.accept(3, 1040, 285.0, ms(start, 320), MAX_AGE));
+        assert!(freshness.accept(4, 1300, 25.0, ms(start, 320), MAX_AGE));
+        assert!(!freshness.expired(ms(start, 520), MAX_AGE));
+        assert!(freshness.expired(ms(start, 521), MAX_AGE));
+    }
+}
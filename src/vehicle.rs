@@ -0,0 +1,586 @@
+use serde::{Deserialize, Serialize};
+
+use crate::alarms::{Alarms, Severity};
+use crate::autotune::{AutoTune, TuneAxis, TuneResult, TuneStep};
+use crate::config::{Config, PidConfig};
+use crate::estimator::{wrap_angle, Attitude, Calibration, Estimator};
+use crate::events::Events;
+use crate::heading::HeadingHold;
+use crate::leak::{LeakDetector, LeakResponse};
+use crate::lights::Lights;
+use crate::mixer::{MixReport, Mixer, Wrench};
+use crate::pid::PidTerms;
+use crate::pilot::PilotInput;
+use crate::power::{PowerMonitor, PowerState};
+use crate::protocol::Command;
+use crate::sensors::Sensors;
+use crate::servo::Servos;
+use crate::shaping::Shaper;
+use crate::stabilise::{StabiliseMode, Stabiliser, Torque};
+use crate::thermal::{ThermalMonitor, ThermalState};
+
+/// Everything the control loop writes to the PCA9685 in one cycle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outputs {
+    /// One pulse per thruster, in the order of the configuration.
+    pub thrusters: Vec<u16>,
+    /// One pulse per servo, in the order of the configuration, `None` until
+    /// the servo is first commanded.
+    pub servos: Vec<Option<u16>>,
+    /// One pulse per light, in the order of the configuration.
+    pub lights: Vec<u16>,
+}
+
+/// Intermediate values of the last control cycle, for the flight recorder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Internals {
+    /// Pilot wrench after shaping and gear.
+    pub shaped: Wrench,
+    /// Wrench handed to the mixer, after heading hold and stabilisation.
+    pub wrench: Wrench,
+    pub heading_target: Option<f32>,
+    pub heading_terms: PidTerms,
+    /// Roll and pitch targets of the stabiliser, in radians.
+    pub attitude_target: (f32, f32),
+    /// Roll and pitch loops in charge.
+    pub stabiliser_terms: [PidTerms; 2],
+    pub thrust_limit: f32,
+    pub mix: MixReport,
+}
+
+/// What the vehicle carries from one cycle to the next and the pilot set
+/// up during the dive, beside the parameters. The flight recorder writes it
+/// at the top of every file after the first, so a replay of a later file
+/// starts from where the vehicle was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VehicleState {
+    pub time: f64,
+    pub armed: bool,
+    pub attitude: Attitude,
+    pub stabilise: StabiliseMode,
+    /// Roll and pitch targets of the attitude hold, in radians.
+    pub attitude_target: (f32, f32),
+    pub heading_target: Option<f32>,
+    pub gear: usize,
+    /// Brightness asked for every light, in percent.
+    pub lights: Vec<u8>,
+    pub servo_angles: Vec<Option<f32>>,
+    pub servo_targets: Vec<Option<f32>>,
+    pub grippers_backed_off: Vec<bool>,
+}
+
+/// A relay experiment in progress, with the setpoint it oscillates around.
+struct Tuning {
+    autotune: AutoTune,
+    setpoint: f32,
+}
+
+/// The control pipeline: estimator, controllers and mixer.
+///
+/// Holds no hardware so it can be driven from recorded data as well as from
+/// the sensors.
+pub struct Vehicle {
+    config: Config,
+    config_changed: bool,
+    armed: bool,
+    /// Seconds of control cycles run so far.
+    time: f64,
+    alarms: Alarms,
+    events: Events,
+    leak: LeakDetector,
+    leaking: bool,
+    power: PowerMonitor,
+    thermal: ThermalMonitor,
+    estimator: Estimator,
+    shaper: Shaper,
+    heading: HeadingHold,
+    stabiliser: Stabiliser,
+    mixer: Mixer,
+    lights: Lights,
+    servos: Servos,
+    tuning: Option<Tuning>,
+    pending_tune: Option<(TuneAxis, TuneResult)>,
+    /// Seconds since the power budget last scaled the thrust.
+    budget_quiet_s: f32,
+    internals: Internals,
+}
+
+impl Vehicle {
+    pub fn new(config: &Config, calibration: Calibration) -> Self {
+        Vehicle {
+            config: config.clone(),
+            config_changed: false,
+            armed: config.arm_on_start,
+            time: 0.0,
+            alarms: Alarms::default(),
+            events: Events::default(),
+            leak: LeakDetector::new(config.leak.clone()),
+            leaking: false,
+            power: PowerMonitor::new(
+                config.power.clone(),
+                config
+                    .ads1115
+                    .as_ref()
+                    .map(|ads| ads.channels.as_slice())
+                    .unwrap_or_default(),
+            ),
+            thermal: ThermalMonitor::new(config.thermal.clone()),
+            estimator: Estimator::new(calibration, config.imu.gyro_weight),
+            shaper: Shaper::new(&config.shaping),
+            heading: HeadingHold::new(config.heading.clone()),
+            stabiliser: Stabiliser::new(config.stabilise.clone()),
+            mixer: Mixer::new(config.thrusters.clone(), config.power_budget.clone()),
+            lights: Lights::new(config.lights.clone()),
+            servos: Servos::new(
+                config.servos.clone(),
+                config.servo_presets.clone(),
+                config
+                    .ads1115
+                    .as_ref()
+                    .map(|ads| ads.channels.as_slice())
+                    .unwrap_or_default(),
+            ),
+            tuning: None,
+            pending_tune: None,
+            budget_quiet_s: 0.0,
+            internals: Internals::default(),
+        }
+    }
+
+    /// Parameters currently in use, including accepted auto-tune gains.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// True once after the parameters changed and should be saved.
+    pub fn take_config_change(&mut self) -> bool {
+        std::mem::take(&mut self.config_changed)
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn alarms(&self) -> &Alarms {
+        &self.alarms
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    pub fn power(&self) -> PowerState {
+        self.power.state()
+    }
+
+    pub fn thermal(&self) -> ThermalState {
+        self.thermal.state()
+    }
+
+    pub fn attitude(&self) -> Attitude {
+        self.estimator.attitude()
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    pub fn lights(&self) -> &Lights {
+        &self.lights
+    }
+
+    pub fn servos(&self) -> &Servos {
+        &self.servos
+    }
+
+    pub fn stabiliser(&self) -> &Stabiliser {
+        &self.stabiliser
+    }
+
+    pub fn shaper(&self) -> &Shaper {
+        &self.shaper
+    }
+
+    pub fn internals(&self) -> &Internals {
+        &self.internals
+    }
+
+    pub fn state(&self) -> VehicleState {
+        VehicleState {
+            time: self.time,
+            armed: self.armed,
+            attitude: self.estimator.attitude(),
+            stabilise: self.stabiliser.mode(),
+            attitude_target: self.stabiliser.target(),
+            heading_target: self.heading.target(),
+            gear: self.shaper.gear(),
+            lights: self.lights.percent(),
+            servo_angles: self.servos.angles(),
+            servo_targets: self.servos.targets(),
+            grippers_backed_off: self.servos.backed_off(),
+        }
+    }
+
+    /// Continues from a state taken with [`Vehicle::state`]. Controller
+    /// integrators, filters and auto-tune runs start over.
+    pub fn restore(&mut self, state: &VehicleState) {
+        self.time = state.time;
+        self.armed = state.armed;
+        self.estimator.restore(state.attitude);
+        self.stabiliser
+            .restore(state.stabilise, state.attitude_target, &state.attitude);
+        self.heading.restore(state.heading_target);
+        self.shaper.set_gear(state.gear);
+        for (index, percent) in state.lights.iter().enumerate() {
+            self.lights.set(index as u8, *percent);
+        }
+        self.servos.restore(
+            &state.servo_angles,
+            &state.servo_targets,
+            &state.grippers_backed_off,
+        );
+    }
+
+    /// Axis being auto-tuned, if any.
+    pub fn tuning(&self) -> Option<TuneAxis> {
+        self.tuning.as_ref().map(|t| t.autotune.axis())
+    }
+
+    /// Auto-tune result waiting for the pilot to accept or discard it.
+    pub fn pending_tune(&self) -> Option<(TuneAxis, TuneResult)> {
+        self.pending_tune
+    }
+
+    /// Applies a discrete pilot command.
+    pub fn handle(&mut self, command: &Command) {
+        let attitude = self.estimator.attitude();
+        match command {
+            Command::SetArmed(armed) => self.set_armed(*armed, "pilot"),
+            Command::SetLight { index, percent } => self.lights.set(*index, *percent),
+            Command::SetServo { index, degrees } => {
+                self.servos.set_angle(*index as usize, *degrees)
+            }
+            Command::ServoPreset(name) => self.servos.apply_preset(name),
+            Command::SetServoPulse { index, pulse } => {
+                self.servos.set_pulse(*index as usize, *pulse)
+            }
+            Command::SetStabilise(mode) => {
+                self.stabiliser.set_mode(*mode, &attitude);
+            }
+            Command::SetGear(gear) => self.shaper.set_gear(*gear as usize),
+            Command::ShiftGear(step) => self.shaper.shift(*step),
+            Command::StartTune(axis) => {
+                if self.tuning.is_some() {
+                    println!("auto-tune already running");
+                    return;
+                }
+                let setpoint = match axis {
+                    TuneAxis::Roll => attitude.roll,
+                    TuneAxis::Pitch => attitude.pitch,
+                    TuneAxis::Yaw => attitude.yaw,
+                };
+                let autotune = match AutoTune::new(
+                    *axis,
+                    self.config.autotune.clone(),
+                    self.pid_config(*axis),
+                ) {
+                    Ok(autotune) => autotune,
+                    Err(e) => {
+                        println!("auto-tune {:?} not started: {}", axis, e);
+                        return;
+                    }
+                };
+                println!("auto-tune {:?} started", axis);
+                self.pending_tune = None;
+                self.tuning = Some(Tuning { autotune, setpoint });
+            }
+            Command::AbortTune => self.stop_tuning("aborted by pilot"),
+            Command::ConfirmTune(accept) => match self.pending_tune.take() {
+                Some((axis, result)) if *accept => {
+                    println!("auto-tune {:?} accepted: {:?}", axis, result.proposed);
+                    self.set_pid_config(axis, result.proposed);
+                    self.config_changed = true;
+                }
+                Some((axis, _)) => println!("auto-tune {:?} discarded", axis),
+                None => println!("no auto-tune result to confirm"),
+            },
+        }
+    }
+
+    fn set_armed(&mut self, armed: bool, by: &str) {
+        if armed != self.armed {
+            println!("{} by {}", if armed { "armed" } else { "disarmed" }, by);
+        }
+        self.armed = armed;
+    }
+
+    /// Debounces the leak sensors, raises the alarm and applies the
+    /// configured response when water first shows up.
+    fn check_leak(&mut self, levels: &[bool], dt: f32) {
+        let leaking = self.leak.update(levels, dt);
+        if leaking {
+            let message = format!("water detected by {}", self.leak.wet_sensors().join(", "));
+            self.alarms.raise("leak", Severity::Critical, message);
+            if !self.leaking && self.leak.config().response == LeakResponse::Disarm {
+                self.set_armed(false, "leak response");
+            }
+        } else {
+            self.alarms.clear("leak");
+        }
+        self.leaking = leaking;
+    }
+
+    fn pid_config(&self, axis: TuneAxis) -> PidConfig {
+        match axis {
+            TuneAxis::Roll => self.config.stabilise.roll_pid,
+            TuneAxis::Pitch => self.config.stabilise.pitch_pid,
+            TuneAxis::Yaw => self.config.heading.pid,
+        }
+    }
+
+    fn set_pid_config(&mut self, axis: TuneAxis, pid: PidConfig) {
+        match axis {
+            TuneAxis::Roll => {
+                self.config.stabilise.roll_pid = pid;
+                self.stabiliser.set_roll_pid(pid);
+            }
+            TuneAxis::Pitch => {
+                self.config.stabilise.pitch_pid = pid;
+                self.stabiliser.set_pitch_pid(pid);
+            }
+            TuneAxis::Yaw => {
+                self.config.heading.pid = pid;
+                self.heading.set_pid(pid);
+            }
+        }
+    }
+
+    fn stop_tuning(&mut self, reason: &str) {
+        if let Some(tuning) = self.tuning.take() {
+            println!("auto-tune {:?} stopped: {}", tuning.autotune.axis(), reason);
+            let attitude = self.estimator.attitude();
+            self.stabiliser.reset(&attitude);
+            self.heading.reset();
+        }
+    }
+
+    /// Runs the relay experiment and writes its output on the tuned axis.
+    fn run_tuning(
+        &mut self,
+        pilot: &PilotInput,
+        attitude: &Attitude,
+        dt: f32,
+        torque: &mut Torque,
+        yaw: &mut f32,
+    ) {
+        let tuning = match self.tuning.as_mut() {
+            Some(tuning) => tuning,
+            None => return,
+        };
+        let axis = tuning.autotune.axis();
+        let (stick, offset) = match axis {
+            TuneAxis::Roll => (pilot.wrench.roll, attitude.roll - tuning.setpoint),
+            TuneAxis::Pitch => (pilot.wrench.pitch, attitude.pitch - tuning.setpoint),
+            TuneAxis::Yaw => (pilot.wrench.yaw, wrap_angle(attitude.yaw - tuning.setpoint)),
+        };
+        if stick.abs() > self.config.stabilise.deadband {
+            self.stop_tuning("pilot took over");
+            return;
+        }
+
+        match tuning.autotune.update(offset, dt) {
+            TuneStep::Running(output) => match axis {
+                TuneAxis::Roll => torque.roll = output,
+                TuneAxis::Pitch => torque.pitch = output,
+                TuneAxis::Yaw => *yaw = output,
+            },
+            TuneStep::Done(result) => {
+                println!(
+                    "auto-tune {:?}: Ku {:.3}, Tu {:.2}s, proposed {:?}, waiting for confirmation",
+                    axis, result.ultimate_gain, result.ultimate_period, result.proposed
+                );
+                self.pending_tune = Some((axis, result));
+                self.stop_tuning("done");
+            }
+            TuneStep::Failed(reason) => self.stop_tuning(&reason),
+        }
+    }
+
+    /// Runs one control cycle. `pilot` is `None` when the link is down, in
+    /// which case the thrusters are left at neutral, as they are while
+    /// disarmed.
+    pub fn step(&mut self, pilot: Option<&PilotInput>, sensors: &Sensors, dt: f32) -> Outputs {
+        self.time += dt as f64;
+        self.internals = Internals::default();
+        let outputs = self.control(pilot, sensors, dt);
+        self.internals.heading_target = self.heading.target();
+        self.internals.heading_terms = self.heading.terms();
+        self.internals.attitude_target = self.stabiliser.target();
+        self.internals.stabiliser_terms = self.stabiliser.terms();
+        self.internals.thrust_limit = self.mixer.thrust_limit();
+        self.internals.mix = self.mixer.report();
+
+        let report = self.mixer.report();
+        if report.budget_limited() {
+            self.budget_quiet_s = 0.0;
+            let message = format!(
+                "thrust scaled to {:.0}% to stay within {:.1}A",
+                report.budget_scale * 100.0,
+                self.config.power_budget.max_current
+            );
+            self.alarms
+                .raise("power_budget", Severity::Warning, message);
+        } else {
+            self.budget_quiet_s += dt;
+            if self.budget_quiet_s >= self.config.power_budget.alarm_hold_s {
+                self.alarms.clear("power_budget");
+            }
+        }
+        outputs
+    }
+
+    fn control(&mut self, pilot: Option<&PilotInput>, sensors: &Sensors, dt: f32) -> Outputs {
+        let attitude = self.estimator.update(&sensors.imu, dt);
+        self.check_leak(&sensors.leak, dt);
+        let power = self.power.update(sensors, dt, &mut self.alarms);
+        let thermal = self.thermal.update(sensors, &mut self.alarms);
+        self.mixer
+            .set_thrust_limit(power.thrust_limit.min(thermal.thrust_limit));
+        let surfacing = self.leaking && self.leak.config().response == LeakResponse::Surface;
+
+        // Servos keep moving to their last target when the link drops.
+        self.servos.update(dt, &sensors.adc);
+        for message in self.servos.take_events() {
+            self.events.push(self.time, "gripper", message);
+        }
+        let servos = self.servos.pulses();
+
+        let pilot = match pilot {
+            Some(pilot) => pilot,
+            None => {
+                self.stop_tuning("link lost");
+                self.shaper.reset();
+                self.heading.reset();
+                self.stabiliser.reset(&attitude);
+                // The surface response does not need the pilot.
+                let thrusters = if surfacing && self.armed {
+                    self.internals.wrench = Wrench {
+                        heave: self.leak.config().surface_heave,
+                        ..Wrench::default()
+                    };
+                    self.mixer.mix(&self.internals.wrench)
+                } else {
+                    self.mixer.neutral()
+                };
+                return Outputs {
+                    thrusters,
+                    servos,
+                    lights: self.lights.pulses(),
+                };
+            }
+        };
+
+        if !self.armed {
+            self.stop_tuning("disarmed");
+            self.shaper.reset();
+            self.heading.reset();
+            self.stabiliser.reset(&attitude);
+            return Outputs {
+                thrusters: self.mixer.neutral(),
+                servos,
+                lights: self.lights.pulses(),
+            };
+        }
+
+        let mut wrench = self.shaper.update(&pilot.wrench, dt);
+        self.internals.shaped = wrench;
+        if surfacing {
+            wrench.heave = self.leak.config().surface_heave;
+        }
+        wrench.yaw = self.heading.update(wrench.yaw, &attitude, dt);
+        let pilot_torque = Torque {
+            roll: wrench.roll,
+            pitch: wrench.pitch,
+        };
+        let mut torque = self.stabiliser.update(pilot_torque, &attitude, dt);
+        self.run_tuning(pilot, &attitude, dt, &mut torque, &mut wrench.yaw);
+        wrench.roll = torque.roll;
+        wrench.pitch = torque.pitch;
+        self.internals.wrench = wrench;
+
+        Outputs {
+            thrusters: self.mixer.mix(&wrench),
+            servos,
+            lights: self.lights.pulses(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::PowerBudgetConfig;
+
+    const DT: f32 = 0.02;
+
+    fn pilot(stick: f32) -> PilotInput {
+        PilotInput {
+            wrench: Wrench {
+                surge: stick,
+                heave: stick,
+                yaw: stick,
+                ..Wrench::default()
+            },
+            servos: [f32::NAN; 5],
+        }
+    }
+
+    #[test]
+    fn power_budget_alarm_holds_through_short_releases() {
+        let config = Config {
+            power_budget: PowerBudgetConfig {
+                max_current: 5.0,
+                ..PowerBudgetConfig::default()
+            },
+            ..Config::default()
+        };
+        let mut vehicle = Vehicle::new(&config, Calibration::default());
+        let sensors = Sensors::default();
+        for _ in 0..50 {
+            vehicle.step(Some(&pilot(1.0)), &sensors, DT);
+        }
+        assert!(vehicle.alarms().is_active("power_budget"));
+        for _ in 0..10 {
+            vehicle.step(Some(&pilot(0.0)), &sensors, DT);
+            assert!(vehicle.alarms().is_active("power_budget"));
+        }
+        for _ in 0..50 {
+            vehicle.step(Some(&pilot(0.0)), &sensors, DT);
+        }
+        assert!(!vehicle.alarms().is_active("power_budget"));
+    }
+
+    #[test]
+    fn servos_move_on_commands_only() {
+        let mut vehicle = Vehicle::new(&Config::default(), Calibration::default());
+        let sensors = Sensors::default();
+        let input = PilotInput {
+            servos: [300.0; 5],
+            ..pilot(0.0)
+        };
+        let outputs = vehicle.step(Some(&input), &sensors, DT);
+        assert!(outputs.servos.iter().all(Option::is_none));
+        vehicle.handle(&Command::SetServoPulse {
+            index: 0,
+            pulse: 300.0,
+        });
+        let outputs = vehicle.step(Some(&input), &sensors, DT);
+        assert!(outputs.servos[0].is_some());
+        assert!(outputs.servos[1..].iter().all(Option::is_none));
+    }
+}
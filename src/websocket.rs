@@ -0,0 +1,337 @@
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role as WsRole;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::control::{ControlGroup, Role};
+use crate::http::{parse_rate, status};
+use crate::link::ACK_INTERVAL;
+use crate::mixer::Wrench;
+use crate::pilot::{Client, PilotInput, PilotLink};
+use crate::protocol::{self, Command, Message, Session};
+use crate::telemetry::Telemetry;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Default and highest rate of the telemetry pushed to a WebSocket client.
+const TELEMETRY_RATE: f32 = 10.0;
+const MAX_TELEMETRY_RATE: f32 = 50.0;
+
+/// Messages waiting for a slow client before telemetry and acks are dropped.
+const SEND_QUEUE: usize = 8;
+
+/// JSON form of a pilot input. Missing axes are zero, missing or null servo
+/// pulses leave the servo where it is.
+#[derive(Debug, Deserialize)]
+struct JsonPilot {
+    #[serde(default)]
+    wrench: Wrench,
+    #[serde(default)]
+    servos: Vec<Option<f32>>,
+}
+
+/// JSON text frame from the client, e.g. `{"pilot": {"wrench": {"surge":
+/// 0.5}}}`, `{"command": {"set_armed": true}}`, `{"take": ["flight"]}` or
+/// `{"stamp": {"seq": 7, "timestamp_ms": 1200}}`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JsonMessage {
+    Pilot(JsonPilot),
+    Command(Command),
+    Hello(Role),
+    Take(Vec<ControlGroup>),
+    Release(Vec<ControlGroup>),
+    Stamp { seq: u32, timestamp_ms: u32 },
+}
+
+impl From<JsonMessage> for Message {
+    fn from(message: JsonMessage) -> Message {
+        match message {
+            JsonMessage::Pilot(pilot) => {
+                let mut servos = [f32::NAN; 5];
+                for (servo, pulse) in servos.iter_mut().zip(pilot.servos) {
+                    *servo = pulse.unwrap_or(f32::NAN);
+                }
+                Message::Pilot(PilotInput {
+                    wrench: pilot.wrench,
+                    servos,
+                })
+            }
+            JsonMessage::Command(command) => Message::Command(command),
+            JsonMessage::Hello(role) => Message::Session(Session::Hello(role)),
+            JsonMessage::Take(groups) => Message::Session(Session::Take(groups)),
+            JsonMessage::Release(groups) => Message::Session(Session::Release(groups)),
+            JsonMessage::Stamp { seq, timestamp_ms } => Message::Stamp { seq, timestamp_ms },
+        }
+    }
+}
+
+/// Answers a WebSocket handshake and runs the session once upgraded.
+///
+/// Clients send pilot inputs and commands as JSON text frames or as binary
+/// frames holding framed protocol messages, and get telemetry back as JSON
+/// text frames at `?rate=HZ`. Session requests are answered with
+/// `{"control": {"role": ..., "groups": [...]}}`, stamps with an
+/// `{"ack": {...}}` every [`ACK_INTERVAL`].
+pub fn upgrade(
+    mut request: Request<Body>,
+    rate: Option<&str>,
+    addr: SocketAddr,
+    link: PilotLink,
+    telemetry: watch::Receiver<Telemetry>,
+) -> Response<Body> {
+    let rate = match parse_rate(rate, TELEMETRY_RATE, MAX_TELEMETRY_RATE) {
+        Some(rate) => rate,
+        None => return status(StatusCode::BAD_REQUEST),
+    };
+    let is_upgrade = request
+        .headers()
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = match request.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) if is_upgrade => key.as_bytes().to_vec(),
+        _ => return status(StatusCode::BAD_REQUEST),
+    };
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut request).await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, WsRole::Server, None).await;
+                let client = link.connect(format!("websocket {}", addr));
+                session(socket, rate, client, telemetry).await;
+            }
+            Err(e) => println!("websocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(&key))
+        .body(Body::empty())
+        .expect("static response parts are valid")
+}
+
+/// Reads pilot messages and queues replies, acks and telemetry for
+/// [`write`]. A client that reads slowly misses telemetry samples and acks,
+/// which are sent again fresh, rather than being sent ever older ones;
+/// replies to its messages wait for room.
+async fn session(
+    socket: WebSocketStream<Upgraded>,
+    rate: f32,
+    mut client: Client,
+    mut telemetry: watch::Receiver<Telemetry>,
+) {
+    let (sink, mut stream) = socket.split();
+    let (outgoing, queue) = mpsc::channel(SEND_QUEUE);
+    tokio::spawn(write(sink, queue));
+    let mut interval = time::interval(Duration::from_secs_f32(1.0 / rate));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let mut acks = time::interval(ACK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            received = stream.next() => {
+                let replies = match received {
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(frame)) => replies(&mut client, frame),
+                };
+                for reply in replies {
+                    if outgoing.send(WsMessage::Text(reply.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = acks.tick() => {
+                if let Some(ack) = client.ack() {
+                    let reply = serde_json::json!({ "ack": ack }).to_string();
+                    if let Err(mpsc::error::TrySendError::Closed(_)) =
+                        outgoing.try_send(WsMessage::Text(reply))
+                    {
+                        break;
+                    }
+                }
+            }
+            _ = interval.tick() => {
+                let sample = match serde_json::to_string(&*telemetry.borrow_and_update()) {
+                    Ok(sample) => sample,
+                    Err(e) => {
+                        println!("failed to encode telemetry: {}", e);
+                        break;
+                    }
+                };
+                if let Err(mpsc::error::TrySendError::Closed(_)) =
+                    outgoing.try_send(WsMessage::Text(sample))
+                {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Applies the messages of a frame from the client and returns what to
+/// answer.
+fn replies(client: &mut Client, frame: WsMessage) -> Vec<serde_json::Value> {
+    let messages = match frame {
+        WsMessage::Text(text) => serde_json::from_str::<JsonMessage>(&text)
+            .map(|message| vec![message.into()])
+            .map_err(|e| e.to_string()),
+        WsMessage::Binary(data) => protocol::decode_frames(&data).map_err(|e| e.to_string()),
+        // Pings are answered by tungstenite.
+        _ => Ok(Vec::new()),
+    };
+    match messages {
+        Ok(messages) => messages
+            .into_iter()
+            .filter_map(|message| match client.message(message) {
+                Ok(Some(status)) => Some(serde_json::json!({ "control": status })),
+                Ok(None) => None,
+                Err(e) => Some(serde_json::json!({ "error": e })),
+            })
+            .collect(),
+        Err(e) => vec![serde_json::json!({ "error": e })],
+    }
+}
+
+/// Sends the queued messages until the client goes away.
+async fn write(
+    mut sink: SplitSink<WebSocketStream<Upgraded>, WsMessage>,
+    mut queue: mpsc::Receiver<WsMessage>,
+) {
+    while let Some(message) = queue.recv().await {
+        if sink.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pilot::PilotState;
+
+    fn link() -> (
+        PilotLink,
+        watch::Receiver<PilotState>,
+        mpsc::UnboundedReceiver<Command>,
+    ) {
+        let (tx, state) = watch::channel(None);
+        let (commands, rx) = mpsc::unbounded_channel();
+        (
+            PilotLink::new(tx, commands, Duration::from_secs(1)),
+            state,
+            rx,
+        )
+    }
+
+    fn text(json: &str) -> WsMessage {
+        WsMessage::Text(json.to_string())
+    }
+
+    #[test]
+    fn rejects_bad_rates_and_plain_requests() {
+        let (link, _state, _commands) = link();
+        let (_tx, telemetry) = watch::channel(Telemetry::default());
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        for rate in [
+            Some("1e-39"),
+            Some("0"),
+            Some("-5"),
+            Some("NaN"),
+            Some("fast"),
+        ] {
+            let request = Request::get("/ws")
+                .header(UPGRADE, "websocket")
+                .header(SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+                .body(Body::empty())
+                .unwrap();
+            let response = upgrade(request, rate, addr, link.clone(), telemetry.clone());
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{:?}", rate);
+        }
+        let request = Request::get("/ws").body(Body::empty()).unwrap();
+        let response = upgrade(request, None, addr, link, telemetry);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn pilot_json_leaves_missing_servos_alone() {
+        let (link, state, mut commands) = link();
+        let mut client = link.connect("test".to_string());
+        let frame = text(r#"{"pilot": {"wrench": {"surge": 0.5}, "servos": [null, 1600]}}"#);
+        assert!(replies(&mut client, frame).is_empty());
+        let (_, input) = state.borrow().unwrap();
+        assert_eq!(input.wrench.surge, 0.5);
+        assert_eq!(input.wrench.yaw, 0.0);
+        assert_eq!(
+            commands.try_recv(),
+            Ok(Command::SetServoPulse {
+                index: 1,
+                pulse: 1600.0
+            })
+        );
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[test]
+    fn sessions_are_answered_with_the_control_status() {
+        let (link, _state, mut commands) = link();
+        let mut client = link.connect("test".to_string());
+        assert_eq!(
+            replies(&mut client, text(r#"{"hello": "co_pilot"}"#)),
+            vec![serde_json::json!({"control": {"role": "co_pilot", "groups": []}})]
+        );
+        assert_eq!(
+            replies(&mut client, text(r#"{"take": ["lights"]}"#)),
+            vec![serde_json::json!({"control": {"role": "co_pilot", "groups": ["lights"]}})]
+        );
+        // Arming needs flight, which a co-pilot cannot hold.
+        let replies = replies(&mut client, text(r#"{"command": {"set_armed": true}}"#));
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0]["error"].is_string());
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[test]
+    fn binary_frames_carry_protocol_messages() {
+        let (link, _state, mut commands) = link();
+        let mut client = link.connect("test".to_string());
+        let mut data = Message::Stamp {
+            seq: 3,
+            timestamp_ms: 40,
+        }
+        .encode();
+        data.extend(Message::Command(Command::SetArmed(true)).encode());
+        assert!(replies(&mut client, WsMessage::Binary(data)).is_empty());
+        assert_eq!(commands.try_recv(), Ok(Command::SetArmed(true)));
+        assert_eq!(client.ack().map(|ack| ack.echo_seq), Some(3));
+    }
+
+    #[test]
+    fn malformed_frames_are_errors() {
+        let (link, _state, _commands) = link();
+        let mut client = link.connect("test".to_string());
+        for frame in [
+            text("{\"fly\": true}"),
+            text("not json"),
+            WsMessage::Binary(vec![0xFF; 12]),
+        ] {
+            let replies = replies(&mut client, frame);
+            assert_eq!(replies.len(), 1);
+            assert!(replies[0]["error"].is_string());
+        }
+        assert!(replies(&mut client, WsMessage::Ping(Vec::new())).is_empty());
+    }
+}